@@ -0,0 +1,262 @@
+// [[file:../gosh.note::8c5f1ee3][8c5f1ee3]]
+use super::*;
+use crate::geometry::{atom_position, mic_vector};
+
+use gchemol::Molecule;
+use vecfx::nalgebra::Rotation3;
+use vecfx::*;
+// 8c5f1ee3 ends here
+
+// [[file:../gosh.note::5467cc8d][5467cc8d]]
+/// An adsorption site on surface.
+#[derive(Debug, Clone)]
+pub(crate) struct Site {
+    /// The site type: top, bridge or hollow.
+    pub kind: &'static str,
+    /// The surface atoms forming the site.
+    pub atoms: Vec<usize>,
+    /// The cartesian position of the site at the height of the surface.
+    pub position: Vector3f,
+}
+
+fn site_kind(n: usize) -> Result<&'static str> {
+    let kind = match n {
+        1 => "top",
+        2 => "bridge",
+        3 => "hollow",
+        _ => bail!("a site should be formed by 1, 2 or 3 atoms, but found {}", n),
+    };
+    Ok(kind)
+}
+
+/// Create a site formed by surface `atoms`.
+pub(crate) fn site_from_atoms(slab: &Molecule, atoms: &[usize]) -> Result<Site> {
+    let kind = site_kind(atoms.len())?;
+    let p0 = atom_position(slab, atoms[0])?;
+    // apply the minimum image convention with respect to the first atom
+    let mut c = Vector3f::zeros();
+    let mut zmax = f64::MIN;
+    for &n in atoms {
+        let p = p0 + mic_vector(slab, p0, atom_position(slab, n)?);
+        c += p;
+        zmax = zmax.max(p.z);
+    }
+    let mut position = c / atoms.len() as f64;
+    position.z = zmax;
+
+    Ok(Site {
+        kind,
+        atoms: atoms.to_vec(),
+        position,
+    })
+}
+
+/// Return atoms in the topmost layer of `slab`.
+fn top_layer_atoms(slab: &Molecule) -> Vec<usize> {
+    let numbers = slab.numbers().collect_vec();
+    let zs = slab.positions().map(|p| p[2]).collect_vec();
+    let layers = crate::slab::cluster_layers(&zs, 0.5);
+    let top = layers.iter().max().copied().unwrap_or(0);
+    numbers.into_iter().zip(layers).filter_map(|(n, l)| if l == top { Some(n) } else { None }).collect()
+}
+
+/// Find all top, bridge and hollow sites formed by atoms in the topmost
+/// layer of `slab`.
+pub(crate) fn find_sites(slab: &Molecule) -> Result<Vec<Site>> {
+    let top = top_layer_atoms(slab);
+    let dist = |i: usize, j: usize| crate::geometry::get_distance(slab, i, j);
+
+    // neighbors are defined by the shortest distance in the surface layer
+    let mut dmin = f64::MAX;
+    for (&i, &j) in top.iter().tuple_combinations() {
+        dmin = dmin.min(dist(i, j)?);
+    }
+    let cutoff = dmin * 1.2;
+    let is_neighbor = |i: usize, j: usize| -> Result<bool> { Ok(dist(i, j)? < cutoff) };
+
+    let mut sites = vec![];
+    for &i in &top {
+        sites.push(site_from_atoms(slab, &[i])?);
+    }
+    for (&i, &j) in top.iter().tuple_combinations() {
+        if is_neighbor(i, j)? {
+            sites.push(site_from_atoms(slab, &[i, j])?);
+        }
+    }
+    for (&i, &j, &k) in top.iter().tuple_combinations() {
+        if is_neighbor(i, j)? && is_neighbor(i, k)? {
+            // the images of j and k nearest to i should also be neighbors,
+            // or three atoms in a row could be taken as a hollow site in
+            // small cells
+            let pi = atom_position(slab, i)?;
+            let vj = mic_vector(slab, pi, atom_position(slab, j)?);
+            let vk = mic_vector(slab, pi, atom_position(slab, k)?);
+            if (vk - vj).norm() < cutoff {
+                sites.push(site_from_atoms(slab, &[i, j, k])?);
+            }
+        }
+    }
+
+    Ok(sites)
+}
+
+/// Return a fingerprint of local environment around `site`: sorted element
+/// symbols and distances of atoms within `cutoff`.
+fn site_fingerprint(slab: &Molecule, site: &Site, cutoff: f64) -> Vec<(String, i64)> {
+    slab.atoms()
+        .filter_map(|(_, a)| {
+            let d = mic_vector(slab, site.position, a.position().into()).norm();
+            if d < cutoff {
+                // distances are compared with a resolution of 0.05 Å
+                Some((a.symbol().to_string(), (d / 0.05).round() as i64))
+            } else {
+                None
+            }
+        })
+        .sorted()
+        .collect()
+}
+
+/// Return symmetry-distinct sites of `slab` by comparing their local
+/// environments.
+pub(crate) fn find_distinct_sites(slab: &Molecule) -> Result<Vec<Site>> {
+    let sites = find_sites(slab)?;
+    let mut fingerprints = vec![];
+    let mut distinct = vec![];
+    for site in sites {
+        let fp = (site.kind, site_fingerprint(slab, &site, 6.0));
+        if !fingerprints.contains(&fp) {
+            fingerprints.push(fp);
+            distinct.push(site);
+        }
+    }
+
+    Ok(distinct)
+}
+
+/// Return the first site of `kind` in `slab`.
+pub(crate) fn find_site_by_kind(slab: &Molecule, kind: &str) -> Result<Site> {
+    find_sites(slab)?
+        .into_iter()
+        .find(|s| s.kind == kind)
+        .ok_or_else(|| format_err!("no {} site found", kind))
+}
+// 5467cc8d ends here
+
+// [[file:../gosh.note::4888c153][4888c153]]
+/// Place `adsorbate` on `site` of `slab` with its `binding` atom at `height`
+/// above the site. The adsorbate is oriented with the binding atom pointing
+/// downward. Bonds of the adsorbate are carried over.
+pub(crate) fn place_adsorbate(slab: &Molecule, adsorbate: &Molecule, binding: usize, site: &Site, height: f64) -> Result<Molecule> {
+    let pb = atom_position(adsorbate, binding)?;
+    let others = adsorbate.numbers().filter(|&n| n != binding).collect_vec();
+
+    // rotate the adsorbate so that its center lies above the binding atom
+    let rot = if others.is_empty() {
+        Rotation3::identity()
+    } else {
+        let center = crate::transform::center_of(adsorbate, &others)?;
+        let v = center - pb;
+        if v.norm() < 1e-6 {
+            Rotation3::identity()
+        } else {
+            Rotation3::rotation_between(&v, &Vector3f::z()).unwrap_or_else(|| Rotation3::from_axis_angle(&Vector3f::x_axis(), std::f64::consts::PI))
+        }
+    };
+
+    let target = site.position + Vector3f::new(0.0, 0.0, height);
+    let mut mol = slab.clone();
+    let mut serial = mol.numbers().max().unwrap_or(0);
+    let mut mapping = std::collections::HashMap::new();
+    for (n, a) in adsorbate.atoms() {
+        let p = target + rot * (atom_position(adsorbate, n)? - pb);
+        let mut a = a.clone();
+        a.set_position(p.into());
+        serial += 1;
+        mol.add_atom(serial, a);
+        mapping.insert(n, serial);
+    }
+    for (i, j, bond) in adsorbate.bonds() {
+        mol.add_bond(mapping[&i], mapping[&j], bond.clone());
+    }
+    let mut mol = crate::edit::renumbered(&mol);
+    let name = format!("{} on {} {}", adsorbate.formula(), site.kind, site.atoms.iter().join("-"));
+    mol.set_title(&name);
+
+    Ok(mol)
+}
+// 4888c153 ends here
+
+// [[file:../gosh.note::6e1b94d0][6e1b94d0]]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gchemol::{Atom, Bond, Lattice};
+
+    // two layers of fcc(111) surface in a 3x3 cell, with the nearest
+    // neighbor distance of 2.8 Å. Atoms 1-9 are in the bottom layer, and
+    // atoms 10-18 in the top layer.
+    fn fcc111() -> Molecule {
+        let d = 2.8;
+        let a1 = Vector3f::new(d, 0.0, 0.0);
+        let a2 = Vector3f::new(d / 2.0, d * 3f64.sqrt() / 2.0, 0.0);
+        let h = d * (2.0 / 3.0f64).sqrt();
+        let mut atoms = vec![];
+        for (z, offset) in [(0.0, (a1 + a2) / 3.0), (h, Vector3f::zeros())] {
+            for i in 0..3 {
+                for j in 0..3 {
+                    let p = offset + a1 * i as f64 + a2 * j as f64 + Vector3f::new(0.0, 0.0, z);
+                    atoms.push(Atom::new("Pt", [p.x, p.y, p.z]));
+                }
+            }
+        }
+        let mut slab = Molecule::from_atoms(atoms);
+        let (a, b) = (a1 * 3.0, a2 * 3.0);
+        slab.set_lattice(Lattice::new([[a.x, a.y, a.z], [b.x, b.y, b.z], [0.0, 0.0, 20.0]]));
+        slab
+    }
+
+    #[test]
+    fn test_find_distinct_sites() -> Result<()> {
+        let slab = fcc111();
+        let sites = find_sites(&slab)?;
+        assert_eq!(sites.iter().filter(|s| s.kind == "top").count(), 9);
+        assert_eq!(sites.iter().filter(|s| s.kind == "bridge").count(), 27);
+        assert_eq!(sites.iter().filter(|s| s.kind == "hollow").count(), 18);
+
+        // top, bridge, hcp and fcc hollow sites
+        let sites = find_distinct_sites(&slab)?;
+        let kinds = sites.iter().map(|s| s.kind).collect_vec();
+        assert_eq!(kinds, ["top", "bridge", "hollow", "hollow"]);
+        // the hcp site is right above an atom in the bottom layer
+        let below = |s: &Site| {
+            slab.atoms()
+                .filter(|(_, a)| a.position()[2] < 1.0)
+                .any(|(_, a)| mic_vector(&slab, s.position, a.position().into()).xy().norm() < 1e-6)
+        };
+        assert_eq!(sites[2..].iter().filter(|s| below(s)).count(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_place_adsorbate() -> Result<()> {
+        let slab = fcc111();
+        let mut co = Molecule::from_atoms([Atom::new("C", [0.0, 0.0, 0.0]), Atom::new("O", [0.0, 0.0, -1.15])]);
+        co.add_bond(1, 2, Bond::double());
+
+        let site = find_site_by_kind(&slab, "top")?;
+        let mol = place_adsorbate(&slab, &co, 1, &site, 1.9)?;
+        assert_eq!(mol.natoms(), 20);
+        assert_eq!(mol.nbonds(), 1);
+        assert!(mol.get_bond(19, 20).is_some());
+        // O points upward
+        let pc = atom_position(&mol, 19)?;
+        let po = atom_position(&mol, 20)?;
+        assert!((pc - site.position - Vector3f::new(0.0, 0.0, 1.9)).norm() < 1e-6);
+        assert!((po - pc - Vector3f::new(0.0, 0.0, 1.15)).norm() < 1e-6);
+
+        Ok(())
+    }
+}
+// 6e1b94d0 ends here
@@ -61,7 +61,7 @@ struct Cli {
 
 // [[file:../gosh.note::a3e4479e][a3e4479e]]
 /// Extract final molecule from calculated model properties
-fn extract_mol_from(mp: &ModelProperties) -> Option<Molecule> {
+pub(crate) fn extract_mol_from(mp: &ModelProperties) -> Option<Molecule> {
     let mut mol = mp.get_molecule()?.clone();
     if let Some(energy) = mp.get_energy() {
         // save energy as comment (useful for .xyz file)
@@ -0,0 +1,456 @@
+// [[file:../gosh.note::6f1d2a83][6f1d2a83]]
+use super::*;
+use crate::geometry::mic_vector;
+
+use gchemol::{Atom, Lattice, Molecule};
+use vecfx::nalgebra::Matrix3;
+use vecfx::*;
+// 6f1d2a83 ends here
+
+// [[file:../gosh.note::c4a9e7b0][c4a9e7b0]]
+/// Return cell vectors a, b, c of lattice `lat`.
+pub(crate) fn cell_vectors(lat: &Lattice) -> [Vector3f; 3] {
+    [
+        lat.to_cart([1.0, 0.0, 0.0]).into(),
+        lat.to_cart([0.0, 1.0, 0.0]).into(),
+        lat.to_cart([0.0, 0.0, 1.0]).into(),
+    ]
+}
+
+fn cell_volume(cell: &[Vector3f; 3]) -> f64 {
+    cell[0].dot(&cell[1].cross(&cell[2]))
+}
+
+/// Make `cell` right-handed by inverting all vectors if necessary. The
+/// metric is unchanged.
+fn right_handed(cell: [Vector3f; 3]) -> [Vector3f; 3] {
+    if cell_volume(&cell) < 0.0 {
+        cell.map(|v| -v)
+    } else {
+        cell
+    }
+}
+
+/// Return `cell` reduced using the Krivy-Gruber algorithm for Niggli
+/// reduction.
+///
+/// Reference: Grosse-Kunstleve et al., Acta Cryst. A60, 1-6 (2004)
+fn niggli_reduce(cell: [Vector3f; 3]) -> Result<[Vector3f; 3]> {
+    let eps = 1e-5 * cell_volume(&cell).abs().powf(2.0 / 3.0);
+    let lt = |x: f64, y: f64| x < y - eps;
+    let gt = |x: f64, y: f64| lt(y, x);
+    let eq = |x: f64, y: f64| !lt(x, y) && !gt(x, y);
+    let sign = |x: f64| if gt(x, 0.0) { 1 } else if lt(x, 0.0) { -1 } else { 0 };
+
+    let [mut a, mut b, mut c] = cell;
+    for _ in 0..10000 {
+        let (ga, gb, gc) = (a.dot(&a), b.dot(&b), c.dot(&c));
+        let (xi, eta, zeta) = (2.0 * b.dot(&c), 2.0 * a.dot(&c), 2.0 * a.dot(&b));
+
+        // step 1 and 2: sort by length
+        if gt(ga, gb) || (eq(ga, gb) && gt(xi.abs(), eta.abs())) {
+            (a, b, c) = (-b, -a, -c);
+            continue;
+        }
+        if gt(gb, gc) || (eq(gb, gc) && gt(eta.abs(), zeta.abs())) {
+            (a, b, c) = (-a, -c, -b);
+            continue;
+        }
+
+        // step 3 and 4: make the off-diagonal metric elements all positive
+        // or all non-positive by inverting vectors
+        let positive = sign(xi) * sign(eta) * sign(zeta) == 1;
+        let ok = |xi: f64, eta: f64, zeta: f64| {
+            if positive {
+                gt(xi, 0.0) && gt(eta, 0.0) && gt(zeta, 0.0)
+            } else {
+                !gt(xi, 0.0) && !gt(eta, 0.0) && !gt(zeta, 0.0)
+            }
+        };
+        if !ok(xi, eta, zeta) {
+            // always possible, see step 3 and 4 of the reference
+            let signs = (0..8).map(|n| [n & 1, n & 2, n & 4].map(|x| if x == 0 { 1.0 } else { -1.0 }));
+            let [i, j, k] = signs
+                .into_iter()
+                .find(|&[i, j, k]| ok(j * k * xi, i * k * eta, i * j * zeta))
+                .expect("niggli sign normalization");
+            (a, b, c) = (i * a, j * b, k * c);
+        }
+        let (xi, eta, zeta) = (2.0 * b.dot(&c), 2.0 * a.dot(&c), 2.0 * a.dot(&b));
+
+        // step 5 to 8: reduce off-diagonal elements
+        if gt(xi.abs(), gb) || (eq(xi, gb) && lt(2.0 * eta, zeta)) || (eq(xi, -gb) && lt(zeta, 0.0)) {
+            c -= xi.signum() * b;
+            continue;
+        }
+        if gt(eta.abs(), ga) || (eq(eta, ga) && lt(2.0 * xi, zeta)) || (eq(eta, -ga) && lt(zeta, 0.0)) {
+            c -= eta.signum() * a;
+            continue;
+        }
+        if gt(zeta.abs(), ga) || (eq(zeta, ga) && lt(2.0 * xi, eta)) || (eq(zeta, -ga) && lt(eta, 0.0)) {
+            b -= zeta.signum() * a;
+            continue;
+        }
+        let s = xi + eta + zeta + ga + gb;
+        if lt(s, 0.0) || (eq(s, 0.0) && gt(2.0 * (ga + eta) + zeta, 0.0)) {
+            c += a + b;
+            continue;
+        }
+
+        return Ok(right_handed([a, b, c]));
+    }
+
+    bail!("Niggli reduction failed to converge.")
+}
+
+/// Return `cell` reduced using Delaunay (Selling) reduction.
+fn delaunay_reduce(cell: [Vector3f; 3]) -> Result<[Vector3f; 3]> {
+    let eps = 1e-5 * cell_volume(&cell).abs().powf(2.0 / 3.0);
+    let [a, b, c] = cell;
+    let mut vs = [a, b, c, -(a + b + c)];
+    'outer: for _ in 0..10000 {
+        for i in 0..4 {
+            for j in (i + 1)..4 {
+                if vs[i].dot(&vs[j]) > eps {
+                    for k in (0..4).filter(|&k| k != i && k != j) {
+                        vs[k] += vs[i];
+                    }
+                    vs[i] = -vs[i];
+                    continue 'outer;
+                }
+            }
+        }
+
+        // choose the three shortest non-coplanar vectors
+        let mut candidates = vs.to_vec();
+        candidates.extend([vs[0] + vs[1], vs[0] + vs[2], vs[1] + vs[2]]);
+        candidates.sort_by(|x, y| x.norm().partial_cmp(&y.norm()).unwrap());
+        let v0 = cell_volume(&cell).abs();
+        for (x, y, z) in candidates.iter().tuple_combinations() {
+            if (cell_volume(&[*x, *y, *z]).abs() - v0).abs() < 1e-6 * v0 {
+                return Ok(right_handed([*x, *y, *z]));
+            }
+        }
+        bail!("Delaunay reduction failed to find a reduced basis.");
+    }
+
+    bail!("Delaunay reduction failed to converge.")
+}
+// c4a9e7b0 ends here
+
+// [[file:../gosh.note::e2b8f4d5][e2b8f4d5]]
+/// Carry bonds of periodic structure `old` over to `new`, whose k-th atom
+/// is an image of atom `sources[k]` in `old`. Two atoms in `new` are
+/// bonded if their source atoms are bonded and their distance matches the
+/// bond length in `old` within `tol`.
+pub(crate) fn carry_bonds(old: &Molecule, new: &mut Molecule, sources: &[usize], tol: f64) {
+    if old.nbonds() == 0 {
+        return;
+    }
+    let bond_length = |m: &Molecule, i: usize, j: usize| {
+        let pi: Vector3f = m.get_atom(i).unwrap().position().into();
+        let pj: Vector3f = m.get_atom(j).unwrap().position().into();
+        mic_vector(m, pi, pj).norm()
+    };
+    let numbers = new.numbers().collect_vec();
+    let mut bonds = vec![];
+    for (p, q) in (0..numbers.len()).tuple_combinations() {
+        let (i, j) = (sources[p], sources[q]);
+        if let Some(bond) = old.get_bond(i, j) {
+            let (np, nq) = (numbers[p], numbers[q]);
+            if (bond_length(new, np, nq) - bond_length(old, i, j)).abs() < tol {
+                bonds.push((np, nq, bond.clone()));
+            }
+        }
+    }
+    for (i, j, bond) in bonds {
+        new.add_bond(i, j, bond);
+    }
+}
+
+/// Return a new structure of `mol` in the cell formed by `cell` vectors,
+/// which should be a sublattice or superlattice of the original lattice.
+/// Atoms are generated from periodic images and wrapped into the new cell,
+/// and duplicates within `tol` (in Angstrom) are removed.
+fn remap_atoms(mol: &Molecule, cell: [Vector3f; 3], tol: f64) -> Result<Molecule> {
+    let lat = mol.lattice.as_ref().ok_or_else(|| format_err!("not a periodic system!"))?;
+    let old = cell_vectors(lat);
+
+    // the range of periodic images covering the new cell
+    let mut lo = [i64::MAX; 3];
+    let mut hi = [i64::MIN; 3];
+    for n in 0..8 {
+        let corner = [n & 1, n & 2, n & 4].iter().zip(cell.iter()).fold(Vector3f::zeros(), |acc, (&x, v)| {
+            if x == 0 {
+                acc
+            } else {
+                acc + v
+            }
+        });
+        let f = lat.to_frac(corner.into());
+        for x in 0..3 {
+            lo[x] = lo[x].min(f[x].floor() as i64 - 1);
+            hi[x] = hi[x].max(f[x].ceil() as i64 + 1);
+        }
+    }
+
+    let inv = Matrix3::from_columns(&cell)
+        .try_inverse()
+        .ok_or_else(|| format_err!("invalid cell: {:?}", cell))?;
+    let m = Matrix3::from_columns(&cell);
+    let is_duplicate = |f1: &Vector3f, f2: &Vector3f| {
+        let d = (f1 - f2).map(|x| x - x.round());
+        (m * d).norm() < tol
+    };
+
+    let mut found: Vec<(Atom, Vector3f)> = vec![];
+    let mut sources = vec![];
+    for (n, a) in mol.atoms() {
+        let p: Vector3f = a.position().into();
+        let images = (lo[0]..=hi[0]).flat_map(|i| (lo[1]..=hi[1]).flat_map(move |j| (lo[2]..=hi[2]).map(move |k| (i, j, k))));
+        for (i, j, k) in images {
+            let q = p + i as f64 * old[0] + j as f64 * old[1] + k as f64 * old[2];
+            let f = inv * q;
+            if f.iter().any(|&x| x < -1e-6 || x >= 1.0 - 1e-6) {
+                continue;
+            }
+            let f = f.map(|x| x.max(0.0));
+            if found.iter().any(|(b, g)| b.symbol() == a.symbol() && is_duplicate(&f, g)) {
+                continue;
+            }
+            found.push((a.clone(), f));
+            sources.push(n);
+        }
+    }
+
+    let atoms = found.into_iter().map(|(mut a, f)| {
+        let p: [f64; 3] = (m * f).into();
+        a.set_position(p);
+        a
+    });
+    let mut new = Molecule::from_atoms(atoms);
+    new.set_title(&mol.title());
+    new.properties = mol.properties.clone();
+    let tvs: [[f64; 3]; 3] = cell.map(|v| v.into());
+    new.set_lattice(Lattice::new(tvs));
+    carry_bonds(mol, &mut new, &sources, tol.max(1e-2));
+
+    let expected = mol.natoms() as f64 * (cell_volume(&cell) / cell_volume(&old)).abs();
+    if (new.natoms() as f64 - expected).abs() > 1e-3 {
+        eprintln!("Expected {:.1} atoms in new cell, but found {}. Please check the tolerance.", expected, new.natoms());
+    }
+
+    Ok(new)
+}
+
+/// Reduce the cell of `mol` using `method`: niggli or delaunay. Atoms are
+/// wrapped into the reduced cell.
+pub(crate) fn reduce_cell(mol: &Molecule, method: &str) -> Result<Molecule> {
+    let lat = mol.lattice.as_ref().ok_or_else(|| format_err!("not a periodic system!"))?;
+    let cell = cell_vectors(lat);
+    let reduced = match method {
+        "niggli" => niggli_reduce(cell)?,
+        "delaunay" => delaunay_reduce(cell)?,
+        _ => bail!("invalid reduction method: {:?}. Possible values: niggli, delaunay", method),
+    };
+    remap_atoms(mol, reduced, 1e-3)
+}
+// e2b8f4d5 ends here
+
+// [[file:../gosh.note::3a7d90ce][3a7d90ce]]
+/// Find pure translations (in fractional coordinates) mapping the crystal
+/// `mol` onto itself within `tol`, excluding the lattice translations.
+pub(crate) fn find_translations(mol: &Molecule, tol: f64) -> Result<Vec<Vector3f>> {
+    let lat = mol.lattice.as_ref().ok_or_else(|| format_err!("not a periodic system!"))?;
+    let atoms: Vec<(&str, Vector3f)> = mol.atoms().map(|(_, a)| (a.symbol(), lat.to_frac(a.position()).into())).collect();
+
+    // try translations between atoms of the least frequent element
+    let mut counts = std::collections::HashMap::new();
+    for (s, _) in atoms.iter() {
+        *counts.entry(*s).or_insert(0) += 1;
+    }
+    let rare = counts
+        .into_iter()
+        .min_by_key(|&(s, n)| (n, s))
+        .map(|(s, _)| s)
+        .ok_or_else(|| format_err!("no atoms"))?;
+    let origin = atoms.iter().find(|(s, _)| *s == rare).unwrap().1;
+
+    let mut translations: Vec<Vector3f> = vec![];
+    for (s, f) in atoms.iter() {
+        if *s != rare {
+            continue;
+        }
+        let t = (f - origin).map(|x| x - x.floor());
+        let is_zero = |t: &Vector3f| mic_vector(mol, Vector3f::zeros(), lat.to_cart((*t).into()).into()).norm() < tol;
+        if is_zero(&t) || translations.iter().any(|u| is_zero(&(t - u))) {
+            continue;
+        }
+        let ok = atoms.iter().all(|(si, fi)| {
+            let g = fi + t;
+            atoms
+                .iter()
+                .any(|(sj, fj)| si == sj && is_zero(&(g - fj)))
+        });
+        if ok {
+            translations.push(t);
+        }
+    }
+
+    Ok(translations)
+}
+
+/// Find the primitive cell of crystal `mol` using symmetry tolerance `tol`
+/// in Angstrom. The returned cell is Niggli reduced.
+pub(crate) fn primitive_cell(mol: &Molecule, tol: f64) -> Result<Molecule> {
+    let lat = mol.lattice.as_ref().ok_or_else(|| format_err!("not a periodic system!"))?;
+    let cell = cell_vectors(lat);
+    let translations = find_translations(mol, tol)?;
+    if translations.is_empty() {
+        info!("The cell is already primitive.");
+        return remap_atoms(mol, niggli_reduce(cell)?, tol);
+    }
+
+    // search for the shortest basis with the primitive volume
+    let n = translations.len() + 1;
+    let volume = cell_volume(&cell).abs() / n as f64;
+    let mut candidates: Vec<Vector3f> = translations
+        .iter()
+        .map(|t| mic_vector(mol, Vector3f::zeros(), lat.to_cart((*t).into()).into()))
+        .collect();
+    candidates.extend(cell);
+    let nc = candidates.len();
+    for i in 0..nc {
+        for j in 0..i {
+            candidates.push(candidates[i] + candidates[j]);
+            candidates.push(candidates[i] - candidates[j]);
+        }
+    }
+    candidates.sort_by(|x, y| x.norm().partial_cmp(&y.norm()).unwrap());
+    candidates.dedup_by(|x, y| (*x - *y).norm() < tol);
+    let basis = candidates
+        .iter()
+        .filter(|v| v.norm() > tol)
+        .tuple_combinations()
+        .find(|(x, y, z)| (cell_volume(&[**x, **y, **z]).abs() - volume).abs() < 1e-3 * volume)
+        .ok_or_else(|| format_err!("failed to find primitive cell basis."))?;
+    let basis = niggli_reduce([*basis.0, *basis.1, *basis.2])?;
+
+    remap_atoms(mol, basis, tol)
+}
+// 3a7d90ce ends here
+
+// [[file:../gosh.note::5b0e3f17][5b0e3f17]]
+/// Find the conventional cell of crystal `mol` from the standardized
+/// lattice of spglib with tolerance `tol` in Angstrom. The orientation of
+/// the original cell is kept. Return the new structure and the name of the
+/// crystal system.
+pub(crate) fn conventional_cell(mol: &Molecule, tol: f64) -> Result<(Molecule, &'static str)> {
+    let lat = mol.lattice.as_ref().ok_or_else(|| format_err!("not a periodic system!"))?;
+    let dataset = crate::symmetry::spglib_dataset(mol, tol)?;
+
+    // (a_s, b_s, c_s) = (a, b, c) P^-1, with P the transformation matrix
+    let p = Matrix3::from_fn(|i, j| dataset.transformation_matrix[i][j]);
+    let p_inv = p.try_inverse().ok_or_else(|| format_err!("invalid transformation matrix: {:?}", p))?;
+    let m = Matrix3::from_columns(&cell_vectors(lat)) * p_inv;
+    let cell = [0, 1, 2].map(|i| m.column(i).into_owned());
+
+    let conv = remap_atoms(mol, cell, tol)?;
+    Ok((conv, crate::symmetry::crystal_system(dataset.spacegroup_number)))
+}
+// 5b0e3f17 ends here
+
+// [[file:../gosh.note::c7b3e5a9][c7b3e5a9]]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Return lengths and angles (alpha, beta, gamma) of `cell`.
+    fn cell_params(cell: &[Vector3f; 3]) -> [f64; 6] {
+        let angle = |u: &Vector3f, v: &Vector3f| u.angle(v).to_degrees();
+        let [a, b, c] = cell.map(|v| v.norm());
+        [a, b, c, angle(&cell[1], &cell[2]), angle(&cell[0], &cell[2]), angle(&cell[0], &cell[1])]
+    }
+
+    fn assert_cell(cell: &[Vector3f; 3], expected: [f64; 6], volume: f64) {
+        for (x, y) in cell_params(cell).iter().zip(expected) {
+            assert_relative_eq!(*x, y, epsilon = 1e-6);
+        }
+        assert_relative_eq!(cell_volume(cell), volume, epsilon = 1e-6);
+    }
+
+    // skewed cells sharing the same lattice with a cubic, fcc and hexagonal
+    // cell
+    fn skewed_cells() -> [[Vector3f; 3]; 3] {
+        let a = 3.6;
+        let [x, y, z] = [Vector3f::new(a, 0.0, 0.0), Vector3f::new(0.0, a, 0.0), Vector3f::new(0.0, 0.0, a)];
+        let cubic = [x, y + 2.0 * x, z + x - y];
+        let h = a / 2.0;
+        let [u, v, w] = [Vector3f::new(0.0, h, h), Vector3f::new(h, 0.0, h), Vector3f::new(h, h, 0.0)];
+        let fcc = [u, v + u, w + 2.0 * u - v];
+        let [p, q, r] = [Vector3f::new(3.0, 0.0, 0.0), Vector3f::new(-1.5, 1.5 * 3f64.sqrt(), 0.0), Vector3f::new(0.0, 0.0, 5.0)];
+        let hexagonal = [p, q + p, r + q];
+        [cubic, fcc, hexagonal]
+    }
+
+    #[test]
+    fn test_niggli_reduce() -> Result<()> {
+        let [cubic, fcc, hexagonal] = skewed_cells();
+        let vh = 3.0 * 1.5 * 3f64.sqrt() * 5.0;
+        assert_cell(&niggli_reduce(cubic)?, [3.6, 3.6, 3.6, 90.0, 90.0, 90.0], 3.6f64.powi(3));
+        let d = 3.6 / 2f64.sqrt();
+        assert_cell(&niggli_reduce(fcc)?, [d, d, d, 60.0, 60.0, 60.0], 3.6f64.powi(3) / 4.0);
+        assert_cell(&niggli_reduce(hexagonal)?, [3.0, 3.0, 5.0, 90.0, 90.0, 120.0], vh);
+        Ok(())
+    }
+
+    #[test]
+    fn test_delaunay_reduce() -> Result<()> {
+        let [cubic, fcc, hexagonal] = skewed_cells();
+        let vh = 3.0 * 1.5 * 3f64.sqrt() * 5.0;
+        assert_cell(&delaunay_reduce(cubic)?, [3.6, 3.6, 3.6, 90.0, 90.0, 90.0], 3.6f64.powi(3));
+        let d = 3.6 / 2f64.sqrt();
+        assert_cell(&delaunay_reduce(fcc)?, [d, d, d, 60.0, 120.0, 120.0], 3.6f64.powi(3) / 4.0);
+        assert_cell(&delaunay_reduce(hexagonal)?, [3.0, 3.0, 5.0, 90.0, 90.0, 120.0], vh);
+        Ok(())
+    }
+
+    #[test]
+    fn test_reduce_cell() -> Result<()> {
+        let [_, fcc, _] = skewed_cells();
+        let mut mol = Molecule::from_atoms([Atom::new("Cu", [0.1, 0.2, 0.3])]);
+        mol.set_lattice(Lattice::new(fcc.map(|v| v.into())));
+        for method in ["niggli", "delaunay"] {
+            let reduced = reduce_cell(&mol, method)?;
+            assert_eq!(reduced.natoms(), 1);
+            let cell = cell_vectors(reduced.lattice.as_ref().unwrap());
+            assert_relative_eq!(cell_volume(&cell), 3.6f64.powi(3) / 4.0, epsilon = 1e-6);
+        }
+        assert!(reduce_cell(&mol, "minkowski").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_conventional_cell() -> Result<()> {
+        let [_, fcc, hexagonal] = skewed_cells();
+        let mut mol = Molecule::from_atoms([Atom::new("Cu", [0.0; 3])]);
+        mol.set_lattice(Lattice::new(fcc.map(|v| v.into())));
+        let (conv, system) = conventional_cell(&mol, 0.01)?;
+        assert_eq!(system, "cubic");
+        assert_eq!(conv.natoms(), 4);
+        let cell = cell_vectors(conv.lattice.as_ref().unwrap());
+        assert_cell(&cell, [3.6, 3.6, 3.6, 90.0, 90.0, 90.0], 3.6f64.powi(3));
+
+        let mut mol = Molecule::from_atoms([Atom::new("Mg", [0.0; 3])]);
+        mol.set_lattice(Lattice::new(hexagonal.map(|v| v.into())));
+        let (conv, system) = conventional_cell(&mol, 0.01)?;
+        assert_eq!(system, "hexagonal");
+        assert_eq!(conv.natoms(), 1);
+        let vh = 3.0 * 1.5 * 3f64.sqrt() * 5.0;
+        assert_relative_eq!(cell_volume(&cell_vectors(conv.lattice.as_ref().unwrap())), vh, epsilon = 1e-6);
+
+        assert!(conventional_cell(&Molecule::from_atoms([Atom::new("Cu", [0.0; 3])]), 0.01).is_err());
+        Ok(())
+    }
+}
+// c7b3e5a9 ends here
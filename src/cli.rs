@@ -3,6 +3,8 @@ use super::*;
 
 use gchemol::prelude::*;
 use gchemol::{io, Molecule};
+use gosh_model::{BlackBoxModel, ChemicalModel, ModelProperties};
+use clap::ValueHint;
 use gut::cli::*;
 use gut::utils::parse_numbers_human_readable;
 
@@ -21,6 +23,12 @@ pub struct Commander {
 
     /// Selected atoms in serial atoms
     selection: Option<Vec<usize>>,
+
+    /// Named slots for keeping molecules in workspace
+    workspace: std::collections::HashMap<String, Vec<Molecule>>,
+
+    /// Model properties from the last `compute` or `optimize` command
+    pub computed: Vec<ModelProperties>,
 }
 
 #[derive(Parser, Debug)]
@@ -34,37 +42,68 @@ pub enum GoshCmd {
     #[clap(name = "help", alias = "h", alias = "?")]
     Help {},
 
-    /// Write molecule(s) into file.
+    /// Write molecule(s) into file, or into stdout if the filename is `-`.
     #[clap(name = "write", alias = "save")]
     Write {
         /// The filename to write.
-        #[clap(name = "FILE-NAME")]
+        #[clap(name = "FILE-NAME", value_hint = ValueHint::FilePath)]
         filename: Option<PathBuf>,
 
         /// Write in raw JSON format
         #[arg(long)]
         json: bool,
+
+        /// Write in explicit format instead of the one deduced from file
+        /// extension: a file extension (e.g. xyz) or a format name (e.g.
+        /// vasp/poscar). Required when writing into stdout.
+        #[arg(long, value_name = "FORMAT")]
+        format: Option<String>,
     },
 
-    /// Load molecule(s) from file.
+    /// Load molecule(s) from file, or from stdin if the filename is `-`.
     #[clap(name = "load")]
     Load {
         /// The filename containing one or more molecules.
-        #[clap(name = "MOLECULE-NAME")]
+        #[clap(name = "MOLECULE-NAME", value_hint = ValueHint::FilePath)]
         filename: PathBuf,
+
+        /// Read in explicit format instead of the one deduced from file
+        /// extension: a file extension (e.g. xyz) or a format name (e.g.
+        /// vasp/poscar). Required when reading from stdin.
+        #[arg(long, value_name = "FORMAT")]
+        format: Option<String>,
     },
 
     /// Load molecule from checkpoint file.
     #[clap(name = "load-chk")]
     LoadChk {
         /// The filename containing one or more molecules.
-        #[clap(name = "MOLECULE-NAME")]
+        #[clap(name = "MOLECULE-NAME", value_hint = ValueHint::FilePath)]
         filename: PathBuf,
 
         #[clap(long, default_value = "-1")]
         chk_slot: i32,
     },
 
+    /// Print a summary of active molecules.
+    #[clap(name = "info")]
+    Info {},
+
+    /// Show details of active molecules.
+    ///
+    /// Usage: show atoms; show atoms --selected
+    #[clap(name = "show")]
+    Show {
+        /// What to show: info, atoms or properties (computed by the last
+        /// `compute` or `optimize` command).
+        #[clap(default_value = "info", value_parser = ["info", "atoms", "properties"])]
+        what: String,
+
+        /// Only show selected atoms.
+        #[clap(long)]
+        selected: bool,
+    },
+
     /// Rebuild bonds based on atom distances.
     #[clap(name = "rebond")]
     Rebond {
@@ -73,21 +112,52 @@ pub enum GoshCmd {
         /// value leading to more bonds. The default value is 0.45
         /// (which is also the default for JMol)
         bond_tolerance: Option<f64>,
+
+        /// Also perceive bond orders (aromatic, double and triple bonds)
+        /// from bond lengths and valences.
+        #[clap(long)]
+        bond_orders: bool,
+    },
+
+    /// Print SMILES of molecules, using bonds created by `rebond`
+    /// and bond orders perceived from geometry. The result is kept in
+    /// molecule properties, and is shown by `info` and available in
+    /// templates of `format` as `molecule.smiles`.
+    #[clap(name = "smiles")]
+    Smiles {
+        /// Print SMILES for each fragment in separate lines.
+        #[clap(long)]
+        fragments: bool,
     },
 
     /// Update current molecule from somewhere with something
+    ///
+    /// Usage: update coords -f CONTCAR --auto-map
     #[clap(name = "update")]
     Update {
-        /// The target properties to be updated: coords, freezing, ...
+        /// The target properties to be updated: coords, freezing, symbols,
+        /// velocities, lattice, title or all. Multiple targets can be
+        /// separated by comma, e.g.: coords,freezing
         target: String,
 
         #[clap(short = 's')]
         /// Select the atoms to be updated: "2,3,8" or "2-9"
         select: Option<String>,
 
-        #[clap(short = 'f')]
-        /// The path to source molecular file
-        source: PathBuf,
+        #[clap(short = 'f', value_hint = ValueHint::FilePath)]
+        /// The path to source molecular file, or a workspace slot as @name
+        source: String,
+
+        /// Explicit atom mapping from current molecule to source molecule,
+        /// e.g.: "1:5,2:6" maps atom 1 to source atom 5, and atom 2 to source
+        /// atom 6.
+        #[clap(long, conflicts_with = "auto_map")]
+        map: Option<String>,
+
+        /// Map atoms automatically by element and nearest position, which is
+        /// useful when atoms were reordered in the source.
+        #[clap(long)]
+        auto_map: bool,
     },
 
     /// Select atoms
@@ -100,6 +170,10 @@ pub enum GoshCmd {
         ///
         /// select 2,3,6-8
         ///
+        /// Select atoms by element symbols:
+        ///
+        /// select Pt,Au
+        ///
         selection: String,
 
         /// Select atoms by z fractional coords. Only work for periodic system.
@@ -111,11 +185,33 @@ pub enum GoshCmd {
     },
 
     /// Freeze select atoms
+    ///
+    /// Usage: freeze; freeze --axes z; freeze --below-fz 0.3; freeze --layers 2
     #[clap(name = "freeze")]
     Freeze {
         #[clap(short = 'u')]
         /// inverse the operation, that is, unfreeze selected atoms.
         inverse: bool,
+
+        /// Only freeze (or unfreeze) the given axes, e.g.: z or xy. Other
+        /// axes are left unchanged.
+        #[clap(long, default_value = "xyz")]
+        axes: String,
+
+        /// Freeze atoms with z fractional coords below the value instead of
+        /// selected atoms. Only work for periodic system.
+        #[clap(long, conflicts_with = "layers")]
+        below_fz: Option<f64>,
+
+        /// Freeze atoms in the bottom N layers along z instead of selected
+        /// atoms.
+        #[clap(long)]
+        layers: Option<usize>,
+
+        /// Atoms within this z distance (in Å) are grouped into the same
+        /// layer. Used with `--layers`.
+        #[clap(long, default_value = "0.3", requires = "layers")]
+        layer_tol: f64,
     },
 
     /// Clean up bad molecular geometry.
@@ -126,6 +222,23 @@ pub enum GoshCmd {
     #[clap(name = "unbuild-crystal")]
     UnbuildCrystal {},
 
+    /// Wrap atoms into the unit cell with fractional coordinates in [0, 1).
+    #[clap(name = "wrap")]
+    Wrap {
+        /// Wrap selected atoms only.
+        #[clap(long)]
+        selected: bool,
+    },
+
+    /// Make molecules whole across periodic boundaries by following bonds.
+    /// Bonds should be created using `rebond` first.
+    #[clap(name = "unwrap", alias = "make-whole")]
+    Unwrap {
+        /// Only move selected atoms, following bonds among them.
+        #[clap(long)]
+        selected: bool,
+    },
+
     /// Create periodic lattice from minimal bounding box extended by a padding
     /// width for molecule.
     #[clap(name = "create-bounding-box")]
@@ -138,13 +251,30 @@ pub enum GoshCmd {
     /// Convert molecule formats in batch.
     ///
     /// Usage: convert 1.xyz 2.xyz -e .mol2
+    ///        convert *.cif --format vasp/poscar --out-dir poscars --split
     #[clap(name = "convert")]
     Convert {
-        /// input files: e.g.: 1.cif 2.cif 3.cif
-        files: Vec<PathBuf>,
+        /// input files or glob patterns: e.g.: 1.cif 2.cif 3.cif or "*.cif"
+        #[clap(value_hint = ValueHint::FilePath, required = true)]
+        files: Vec<String>,
         /// target format (file extension): e.g.: .mol2 or .poscar
-        #[clap(short = 'e')]
-        format_to: String,
+        #[clap(short = 'e', value_name = "EXT")]
+        format_to: Option<String>,
+        /// explicit target format independent of file extension, e.g.:
+        /// vasp/poscar. The file extension defaults to the last part of
+        /// the format name if `-e` is not specified.
+        #[clap(long, value_name = "FORMAT")]
+        format: Option<String>,
+        /// write output files into this directory instead of the directory
+        /// of input file
+        #[clap(long, value_hint = ValueHint::DirPath)]
+        out_dir: Option<PathBuf>,
+        /// write one file per frame, with frame index appended to file name
+        #[clap(long)]
+        split: bool,
+        /// do not overwrite existing files
+        #[clap(long)]
+        no_clobber: bool,
     },
 
     /// Format molecule using template file.
@@ -155,7 +285,7 @@ pub enum GoshCmd {
         filename: PathBuf,
 
         /// Path to output file.
-        #[clap(name = "OUTPUT_FILE_NAME", short = 'o')]
+        #[clap(name = "OUTPUT_FILE_NAME", short = 'o', value_hint = ValueHint::FilePath)]
         output: Option<PathBuf>,
     },
 
@@ -173,15 +303,590 @@ pub enum GoshCmd {
         range_c: usize,
     },
 
+    /// Displace atoms randomly, e.g. for breaking symmetry before
+    /// optimization. Only the selected atoms (or all atoms when nothing is
+    /// selected) are displaced, and frozen coordinates are not changed.
+    ///
+    /// Usage: rattle --stdev 0.05 --seed 42
+    #[clap(name = "rattle")]
+    Rattle {
+        /// The standard deviation of displacements in Angstrom.
+        #[clap(long, default_value = "0.05")]
+        stdev: f64,
+
+        /// The seed for random displacements.
+        #[clap(long)]
+        seed: Option<u64>,
+    },
+
+    /// Deform the lattice by a strain tensor. Atoms follow the lattice in
+    /// fractional coordinates.
+    ///
+    /// Usage: strain --xx 0.02 --yz 0.01; strain --scale 1.02
+    #[clap(name = "strain")]
+    Strain {
+        #[clap(long, default_value = "0", allow_hyphen_values = true)]
+        xx: f64,
+        #[clap(long, default_value = "0", allow_hyphen_values = true)]
+        yy: f64,
+        #[clap(long, default_value = "0", allow_hyphen_values = true)]
+        zz: f64,
+        #[clap(long, default_value = "0", allow_hyphen_values = true)]
+        xy: f64,
+        #[clap(long, default_value = "0", allow_hyphen_values = true)]
+        xz: f64,
+        #[clap(long, default_value = "0", allow_hyphen_values = true)]
+        yz: f64,
+
+        /// Scale cell lengths isotropically by this factor, e.g. 1.02.
+        #[clap(long)]
+        scale: Option<f64>,
+    },
+
+    /// Reduce the cell of periodic structures. Atoms are wrapped into the
+    /// reduced cell.
+    #[clap(name = "reduce-cell")]
+    ReduceCell {
+        /// The reduction method: niggli or delaunay.
+        #[clap(long, default_value = "niggli")]
+        method: String,
+    },
+
+    /// Find the primitive cell of crystal structures. The cell is Niggli
+    /// reduced.
+    #[clap(name = "primitive")]
+    Primitive {
+        /// The tolerance in Angstrom for matching atoms.
+        #[clap(long, default_value = "0.01")]
+        tol: f64,
+    },
+
+    /// Find the conventional cell of crystal structures, based on the
+    /// standardized lattice from spglib.
+    #[clap(name = "conventional")]
+    Conventional {
+        /// The symmetry tolerance in Angstrom for spglib and matching atoms.
+        #[clap(long, default_value = "0.01")]
+        tol: f64,
+    },
+
+    /// Detect point group of molecules or space group of crystals. The
+    /// results are kept in molecule properties, and are available in
+    /// templates of `format` as `molecule.point_group` and `molecule.space_group`.
+    #[clap(name = "symmetry", alias = "sym")]
+    Symmetry {
+        /// The tolerance in Angstrom for matching atoms.
+        #[clap(long, default_value = "0.1")]
+        tol: f64,
+
+        /// Symmetrize atom positions to the detected symmetry.
+        #[clap(long)]
+        symmetrize: bool,
+    },
+
+    /// Cut a surface slab from loaded bulk crystal.
+    ///
+    /// Usage: slab 1 1 1 --layers 4 --vacuum 15 --freeze 2
+    #[clap(name = "slab")]
+    Slab {
+        /// Miller index h
+        #[clap(allow_hyphen_values = true)]
+        h: i64,
+        /// Miller index k
+        #[clap(allow_hyphen_values = true)]
+        k: i64,
+        /// Miller index l
+        #[clap(allow_hyphen_values = true)]
+        l: i64,
+
+        /// The number of repeated unit layers along surface normal.
+        #[clap(long, default_value = "3")]
+        layers: usize,
+
+        /// The vacuum thickness in Angstrom.
+        #[clap(long, default_value = "15.0")]
+        vacuum: f64,
+
+        /// Choose surface termination by the index of atomic layer in unit
+        /// layer (starting from 0).
+        #[clap(long, default_value = "0")]
+        termination: usize,
+
+        /// Search for an orthogonal in-plane supercell when possible.
+        #[clap(long)]
+        orthogonal: bool,
+
+        /// Freeze atoms in bottom N atomic layers.
+        #[clap(long, default_value = "0")]
+        freeze: usize,
+    },
+
+    /// Place an adsorbate molecule on surface.
+    ///
+    /// Usage: adsorb CO.xyz --site top --height 1.9 --binding 1
+    #[clap(name = "adsorb")]
+    Adsorb {
+        /// The adsorbate molecule from file, or from a workspace slot as @name.
+        #[clap(value_hint = ValueHint::FilePath)]
+        adsorbate: String,
+
+        /// The adsorption site: top, bridge, hollow, or surface atoms forming
+        /// the site such as "12,13".
+        #[clap(long, default_value = "top")]
+        site: String,
+
+        /// The height of the binding atom above the site in Angstrom.
+        #[clap(long, default_value = "2.0")]
+        height: f64,
+
+        /// The serial number of the binding atom in adsorbate.
+        #[clap(long, default_value = "1")]
+        binding: usize,
+
+        /// Enumerate all symmetry-distinct sites, generating one structure
+        /// per site.
+        #[clap(long)]
+        all_sites: bool,
+
+        /// Write one file per structure. For example, "-o site.vasp" writes
+        /// "site-top-1.vasp", "site-bridge-2.vasp", ...
+        #[clap(short = 'o', value_hint = ValueHint::FilePath)]
+        output: Option<PathBuf>,
+    },
+
+    /// Sort atoms of all molecules. The permutation is kept in molecule
+    /// properties for restoring the original order using `unsort`, and is
+    /// available in templates as `molecule.permutation`.
+    ///
+    /// Usage: sort by element; sort by fz --reverse; sort by distance-to 5
+    #[clap(name = "sort")]
+    Sort {
+        #[clap(value_parser = ["by"], hide = true)]
+        by: String,
+
+        /// The sort key: element, z, fz or distance-to.
+        key: String,
+
+        /// The reference atom for sorting by distance-to.
+        atom: Option<usize>,
+
+        /// Sort in descending order.
+        #[clap(long)]
+        reverse: bool,
+    },
+
+    /// Restore the original atom order before `sort`.
+    #[clap(name = "unsort")]
+    Unsort {},
+
+    /// Keep a copy of current molecules in a named workspace slot.
+    #[clap(name = "store")]
+    Store {
+        /// The name of the slot.
+        name: String,
+    },
+
+    /// Restore molecules from a named workspace slot.
+    #[clap(name = "restore")]
+    Restore {
+        /// The name of the slot.
+        name: String,
+    },
+
+    /// List named workspace slots.
+    #[clap(name = "slots")]
+    Slots {},
+
+    /// List active molecules (frames) with index, formula and energy or
+    /// title.
+    #[clap(name = "frames")]
+    Frames {},
+
+    /// Keep only the selected frames.
+    ///
+    /// Usage: pick 1,5-9
+    #[clap(name = "pick")]
+    Pick {
+        /// Frames to keep, such as "1,5-9".
+        frames: String,
+    },
+
+    /// Remove the selected frames.
+    ///
+    /// Usage: drop 2-4
+    #[clap(name = "drop")]
+    Drop {
+        /// Frames to remove, such as "1,5-9".
+        frames: String,
+    },
+
+    /// Reverse the order of frames.
+    #[clap(name = "reverse")]
+    Reverse {},
+
+    /// Keep frames in a slice. The start and end are 1-based and
+    /// inclusive, and can be omitted or negative to count from the last
+    /// frame.
+    ///
+    /// Usage: slice 10:100:5; slice -10:
+    #[clap(name = "slice")]
+    Slice {
+        /// Frame slice in start:end:step format.
+        #[clap(allow_hyphen_values = true)]
+        slice: String,
+    },
+
+    /// Sort frames.
+    ///
+    /// Usage: sort-frames by energy
+    #[clap(name = "sort-frames")]
+    SortFrames {
+        #[clap(value_parser = ["by"], hide = true)]
+        by: String,
+
+        /// The sort key: energy or natoms.
+        key: String,
+
+        /// Sort in descending order.
+        #[clap(long)]
+        reverse: bool,
+    },
+
+    /// Append frames from files or workspace slots (@name).
+    ///
+    /// Usage: concat more.xyz @saved
+    #[clap(name = "concat")]
+    Concat {
+        /// Files or workspace slots to append.
+        #[clap(required = true, value_hint = ValueHint::FilePath)]
+        sources: Vec<String>,
+    },
+
+    /// Compute active molecules using a BlackBox model, replacing them with
+    /// the computed structures.
+    #[clap(name = "compute")]
+    Compute {
+        /// Template directory with all related files. The default is current
+        /// directory.
+        #[clap(short = 't', long = "bbm-dir", value_hint = ValueHint::DirPath)]
+        bbmdir: Option<PathBuf>,
+
+        /// Compute many molecules in bunch.
+        #[clap(short = 'b', long = "bunch")]
+        bunch: bool,
+    },
+
+    /// Optimize active molecules using a BlackBox model with the builtin
+    /// LBFGS optimizer, replacing them with the optimized structures.
+    #[clap(name = "optimize", alias = "opt")]
+    Optimize {
+        /// Template directory with all related files. The default is current
+        /// directory.
+        #[clap(short = 't', long = "bbm-dir", value_hint = ValueHint::DirPath)]
+        bbmdir: Option<PathBuf>,
+
+        /// Forces convergence criterion for optimizing molecule geometry.
+        #[clap(long, default_value = "0.1")]
+        fmax: f64,
+
+        /// Max allowed number of iterations during optimization.
+        #[clap(long, default_value = "50")]
+        nmax: usize,
+    },
+
+    /// Generate intermediate images between two structures, e.g. for
+    /// preparing NEB calculations. The result replaces active molecules,
+    /// including the two end points.
+    ///
+    /// Usage: interpolate 7 --idpp; interpolate 5 --from @is --to @fs
+    #[clap(name = "interpolate")]
+    Interpolate {
+        /// The number of intermediate images.
+        nimages: usize,
+
+        /// The initial structure from file or workspace slot (@name). The
+        /// default is the first active molecule.
+        #[clap(long, value_hint = ValueHint::FilePath)]
+        from: Option<String>,
+
+        /// The final structure from file or workspace slot (@name). The
+        /// default is the last active molecule.
+        #[clap(long, value_hint = ValueHint::FilePath)]
+        to: Option<String>,
+
+        /// Refine linearly interpolated images using image dependent pair
+        /// potential (IDPP).
+        #[clap(long)]
+        idpp: bool,
+
+        /// Forces convergence criterion for IDPP refinement.
+        #[clap(long, default_value = "0.01", requires = "idpp")]
+        fmax: f64,
+
+        /// Max allowed number of steps for IDPP refinement.
+        #[clap(long, default_value = "1000", requires = "idpp")]
+        nmax: usize,
+    },
+
+    /// Print RMSD matrix between all active molecules after alignment.
+    #[clap(name = "rmsd-matrix")]
+    RmsdMatrix {
+        /// Match atoms of the same element for permutation invariance.
+        #[clap(long)]
+        permute: bool,
+    },
+
+    /// Remove duplicate structures from active molecules. Energies are read
+    /// from molecule properties or titles when available.
+    ///
+    /// Usage: dedup --rmsd 0.1 --energy 1e-3
+    #[clap(name = "dedup")]
+    Dedup {
+        /// RMSD threshold in Angstrom for duplicates.
+        #[clap(long, default_value = "0.1")]
+        rmsd: f64,
+
+        /// Energy threshold for duplicates.
+        #[clap(long, default_value = "1e-3")]
+        energy: f64,
+
+        /// Match atoms of the same element for permutation invariance.
+        #[clap(long)]
+        permute: bool,
+    },
+
+    /// Group active molecules into clusters of similar structures.
+    #[clap(name = "cluster")]
+    Cluster {
+        /// RMSD threshold in Angstrom for linking similar structures.
+        #[clap(long, default_value = "0.5")]
+        rmsd: f64,
+
+        /// Match atoms of the same element for permutation invariance.
+        #[clap(long)]
+        permute: bool,
+
+        /// Keep only the representative of each cluster, which has the
+        /// lowest energy if available.
+        #[clap(long)]
+        representatives: bool,
+    },
+
     /// Superimpose current molecule onto reference molecule by translating and
     /// rotating target molecule
     #[clap(name = "superimpose")]
     Superimpose {
         /// Path to reference molecule file.
-        #[clap(name = "REFERENCE_MOLECULE")]
+        #[clap(name = "REFERENCE_MOLECULE", value_hint = ValueHint::FilePath)]
         filename: PathBuf,
     },
 
+    /// Measure distance, angle or dihedral angle between atoms.
+    ///
+    /// Usage: measure 1 2; measure 1 2 3; measure 1 2 3 4
+    #[clap(name = "measure")]
+    Measure {
+        /// Serial numbers of two, three or four atoms.
+        #[clap(num_args = 2..=4, required = true)]
+        atoms: Vec<usize>,
+    },
+
+    /// Set the distance between two atoms by moving the fragment bonded to
+    /// the second atom.
+    #[clap(name = "set-distance")]
+    SetDistance {
+        atom1: usize,
+        atom2: usize,
+        /// The target distance in Angstrom.
+        #[clap(allow_hyphen_values = true)]
+        value: f64,
+    },
+
+    /// Set the angle formed by three atoms by rotating the fragment bonded to
+    /// the third atom.
+    #[clap(name = "set-angle")]
+    SetAngle {
+        atom1: usize,
+        atom2: usize,
+        atom3: usize,
+        /// The target angle in degree.
+        #[clap(allow_hyphen_values = true)]
+        value: f64,
+    },
+
+    /// Set the dihedral angle formed by four atoms by rotating the fragment
+    /// bonded to the third atom.
+    #[clap(name = "set-dihedral")]
+    SetDihedral {
+        atom1: usize,
+        atom2: usize,
+        atom3: usize,
+        atom4: usize,
+        /// The target dihedral angle in degree.
+        #[clap(allow_hyphen_values = true)]
+        value: f64,
+    },
+
+    /// Translate selected atoms, or all atoms when nothing is selected.
+    ///
+    /// Usage: translate 0,0,1.5; translate --to 5,5,10
+    #[clap(name = "translate")]
+    Translate {
+        /// The displacement vector in Angstrom, e.g.: 0,0,1.5
+        #[clap(allow_hyphen_values = true)]
+        vector: String,
+
+        /// Move the center of atoms to the position given by `vector`.
+        #[clap(long)]
+        to: bool,
+    },
+
+    /// Rotate selected atoms (or all atoms when nothing is selected) rigidly
+    /// about an axis.
+    ///
+    /// Usage: rotate 90 --axis z --atom 1
+    #[clap(name = "rotate")]
+    Rotate {
+        /// The rotation angle in degree.
+        #[clap(allow_hyphen_values = true)]
+        angle: f64,
+
+        /// The rotation axis: x, y, z or a vector such as 1,1,0
+        #[clap(long, default_value = "z", allow_hyphen_values = true)]
+        axis: String,
+
+        /// The point the rotation axis passing through, e.g.: 0,0,0. The
+        /// default is the center of the atoms to be rotated.
+        #[clap(long, conflicts_with = "atom", allow_hyphen_values = true)]
+        point: Option<String>,
+
+        /// The atom the rotation axis passing through.
+        #[clap(long)]
+        atom: Option<usize>,
+    },
+
+    /// Mirror selected atoms (or all atoms when nothing is selected) through
+    /// a plane.
+    ///
+    /// Usage: mirror xy --atom 1
+    #[clap(name = "mirror")]
+    Mirror {
+        /// The mirror plane: xy, yz, xz or the plane normal such as 1,1,0
+        #[clap(allow_hyphen_values = true)]
+        plane: String,
+
+        /// The point the mirror plane passing through. The default is the
+        /// center of the atoms to be mirrored.
+        #[clap(long, conflicts_with = "atom", allow_hyphen_values = true)]
+        point: Option<String>,
+
+        /// The atom the mirror plane passing through.
+        #[clap(long)]
+        atom: Option<usize>,
+    },
+
+    /// Move the center of selected atoms (or all atoms when nothing is
+    /// selected) to the center of the cell, or to the origin for non-periodic
+    /// system.
+    #[clap(name = "center")]
+    Center {
+        /// Center at the origin even for periodic system.
+        #[clap(long)]
+        origin: bool,
+    },
+
+    /// Delete selected atoms.
+    #[clap(name = "delete", alias = "del")]
+    Delete {
+        /// Atoms to be deleted, e.g.: "2,3,8" or "2-9". The default is the
+        /// current selection.
+        #[clap(value_parser = atoms_arg)]
+        atoms: Option<String>,
+    },
+
+    /// Add a new atom. Bonds are rebuilt afterwards.
+    ///
+    /// Usage: add O 0.0 0.0 1.2; add H 0.5 0.5 0.5 --frac
+    #[clap(name = "add")]
+    Add {
+        /// The element symbol of the new atom.
+        #[clap(value_name = "ELEMENT")]
+        element: String,
+        #[clap(allow_hyphen_values = true)]
+        x: f64,
+        #[clap(allow_hyphen_values = true)]
+        y: f64,
+        #[clap(allow_hyphen_values = true)]
+        z: f64,
+
+        /// Treat x, y, z as fractional coordinates.
+        #[clap(long)]
+        frac: bool,
+    },
+
+    /// Substitute the element of selected atoms (or all atoms when nothing is
+    /// selected). Bonds are rebuilt afterwards.
+    ///
+    /// Usage: substitute Au; substitute Pt->Au 25% --seed 42
+    #[clap(name = "substitute", alias = "sub")]
+    Substitute {
+        /// The new element, or "A->B" for replacing element A with element B.
+        #[clap(value_name = "ELEMENT")]
+        spec: String,
+
+        /// Substitute randomly only a fraction of the atoms, e.g.: 25% or 0.25
+        #[clap(value_parser = fraction_arg)]
+        fraction: Option<String>,
+
+        /// The seed for random substitution.
+        #[clap(long)]
+        seed: Option<u64>,
+    },
+
+    /// Add hydrogens to saturate the valences of atoms, using bonds created
+    /// by `rebond`. The hybridization (sp3, sp2 or sp) of each atom is
+    /// estimated from its bond angles and bond lengths.
+    ///
+    /// Usage: rebond; select 12-20; add-hydrogens --selected
+    #[clap(name = "add-hydrogens", alias = "addh")]
+    AddHydrogens {
+        /// Add hydrogens only to selected atoms, e.g. for capping a cluster
+        /// model cut from crystal.
+        #[clap(long)]
+        selected: bool,
+    },
+
+    /// Report atoms with unusual number of bonds, which is useful for
+    /// checking the bond tolerance of `rebond`.
+    #[clap(name = "check-valence")]
+    CheckValence {},
+
+    /// Merge molecules into a single structure, keeping the lattice and
+    /// properties of the first one. Without sources, all active molecules are merged;
+    /// otherwise the first active molecule is merged with molecules from
+    /// the sources.
+    ///
+    /// Usage: merge; merge CO.xyz --offset 0,0,12; merge @water --frac --offset 0.5,0.5,0.3
+    #[clap(name = "merge")]
+    Merge {
+        /// Files or workspace slots (@name) to merge with.
+        #[clap(value_hint = ValueHint::FilePath)]
+        sources: Vec<String>,
+
+        /// Translation vector such as "0,0,5" for each molecule after the
+        /// first, in order. This option can be repeated.
+        #[clap(long, allow_hyphen_values = true)]
+        offset: Vec<String>,
+
+        /// The offsets are in fractional coordinates.
+        #[clap(long)]
+        frac: bool,
+
+        /// Warn about atoms closer than this distance in Angstrom.
+        #[clap(long, default_value = "0.7")]
+        overlap: f64,
+    },
+
     /// Show supported file formats.
     #[clap(name = "avail")]
     Avail {},
@@ -203,6 +908,8 @@ impl Commander {
             filename: None,
             molecules: vec![],
             selection: None,
+            workspace: Default::default(),
+            computed: vec![],
         }
     }
 
@@ -221,12 +928,22 @@ impl Commander {
                     eprintln!("{:?}", e);
                 }
             }
-            GoshCmd::Load { filename } => {
-                let filename = normalize_path(&filename);
-                self.molecules = gchemol::io::read_all(&filename)?;
-                self.filename = filename.into();
+            GoshCmd::Load { filename, format } => {
+                if filename == Path::new("-") {
+                    let fmt = format.as_deref().ok_or_else(|| format_err!("reading from stdin requires --format"))?;
+                    let s = std::io::read_to_string(std::io::stdin())?;
+                    self.molecules = parse_molecules(&s, fmt)?;
+                    self.filename = None;
+                } else {
+                    let filename = normalize_path(&filename);
+                    self.molecules = match format {
+                        Some(fmt) => parse_molecules(&gut::fs::read_file(&filename)?, fmt)?,
+                        None => gchemol::io::read_all(&filename)?,
+                    };
+                    self.filename = filename.into();
+                }
 
-                println!("Loaded {} molecule(s).", self.molecules.len());
+                eprintln!("Loaded {} molecule(s).", self.molecules.len());
             }
 
             GoshCmd::LoadChk { filename, chk_slot } => {
@@ -238,7 +955,40 @@ impl Commander {
                 self.molecules = vec![mol];
                 self.filename = Some(filename.to_owned());
 
-                println!("Loaded one molecule from checkpoint file: {}", filename.display());
+                eprintln!("Loaded one molecule from checkpoint file: {}", filename.display());
+            }
+
+            GoshCmd::Info {} => {
+                self.action(&GoshCmd::Show {
+                    what: "info".into(),
+                    selected: false,
+                })?;
+            }
+            GoshCmd::Show { what, selected } => {
+                if what == "properties" {
+                    if self.computed.is_empty() {
+                        bail!("No computed properties. Please run `compute` or `optimize` first.");
+                    }
+                    for mp in self.computed.iter() {
+                        println!("{}", mp);
+                    }
+                    return Ok(());
+                }
+                self.check()?;
+                let n = self.molecules.len();
+                for (i, mol) in self.molecules.iter().enumerate() {
+                    if n > 1 {
+                        println!("== molecule {}/{} ==", i + 1, n);
+                    }
+                    match what.as_str() {
+                        "info" => crate::info::print_summary(mol),
+                        "atoms" => {
+                            let atoms = if *selected { self.selected_atoms(mol) } else { mol.numbers().collect() };
+                            crate::info::print_atoms(mol, &atoms)?;
+                        }
+                        _ => bail!("invalid item to show: {:?}. Possible values: info, atoms, properties", what),
+                    }
+                }
             }
 
             GoshCmd::Clean {} => {
@@ -259,6 +1009,25 @@ impl Commander {
                 }
             }
 
+            GoshCmd::Wrap { selected } => {
+                self.check()?;
+                for i in 0..self.molecules.len() {
+                    let mol = &self.molecules[i];
+                    let atoms = if *selected { self.selected_atoms(mol) } else { mol.numbers().collect() };
+                    crate::pbc::wrap_atoms(&mut self.molecules[i], &atoms)?;
+                }
+            }
+
+            GoshCmd::Unwrap { selected } => {
+                self.check()?;
+                for i in 0..self.molecules.len() {
+                    let mol = &self.molecules[i];
+                    let atoms = if *selected { self.selected_atoms(mol) } else { mol.numbers().collect() };
+                    let n = crate::pbc::make_whole(&mut self.molecules[i], &atoms)?;
+                    eprintln!("Moved {} atoms across periodic boundaries.", n);
+                }
+            }
+
             GoshCmd::BoundingBox { padding } => {
                 self.check()?;
                 if *padding > 0.01 {
@@ -270,21 +1039,29 @@ impl Commander {
                 }
             }
 
-            GoshCmd::Write { filename, json } => {
+            GoshCmd::Write { filename, json, format } => {
                 self.check()?;
 
                 if let Some(filename) = filename.as_ref().or(self.filename.as_ref()) {
                     let filename = normalize_path(filename);
-                    if *json {
+                    if filename == Path::new("-") {
+                        let s = if *json {
+                            self.molecules.iter().map(|m| io::to_json(m)).collect::<Result<String>>()?
+                        } else {
+                            let fmt = format.as_deref().ok_or_else(|| format_err!("writing into stdout requires --format"))?;
+                            format_molecules(&self.molecules, fmt)?
+                        };
+                        print!("{}", s);
+                    } else if *json {
                         let s: Result<String> = self.molecules.iter().map(|m| io::to_json(m)).collect();
-                        println!("writing in raw json format ...");
+                        eprintln!("writing in raw json format ...");
                         gut::fs::write_to_file(&filename, &s?);
                     } else {
-                        io::write(&filename, &self.molecules)?;
+                        write_molecules(&filename, &self.molecules, format.as_deref())?;
                     }
-                    println!("Wrote {} molecules in {}", self.molecules.len(), filename.display());
+                    eprintln!("Wrote {} molecules in {}", self.molecules.len(), filename.display());
                 } else {
-                    eprintln!("No filename.");
+                    bail!("No filename.");
                 }
             }
 
@@ -298,21 +1075,102 @@ impl Commander {
             //     self.molecules.clear();
             //     self.molecules.extend(mols);
             // }
-            GoshCmd::Rebond { bond_tolerance } => {
+            GoshCmd::Rebond { bond_tolerance, bond_orders } => {
                 self.check()?;
                 let mut options = Molecule::rebond_options();
                 options.bond_tolerance = *bond_tolerance;
                 for mol in self.molecules.iter_mut() {
                     mol.rebond_with_options(&options);
-                    println!("Created {} bonds", mol.nbonds());
+                    eprintln!("Created {} bonds", mol.nbonds());
+                    if *bond_orders {
+                        let n = crate::valence::set_bond_orders(mol);
+                        eprintln!("Found {} multiple or aromatic bonds", n);
+                    }
                 }
             }
-            GoshCmd::Convert { files, format_to } => {
+            GoshCmd::Smiles { fragments } => {
+                self.check()?;
+                for mol in self.molecules.iter_mut() {
+                    let ss = crate::smiles::fragment_smiles(mol)?;
+                    if *fragments {
+                        for s in ss.iter() {
+                            println!("{}", s);
+                        }
+                    } else {
+                        println!("{}", ss.join("."));
+                    }
+                    mol.properties.store(crate::smiles::SMILES_KEY, &ss.join("."))?;
+                }
+            }
+            GoshCmd::Convert {
+                files,
+                format_to,
+                format,
+                out_dir,
+                split,
+                no_clobber,
+            } => {
+                let ext = match (format_to, format) {
+                    (Some(e), _) => {
+                        let ext = e.trim_start_matches('.');
+                        if ext.is_empty() {
+                            bail!("invalid target file extension: {:?}", e);
+                        }
+                        ext.to_owned()
+                    }
+                    (None, Some(f)) => {
+                        let ext = format_extension(f);
+                        if ext.is_empty() {
+                            bail!("no file extension found in format {:?}: please specify -e", f);
+                        }
+                        ext.to_owned()
+                    }
+                    (None, None) => bail!("no target format: please specify -e or --format"),
+                };
+                let files = expand_file_patterns(files)?;
+                let output_path = |f: &Path| -> Result<PathBuf> {
+                    let path = f.with_extension(&ext);
+                    match out_dir {
+                        Some(d) => {
+                            let name = path.file_name().ok_or_else(|| format_err!("invalid input file: {}", f.display()))?;
+                            Ok(d.join(name))
+                        }
+                        None => Ok(path),
+                    }
+                };
+                // check all targets before converting any file
                 for f in files.iter() {
-                    let mols = gchemol::io::read_all(f)?;
-                    io::write(f.with_extension(&format_to[1..]), &mols)?;
+                    if output_path(f)? == *f {
+                        bail!("output file is the same as input: {}", f.display());
+                    }
                 }
-                println!("{} files were converted in {} format", files.len(), format_to);
+                if let Some(d) = out_dir {
+                    std::fs::create_dir_all(d).with_context(|| format!("failed to create directory {:?}", d))?;
+                }
+
+                let mut nwritten = 0;
+                for f in files.iter() {
+                    let mols = io::read_all(f)?;
+                    let path = output_path(f)?;
+                    let outputs: Vec<(PathBuf, &[Molecule])> = if *split && mols.len() > 1 {
+                        let width = mols.len().to_string().len();
+                        mols.iter()
+                            .enumerate()
+                            .map(|(i, m)| (indexed_path(&path, &format!("{:0w$}", i + 1, w = width)), std::slice::from_ref(m)))
+                            .collect()
+                    } else {
+                        vec![(path, &mols[..])]
+                    };
+                    for (path, mols) in outputs {
+                        if *no_clobber && path.exists() {
+                            eprintln!("Warning: {} exists, skipped.", path.display());
+                            continue;
+                        }
+                        write_molecules(&path, mols, format.as_deref())?;
+                        nwritten += 1;
+                    }
+                }
+                eprintln!("{} files were converted into {} files in {} format", files.len(), nwritten, format.as_deref().unwrap_or(&ext));
             }
             GoshCmd::Supercell {
                 range_a,
@@ -323,13 +1181,371 @@ impl Commander {
 
                 let mut mols = vec![];
                 for mol in self.molecules.iter() {
-                    if let Some(mol) = mol.supercell(*range_a, *range_b, *range_c) {
-                        mols.push(mol);
-                    } else {
-                        eprintln!("No lattice data.");
+                    let mol = mol.supercell(*range_a, *range_b, *range_c).ok_or_else(|| format_err!("No lattice data."))?;
+                    mols.push(mol);
+                }
+                self.molecules = mols;
+            }
+            GoshCmd::Rattle { stdev, seed } => {
+                self.check()?;
+                let mut rng = crate::edit::new_rng(*seed);
+                for i in 0..self.molecules.len() {
+                    let atoms = self.selected_atoms(&self.molecules[i]);
+                    let n = crate::perturb::rattle(&mut self.molecules[i], &atoms, *stdev, &mut rng)?;
+                    eprintln!("Displaced {} atoms.", n);
+                }
+            }
+            GoshCmd::Strain {
+                xx,
+                yy,
+                zz,
+                xy,
+                xz,
+                yz,
+                scale,
+            } => {
+                self.check()?;
+                let strain = crate::perturb::strain_tensor(*xx, *yy, *zz, *xy, *xz, *yz, *scale);
+                for mol in self.molecules.iter_mut() {
+                    crate::perturb::apply_strain(mol, strain)?;
+                }
+            }
+            GoshCmd::ReduceCell { method } => {
+                self.check()?;
+                for mol in self.molecules.iter_mut() {
+                    *mol = crate::cell::reduce_cell(mol, method)?;
+                }
+                self.selection = None;
+            }
+            GoshCmd::Primitive { tol } => {
+                self.check()?;
+                for mol in self.molecules.iter_mut() {
+                    let n = mol.natoms();
+                    *mol = crate::cell::primitive_cell(mol, *tol)?;
+                    eprintln!("Found primitive cell with {} atoms (was {}).", mol.natoms(), n);
+                }
+                self.selection = None;
+            }
+            GoshCmd::Conventional { tol } => {
+                self.check()?;
+                for mol in self.molecules.iter_mut() {
+                    let (conv, system) = crate::cell::conventional_cell(mol, *tol)?;
+                    *mol = conv;
+                    eprintln!("Found {} conventional cell with {} atoms.", system, mol.natoms());
+                }
+                self.selection = None;
+            }
+            GoshCmd::Symmetry { tol, symmetrize } => {
+                self.check()?;
+                let n = self.molecules.len();
+                for (i, mol) in self.molecules.iter_mut().enumerate() {
+                    if n > 1 {
+                        println!("== molecule {}/{} ==", i + 1, n);
+                    }
+                    let sym = crate::symmetry::detect_symmetry(mol, *tol)?;
+                    println!("point group: {}", sym.point_group);
+                    if let Some(sg) = &sym.space_group {
+                        println!("space group: {}", sg);
+                    }
+                    if let Some(system) = sym.crystal_system {
+                        println!("crystal system: {}", system);
+                    }
+                    println!("symmetry operations: {}", sym.nops());
+                    println!("equivalent atoms:");
+                    for set in sym.equivalent_atoms(mol) {
+                        let symbol = mol.get_atom(set[0]).unwrap().symbol().to_owned();
+                        let s = gut::utils::abbreviate_numbers_human_readable(&set)?;
+                        println!("  {:<3} {}", symbol, s);
+                    }
+                    if *symmetrize {
+                        sym.symmetrize(mol)?;
+                        eprintln!("Symmetrized atom positions.");
+                    }
+                    mol.properties.store(crate::symmetry::POINT_GROUP_KEY, &sym.point_group)?;
+                    if let Some(sg) = &sym.space_group {
+                        mol.properties.store(crate::symmetry::SPACE_GROUP_KEY, sg)?;
                     }
                 }
+            }
+            GoshCmd::Slab {
+                h,
+                k,
+                l,
+                layers,
+                vacuum,
+                termination,
+                orthogonal,
+                freeze,
+            } => {
+                self.check()?;
+                let options = crate::slab::SlabOptions {
+                    layers: *layers,
+                    vacuum: *vacuum,
+                    termination: *termination,
+                    orthogonal: *orthogonal,
+                    freeze: *freeze,
+                };
+                let mols: Result<Vec<_>> = self
+                    .molecules
+                    .iter()
+                    .map(|mol| crate::slab::build_slab(mol, [*h, *k, *l], &options))
+                    .collect();
+                self.molecules = mols?;
+                self.selection = None;
+                for mol in &self.molecules {
+                    eprintln!("Created slab {} with {} atoms", mol.title(), mol.natoms());
+                }
+            }
+            GoshCmd::Adsorb {
+                adsorbate,
+                site,
+                height,
+                binding,
+                all_sites,
+                output,
+            } => {
+                self.check()?;
+                if self.molecules.len() != 1 {
+                    bail!("only work for a single molecule");
+                }
+                let adsorbate = self
+                    .fetch_molecules(adsorbate)?
+                    .into_iter()
+                    .next()
+                    .ok_or_else(|| format_err!("no adsorbate molecule found"))?;
+                let slab = &self.molecules[0];
+                let sites = if *all_sites {
+                    crate::adsorb::find_distinct_sites(slab)?
+                } else if ["top", "bridge", "hollow"].contains(&site.as_str()) {
+                    vec![crate::adsorb::find_site_by_kind(slab, site)?]
+                } else {
+                    let atoms = parse_numbers_human_readable(site)?;
+                    vec![crate::adsorb::site_from_atoms(slab, &atoms)?]
+                };
+
+                let mut mols = vec![];
+                for (i, site) in sites.iter().enumerate() {
+                    let mol = crate::adsorb::place_adsorbate(slab, &adsorbate, *binding, site, *height)?;
+                    println!("site {}: {} on atoms {:?}", i + 1, site.kind, site.atoms);
+                    if let Some(output) = output {
+                        let path = indexed_path(output, &format!("{}-{}", site.kind, i + 1));
+                        mol.to_file(&path)?;
+                        eprintln!("Wrote {}", path.display());
+                    }
+                    mols.push(mol);
+                }
                 self.molecules = mols;
+                self.selection = None;
+            }
+            GoshCmd::Store { name } => {
+                self.check()?;
+                self.workspace.insert(name.to_owned(), self.molecules.clone());
+                eprintln!("Stored {} molecule(s) in slot {:?}", self.molecules.len(), name);
+            }
+            GoshCmd::Restore { name } => {
+                self.molecules = self.fetch_molecules(&format!("@{}", name))?;
+                self.selection = None;
+                eprintln!("Restored {} molecule(s) from slot {:?}", self.molecules.len(), name);
+            }
+            GoshCmd::Slots {} => {
+                for (name, mols) in self.workspace.iter().sorted_by_key(|(k, _)| k.as_str()) {
+                    println!("@{}: {} molecule(s)", name, mols.len());
+                }
+            }
+            GoshCmd::Frames {} => {
+                self.check()?;
+                crate::frames::print_frames(&self.molecules);
+            }
+            GoshCmd::Pick { frames } => {
+                self.check()?;
+                let selected = crate::frames::parse_frames(frames, self.molecules.len())?;
+                self.molecules = selected.into_iter().map(|i| self.molecules[i].clone()).collect();
+                eprintln!("Picked {} frames.", self.molecules.len());
+            }
+            GoshCmd::Drop { frames } => {
+                self.check()?;
+                let selected = crate::frames::parse_frames(frames, self.molecules.len())?;
+                let mols = std::mem::take(&mut self.molecules);
+                self.molecules = mols
+                    .into_iter()
+                    .enumerate()
+                    .filter_map(|(i, m)| if selected.contains(&i) { None } else { Some(m) })
+                    .collect();
+                eprintln!("{} frames left.", self.molecules.len());
+            }
+            GoshCmd::Reverse {} => {
+                self.check()?;
+                self.molecules.reverse();
+            }
+            GoshCmd::Slice { slice } => {
+                self.check()?;
+                let selected = crate::frames::parse_slice(slice, self.molecules.len())?;
+                self.molecules = selected.into_iter().map(|i| self.molecules[i].clone()).collect();
+                eprintln!("Kept {} frames.", self.molecules.len());
+            }
+            GoshCmd::SortFrames { key, reverse, .. } => {
+                self.check()?;
+                crate::frames::sort_frames(&mut self.molecules, key, *reverse)?;
+            }
+            GoshCmd::Concat { sources } => {
+                for source in sources {
+                    let mols = self.fetch_molecules(source)?;
+                    eprintln!("Appended {} frames from {}.", mols.len(), source);
+                    self.molecules.extend(mols);
+                }
+            }
+            GoshCmd::Compute { bbmdir, bunch } => {
+                self.check()?;
+                let mut bbm = new_bbm(bbmdir.as_deref())?;
+                let computed = if *bunch {
+                    bbm.compute_bunch(&self.molecules)
+                } else {
+                    self.molecules.iter().map(|mol| bbm.compute(mol)).collect()
+                };
+                let computed = match computed {
+                    Ok(computed) => computed,
+                    Err(e) => {
+                        bbm.keep_scratch_files();
+                        return Err(e);
+                    }
+                };
+                self.update_from_computed(computed)?;
+            }
+            GoshCmd::Optimize { bbmdir, fmax, nmax } => {
+                self.check()?;
+                let mut bbm = new_bbm(bbmdir.as_deref())?;
+                let mut computed = vec![];
+                for mol in self.molecules.iter() {
+                    eprintln!("Optimizing molecule using builtin algorithm ...");
+                    let mut mol = mol.clone();
+                    match gosh_optim::Optimizer::new(*fmax, *nmax).optimize_geometry(&mut mol, &mut bbm) {
+                        Ok(optimized) => computed.push(optimized.computed),
+                        Err(e) => {
+                            bbm.keep_scratch_files();
+                            return Err(e);
+                        }
+                    }
+                }
+                self.update_from_computed(computed)?;
+            }
+            GoshCmd::Sort { key, atom, reverse, .. } => {
+                self.check()?;
+                for mol in self.molecules.iter_mut() {
+                    crate::reorder::sort_atoms(mol, key, *atom, *reverse)?;
+                }
+                // atom numbers changed
+                self.selection = None;
+                eprintln!("Sorted atoms by {}.", key);
+            }
+            GoshCmd::Unsort {} => {
+                self.check()?;
+                for mol in self.molecules.iter_mut() {
+                    crate::reorder::unsort_atoms(mol)?;
+                }
+                self.selection = None;
+                eprintln!("Restored original atom order.");
+            }
+            GoshCmd::Interpolate {
+                nimages,
+                from,
+                to,
+                idpp,
+                fmax,
+                nmax,
+            } => {
+                if from.is_none() && to.is_none() && self.molecules.len() < 2 {
+                    bail!("Need at least two active molecules, or the end points specified using --from/--to.");
+                }
+                let initial = match from {
+                    Some(from) => self.fetch_molecules(from)?.into_iter().next(),
+                    None => self.molecules.first().cloned(),
+                };
+                let final_ = match to {
+                    Some(to) => self.fetch_molecules(to)?.into_iter().last(),
+                    None => self.molecules.last().cloned(),
+                };
+                let (initial, final_) = match (initial, final_) {
+                    (Some(a), Some(b)) => (a, b),
+                    _ => bail!("No initial or final structure available."),
+                };
+
+                let mut images = crate::interpolate::interpolate_linear(&initial, &final_, *nimages)?;
+                if *idpp {
+                    eprintln!("Refining images using IDPP method ...");
+                    if !crate::interpolate::refine_idpp(&mut images, *fmax, *nmax)? {
+                        eprintln!("IDPP refinement not converged in {} steps.", nmax);
+                    }
+                }
+                self.molecules = images;
+                eprintln!("Generated {} images including end points.", self.molecules.len());
+            }
+            GoshCmd::RmsdMatrix { permute } => {
+                self.check()?;
+                let matrix = crate::similarity::rmsd_matrix(&self.molecules, *permute);
+                print!("{:>6}", "#");
+                for j in 0..matrix.len() {
+                    print!(" {:>8}", j + 1);
+                }
+                println!();
+                for (i, row) in matrix.iter().enumerate() {
+                    print!("{:>6}", i + 1);
+                    for x in row {
+                        match x {
+                            Some(x) => print!(" {:8.4}", x),
+                            None => print!(" {:>8}", "-"),
+                        }
+                    }
+                    println!();
+                }
+            }
+            GoshCmd::Dedup { rmsd, energy, permute } => {
+                self.check()?;
+                let duplicates = crate::similarity::find_duplicates(&self.molecules, *rmsd, *energy, *permute);
+                println!("{:>6} {:>12} {:>10}", "#", "duplicate of", "rmsd");
+                for (i, dup) in duplicates.iter().enumerate() {
+                    if let Some(j) = dup {
+                        let x = crate::similarity::rmsd(&self.molecules[i], &self.molecules[*j], *permute).unwrap_or(0.0);
+                        println!("{:>6} {:>12} {:10.4}", i + 1, j + 1, x);
+                    }
+                }
+                let n = self.molecules.len();
+                let mols = std::mem::take(&mut self.molecules);
+                self.molecules = mols.into_iter().zip(duplicates).filter_map(|(m, d)| if d.is_none() { Some(m) } else { None }).collect();
+                eprintln!("Removed {} duplicates, kept {} molecules.", n - self.molecules.len(), self.molecules.len());
+            }
+            GoshCmd::Cluster {
+                rmsd,
+                permute,
+                representatives,
+            } => {
+                self.check()?;
+                let labels = crate::similarity::cluster(&self.molecules, *rmsd, *permute);
+                let nclusters = labels.iter().max().map_or(0, |x| x + 1);
+                let energies = self.molecules.iter().map(crate::info::get_energy).collect_vec();
+                let mut picked = vec![];
+                println!("{:>8} {:>6} {:>8} {:>16}  {}", "cluster", "size", "rep", "energy", "members");
+                for c in 0..nclusters {
+                    let members = (0..labels.len()).filter(|&i| labels[i] == c).collect_vec();
+                    // the representative has the lowest energy, or the first one
+                    let rep = members
+                        .iter()
+                        .copied()
+                        .min_by(|&i, &j| {
+                            let (ei, ej) = (energies[i].unwrap_or(f64::MAX), energies[j].unwrap_or(f64::MAX));
+                            ei.total_cmp(&ej)
+                        })
+                        .unwrap();
+                    let energy = energies[rep].map_or("-".to_owned(), |e| format!("{:16.6}", e));
+                    let ids = members.iter().map(|i| i + 1).collect_vec();
+                    let s = gut::utils::abbreviate_numbers_human_readable(&ids)?;
+                    println!("{:>8} {:>6} {:>8} {:>16}  {}", c + 1, members.len(), rep + 1, energy, s);
+                    picked.push(rep);
+                }
+                if *representatives {
+                    picked.sort();
+                    self.molecules = picked.into_iter().map(|i| self.molecules[i].clone()).collect();
+                    eprintln!("Kept {} representative molecules.", self.molecules.len());
+                }
             }
             GoshCmd::Superimpose { filename } => {
                 self.check()?;
@@ -341,8 +1557,7 @@ impl Commander {
 
                 let mut ss = vec![];
                 for mol in &self.molecules {
-                    let s = mol
-                        .render_with(&filename)
+                    let s = crate::template::render_molecule(mol, &filename)
                         .with_context(|| format!("Failed to render molecule with file: {:?}", filename))?;
                     ss.push(s);
                 }
@@ -371,106 +1586,328 @@ impl Commander {
                         self.selection = self.molecules[0].numbers().collect_vec().into();
                     } else if selection == "none" {
                         self.selection = None;
+                    } else if selection.starts_with(|c: char| c.is_ascii_alphabetic()) {
+                        // select atoms by element symbols
+                        let symbols = selection.split(',').map(|x| x.trim()).collect_vec();
+                        let selected = self.molecules[0]
+                            .atoms()
+                            .filter_map(|(i, a)| if symbols.contains(&a.symbol()) { Some(i) } else { None })
+                            .collect_vec();
+                        self.selection = selected.into();
                     } else {
                         let selected = parse_numbers_human_readable(&selection)?;
                         self.selection = selected.into();
                     }
                 }
                 let n = self.selection.as_ref().map(|x| x.len()).unwrap_or_default();
-                println!("Selected {} atoms", n);
+                eprintln!("Selected {} atoms", n);
                 if let Some(selection) = &self.selection {
                     let s = gut::utils::abbreviate_numbers_human_readable(selection)?;
-                    println!("Selection: {}", s);
+                    eprintln!("Selection: {}", s);
                 }
             }
-            GoshCmd::Freeze { inverse } => {
+            GoshCmd::Freeze {
+                inverse,
+                axes,
+                below_fz,
+                layers,
+                layer_tol,
+            } => {
                 self.check()?;
-                if self.molecules.len() != 1 {
-                    bail!("only work for a single molecule");
-                }
-                if let Some(selected) = &self.selection {
-                    for &i in selected {
-                        let a = self.molecules[0]
-                            .get_atom_mut(i)
-                            .ok_or_else(|| format_err!("no such atom: {}", i))?;
-                        if *inverse {
-                            println!("atom {} was unfreezed", i);
-                            a.set_freezing([false; 3]);
-                        } else {
-                            println!("atom {} was freezed", i);
-                            a.set_freezing([true; 3]);
+                let mask = parse_axes_mask(axes)?;
+                for i in 0..self.molecules.len() {
+                    let mol = &self.molecules[i];
+                    let selected = if let Some(fz) = below_fz {
+                        atoms_by_fz(mol, *fz, |fz, fz_| fz < fz_)?
+                    } else if let Some(n) = layers {
+                        crate::slab::bottom_layer_atoms(mol, *n, *layer_tol)
+                    } else if let Some(selected) = &self.selection {
+                        selected.clone()
+                    } else {
+                        bail!("no selected atoms found!");
+                    };
+
+                    let mol = &mut self.molecules[i];
+                    for &n in &selected {
+                        let a = mol.get_atom_mut(n).ok_or_else(|| format_err!("no such atom: {}", n))?;
+                        let mut freezing = a.freezing();
+                        for k in 0..3 {
+                            if mask[k] {
+                                freezing[k] = !*inverse;
+                            }
                         }
+                        a.set_freezing(freezing);
                     }
-                } else {
-                    eprintln!("no selected atoms found!");
+                    let action = if *inverse { "unfreezed" } else { "freezed" };
+                    let s = gut::utils::abbreviate_numbers_human_readable(&selected)?;
+                    eprintln!("{} atoms were {} along {}: {}", selected.len(), action, axes, s);
                 }
             }
 
-            // FIXME: rewrite
-            GoshCmd::Update { target, select, source } => {
+            GoshCmd::Update {
+                target,
+                select,
+                source,
+                map,
+                auto_map,
+            } => {
                 self.check()?;
                 if self.molecules.len() != 1 {
                     bail!("only work for a single molecule");
                 }
-                let mol = Molecule::from_file(&source)?;
-                if mol.natoms() != self.molecules[0].natoms() {
-                    bail!("invalid source!");
-                }
-
-                match target.as_str() {
-                    "coords" => {
-                        let m: std::collections::HashMap<_, _> = if let Some(select) = select {
-                            let selected_atoms = parse_numbers_human_readable(&select)?;
-                            mol.atoms()
-                                .filter_map(|(i, a)| {
-                                    if selected_atoms.contains(&i) {
-                                        Some((i, a.position()))
-                                    } else {
-                                        None
-                                    }
-                                })
-                                .collect()
-                        } else {
-                            mol.atoms().map(|(i, a)| (i, a.position())).collect()
-                        };
-                        for (i, p) in m {
-                            let a = self.molecules[0].get_atom_mut(i).unwrap();
-                            a.set_position(p);
+                let mol = self
+                    .fetch_molecules(source)?
+                    .into_iter()
+                    .next()
+                    .ok_or_else(|| format_err!("no molecule found in source: {}", source))?;
+
+                let mut mapping = if let Some(map) = map {
+                    crate::update::parse_mapping(map)?
+                } else if *auto_map {
+                    crate::update::auto_mapping(&self.molecules[0], &mol)?
+                } else {
+                    crate::update::ordered_mapping(&self.molecules[0], &mol)?
+                };
+                if let Some(select) = select {
+                    let selected_atoms = parse_numbers_human_readable(select)?;
+                    mapping.retain(|(i, _)| selected_atoms.contains(i));
+                }
+
+                let targets = target.split(',').map(|x| x.trim()).collect_vec();
+                crate::update::update_from(&mut self.molecules[0], &mol, &targets, &mapping)?;
+                eprintln!("Updated {} for {} atoms", targets.join(", "), mapping.len());
+            }
+            GoshCmd::Measure { atoms } => {
+                self.check()?;
+                for mol in self.molecules.iter() {
+                    match atoms.as_slice() {
+                        &[i, j] => {
+                            let d = crate::geometry::get_distance(mol, i, j)?;
+                            println!("distance {}-{}: {:-10.4} Å", i, j, d);
                         }
-                    }
-                    "freezing" => {
-                        let m: std::collections::HashMap<_, _> = if let Some(select) = select {
-                            let selected_atoms = parse_numbers_human_readable(&select)?;
-                            mol.atoms()
-                                .filter_map(|(i, a)| {
-                                    if selected_atoms.contains(&i) {
-                                        Some((i, a.freezing()))
-                                    } else {
-                                        None
-                                    }
-                                })
-                                .collect()
-                        } else {
-                            mol.atoms().map(|(i, a)| (i, a.freezing())).collect()
-                        };
-                        for (i, f) in m {
-                            let a = self.molecules[0].get_atom_mut(i).unwrap();
-                            a.set_freezing(f);
+                        &[i, j, k] => {
+                            let a = crate::geometry::get_angle(mol, i, j, k)?;
+                            println!("angle {}-{}-{}: {:-10.4} °", i, j, k, a);
                         }
+                        &[i, j, k, l] => {
+                            let a = crate::geometry::get_dihedral(mol, i, j, k, l)?;
+                            println!("dihedral {}-{}-{}-{}: {:-10.4} °", i, j, k, l, a);
+                        }
+                        _ => bail!("expect 2, 3 or 4 atoms, but found {}", atoms.len()),
+                    }
+                }
+            }
+            GoshCmd::SetDistance { atom1, atom2, value } => {
+                self.check()?;
+                for mol in self.molecules.iter_mut() {
+                    crate::geometry::set_distance(mol, *atom1, *atom2, *value)?;
+                }
+            }
+            GoshCmd::SetAngle {
+                atom1,
+                atom2,
+                atom3,
+                value,
+            } => {
+                self.check()?;
+                for mol in self.molecules.iter_mut() {
+                    crate::geometry::set_angle(mol, *atom1, *atom2, *atom3, *value)?;
+                }
+            }
+            GoshCmd::SetDihedral {
+                atom1,
+                atom2,
+                atom3,
+                atom4,
+                value,
+            } => {
+                self.check()?;
+                for mol in self.molecules.iter_mut() {
+                    crate::geometry::set_dihedral(mol, *atom1, *atom2, *atom3, *atom4, *value)?;
+                }
+            }
+            GoshCmd::Translate { vector, to } => {
+                self.check()?;
+                let v = crate::transform::parse_vector(vector)?;
+                for i in 0..self.molecules.len() {
+                    let atoms = self.selected_atoms(&self.molecules[i]);
+                    let mol = &mut self.molecules[i];
+                    let disp = if *to { v - crate::transform::center_of(mol, &atoms)? } else { v };
+                    crate::geometry::translate_atoms(mol, &atoms, disp)?;
+                }
+            }
+            GoshCmd::Rotate {
+                angle,
+                axis,
+                point,
+                atom,
+            } => {
+                self.check()?;
+                let axis = crate::transform::parse_axis(axis)?;
+                for i in 0..self.molecules.len() {
+                    let atoms = self.selected_atoms(&self.molecules[i]);
+                    let mol = &mut self.molecules[i];
+                    let origin = pivot_point(mol, &atoms, point.as_deref(), *atom)?;
+                    crate::geometry::rotate_atoms(mol, &atoms, origin, axis, *angle)?;
+                }
+            }
+            GoshCmd::Mirror { plane, point, atom } => {
+                self.check()?;
+                let normal = crate::transform::parse_plane_normal(plane)?;
+                for i in 0..self.molecules.len() {
+                    let atoms = self.selected_atoms(&self.molecules[i]);
+                    let mol = &mut self.molecules[i];
+                    let origin = pivot_point(mol, &atoms, point.as_deref(), *atom)?;
+                    crate::transform::mirror(mol, &atoms, origin, normal)?;
+                }
+            }
+            GoshCmd::Center { origin } => {
+                self.check()?;
+                for i in 0..self.molecules.len() {
+                    let atoms = self.selected_atoms(&self.molecules[i]);
+                    crate::transform::center(&mut self.molecules[i], &atoms, *origin)?;
+                }
+            }
+            GoshCmd::Delete { atoms } => {
+                self.check()?;
+                let selected = match atoms {
+                    Some(atoms) => parse_numbers_human_readable(atoms)?,
+                    None => self.selection.clone().ok_or_else(|| format_err!("no selected atoms found!"))?,
+                };
+                for mol in self.molecules.iter_mut() {
+                    crate::edit::delete_atoms(mol, &selected)?;
+                }
+                // serial numbers have been changed
+                self.selection = None;
+                eprintln!("Deleted {} atoms", selected.len());
+            }
+            GoshCmd::Add { element, x, y, z, frac } => {
+                self.check()?;
+                for mol in self.molecules.iter_mut() {
+                    crate::edit::add_atom(mol, element, [*x, *y, *z], *frac)?;
+                }
+            }
+            GoshCmd::Merge {
+                sources,
+                offset,
+                frac,
+                overlap,
+            } => {
+                self.check()?;
+                let mut mols = if sources.is_empty() {
+                    self.molecules.clone()
+                } else {
+                    vec![self.molecules[0].clone()]
+                };
+                for source in sources {
+                    mols.extend(self.fetch_molecules(source)?);
+                }
+                if mols.len() < 2 {
+                    bail!("At least two molecules are required for merging.");
+                }
+                let offsets: Vec<_> = offset.iter().map(|s| crate::transform::parse_vector(s)).collect::<Result<_>>()?;
+                let merged = crate::edit::merge_molecules(&mols, &offsets, *frac)?;
+                for (i, j, d) in crate::edit::find_overlaps(&merged, *overlap) {
+                    eprintln!("Warning: atoms {} and {} overlap: distance = {:.3}", i, j, d);
+                }
+                eprintln!("Merged {} molecules into one with {} atoms.", mols.len(), merged.natoms());
+                self.molecules = vec![merged];
+                self.selection = None;
+            }
+            GoshCmd::AddHydrogens { selected } => {
+                self.check()?;
+                for i in 0..self.molecules.len() {
+                    let mol = &self.molecules[i];
+                    let atoms = if *selected { self.selected_atoms(mol) } else { mol.numbers().collect() };
+                    // existing atoms keep their serial numbers, so the
+                    // selection is still valid
+                    let n = crate::valence::add_hydrogens(&mut self.molecules[i], &atoms)?;
+                    eprintln!("Added {} hydrogens: {}", n, self.molecules[i].formula());
+                }
+            }
+            GoshCmd::CheckValence {} => {
+                self.check()?;
+                let n = self.molecules.len();
+                for (i, mol) in self.molecules.iter().enumerate() {
+                    if n > 1 {
+                        println!("== molecule {}/{} ==", i + 1, n);
+                    }
+                    if mol.nbonds() == 0 && mol.natoms() > 1 {
+                        eprintln!("Warning: no bonds found. Please run `rebond` first.");
+                    }
+                    let problems = crate::valence::check_valence(mol);
+                    if problems.is_empty() {
+                        println!("No valence problems found.");
+                        continue;
                     }
-                    _ => {
-                        bail!("update: not implemented yet!")
+                    println!("{:>6} {:>6} {:>6}  {}", "atom", "symbol", "bonds", "problem");
+                    for (j, symbol, nbonds, problem) in problems {
+                        println!("{:>6} {:>6} {:>6}  {}", j, symbol, nbonds, problem);
                     }
                 }
             }
+            GoshCmd::Substitute { spec, fraction, seed } => {
+                self.check()?;
+                let mut rng = crate::edit::new_rng(*seed);
+                for i in 0..self.molecules.len() {
+                    let atoms = self.selected_atoms(&self.molecules[i]);
+                    let mol = &mut self.molecules[i];
+                    let n = crate::edit::substitute(mol, &atoms, spec, fraction.as_deref(), &mut rng)?;
+                    eprintln!("Substituted {} atoms: {}", n, mol.formula());
+                }
+            }
             o => {
-                eprintln!("{:?}: not implemented yet!", o);
+                bail!("{:?}: not implemented yet!", o);
             }
         }
 
         Ok(())
     }
 
+    /// Return selected atoms in `mol`, or all atoms when nothing is selected.
+    fn selected_atoms(&self, mol: &Molecule) -> Vec<usize> {
+        match &self.selection {
+            Some(selected) => selected.clone(),
+            None => mol.numbers().collect(),
+        }
+    }
+
+    /// Fetch molecules from a workspace slot (as "@name") or from a file.
+    fn fetch_molecules(&self, source: &str) -> Result<Vec<Molecule>> {
+        if let Some(name) = source.strip_prefix('@') {
+            self.workspace
+                .get(name)
+                .cloned()
+                .ok_or_else(|| format_err!("no such slot: {}", name))
+        } else {
+            let filename = normalize_path(source.as_ref());
+            gchemol::io::read_all(&filename)
+        }
+    }
+
+    /// Replace active molecules with the structures in `computed` model
+    /// properties, which are kept for later inspection.
+    fn update_from_computed(&mut self, computed: Vec<ModelProperties>) -> Result<()> {
+        let mols: Option<Vec<_>> = computed.iter().map(crate::bbm::extract_mol_from).collect();
+        let mut mols = mols.ok_or_else(|| format_err!("no mol in model properties"))?;
+        // keep atom permutation for `unsort`
+        for (mol, old) in mols.iter_mut().zip(self.molecules.iter()) {
+            if let Ok(perm) = old.properties.load::<Vec<usize>>(crate::reorder::PERMUTATION_KEY) {
+                if perm.len() == mol.natoms() {
+                    mol.properties.store(crate::reorder::PERMUTATION_KEY, &perm)?;
+                }
+            }
+        }
+        for mp in computed.iter() {
+            println!("{}", mp);
+        }
+        self.molecules = mols;
+        self.computed = computed;
+        eprintln!("Updated {} molecule(s) from computed results.", self.molecules.len());
+
+        Ok(())
+    }
+
     /// basic sanity check
     fn check(&self) -> Result<()> {
         if self.molecules.is_empty() {
@@ -509,25 +1946,162 @@ fn normalize_path(s: &Path) -> PathBuf {
 // 11042ec8 ends here
 
 // [[file:../gosh.note::*utils][utils:1]]
+/// Construct a BlackBox model from template directory `bbmdir`, or from
+/// current directory by default.
+fn new_bbm(bbmdir: Option<&Path>) -> Result<BlackBoxModel> {
+    match bbmdir {
+        Some(d) => BlackBoxModel::from_dir(normalize_path(d)),
+        None => BlackBoxModel::from_dir(std::env::current_dir()?),
+    }
+}
+
+/// Parse axes such as "xyz" or "z" into a mask for atom freezing.
+fn parse_axes_mask(axes: &str) -> Result<[bool; 3]> {
+    let mut mask = [false; 3];
+    for c in axes.chars() {
+        match c.to_ascii_lowercase() {
+            'x' => mask[0] = true,
+            'y' => mask[1] = true,
+            'z' => mask[2] = true,
+            _ => bail!("invalid axes: {:?}", axes),
+        }
+    }
+    Ok(mask)
+}
+
+/// Expand file names containing wildcards as glob patterns.
+fn expand_file_patterns(patterns: &[String]) -> Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    for p in patterns {
+        let p = normalize_path(p.as_ref()).to_string_lossy().into_owned();
+        if p.contains(|c| c == '*' || c == '?' || c == '[') {
+            let n = files.len();
+            for path in glob::glob(&p)? {
+                files.push(path?);
+            }
+            if files.len() == n {
+                bail!("no files match pattern: {:?}", p);
+            }
+        } else {
+            files.push(p.into());
+        }
+    }
+    Ok(files)
+}
+
+/// Write `mols` into `path` in explicit `format`, or in the format deduced
+/// from file extension by default.
+fn write_molecules(path: &Path, mols: &[Molecule], format: Option<&str>) -> Result<()> {
+    match format {
+        Some(fmt) => gut::fs::write_to_file(path, &format_molecules(mols, fmt)?)?,
+        None => io::write(path, mols)?,
+    }
+    Ok(())
+}
+
+/// Return the file extension for `format`: "vasp/poscar" => "poscar", ".xyz" => "xyz"
+fn format_extension(format: &str) -> &str {
+    format.rsplit('/').next().unwrap_or_default().trim_start_matches('.')
+}
+
+/// Chemical file formats supported by gchemol backends: format names with
+/// their possible file extensions, as listed by `avail` command.
+pub(crate) const BACKEND_FORMATS: &[(&str, &[&str])] = &[
+    ("text/xyz", &[".xyz"]),
+    ("text/mol2", &[".mol2"]),
+    ("text/sdf", &[".sd", ".sdf", ".mol"]),
+    ("text/coord", &[".coord"]),
+    ("vasp/input", &[".poscar", ".vasp"]),
+    ("text/cif", &[".cif"]),
+    ("text/pdb", &[".pdb", ".ent"]),
+    ("gaussian/input", &[".gjf", ".com", ".gau"]),
+    ("ms/car", &[".car"]),
+    ("ms/xtl", &[".xtl"]),
+    ("siesta/input", &[".fdf"]),
+    ("gulp/input", &[".gin", ".gulp"]),
+];
+
+/// Return gchemol format name for `format`, which is a format name such as
+/// vasp/input, or a file extension such as xyz.
+fn format_name(format: &str) -> Result<String> {
+    if format.contains('/') {
+        return Ok(format.to_owned());
+    }
+    let ext = format!(".{}", format.trim_start_matches('.'));
+    BACKEND_FORMATS
+        .iter()
+        .find(|(_, extensions)| extensions.contains(&ext.as_str()))
+        .map(|(name, _)| name.to_string())
+        .ok_or_else(|| format_err!("unknown format: {:?}. Please use a format name listed by `avail` command.", format))
+}
+
+/// Format `mols` as string in `format`, which is a format name such as
+/// vasp/input, or a file extension such as xyz.
+fn format_molecules(mols: &[Molecule], format: &str) -> Result<String> {
+    let fmt = format_name(format)?;
+    mols.iter().map(|m| m.format_as(&fmt)).collect()
+}
+
+/// Parse molecules from string `s` in `format`. The format is given as file
+/// extension such as xyz, or as format name such as vasp/input.
+fn parse_molecules(s: &str, format: &str) -> Result<Vec<Molecule>> {
+    let fmt = format_name(format)?;
+    let mols = io::read_from(std::io::Cursor::new(s.to_owned()), &fmt)?.collect();
+    Ok(mols)
+}
+
+/// Validate atom list argument such as "2,3,8" or "2-9".
+fn atoms_arg(s: &str) -> Result<String> {
+    parse_numbers_human_readable(s)?;
+    Ok(s.to_owned())
+}
+
+/// Validate fraction argument such as "25%" or "0.25".
+fn fraction_arg(s: &str) -> Result<String> {
+    crate::edit::parse_fraction(s)?;
+    Ok(s.to_owned())
+}
+
+/// Insert `label` into the file stem of `path`: "site.vasp" => "site-label.vasp"
+fn indexed_path(path: &Path, label: &str) -> PathBuf {
+    let stem = path.file_stem().map(|s| s.to_string_lossy()).unwrap_or_default();
+    let name = match path.extension() {
+        Some(ext) => format!("{}-{}.{}", stem, label, ext.to_string_lossy()),
+        None => format!("{}-{}", stem, label),
+    };
+    path.with_file_name(name)
+}
+
+/// Return the point for rotation or mirror operation: an explicit point, the
+/// position of an atom, or the center of `atoms` by default.
+fn pivot_point(mol: &Molecule, atoms: &[usize], point: Option<&str>, atom: Option<usize>) -> Result<vecfx::Vector3f> {
+    match (point, atom) {
+        (Some(p), _) => crate::transform::parse_vector(p),
+        (None, Some(n)) => crate::geometry::atom_position(mol, n),
+        (None, None) => crate::transform::center_of(mol, atoms),
+    }
+}
+
 fn select_atoms_by_fz<F>(mol: &Molecule, selection: &str, cmp: F) -> Result<Vec<usize>>
+where
+    F: Fn(f64, f64) -> bool,
+{
+    let fz_ = selection.parse().map_err(|_| format_err!("parse fz value failure: {}", selection))?;
+    atoms_by_fz(mol, fz_, cmp)
+}
+
+fn atoms_by_fz<F>(mol: &Molecule, fz_: f64, cmp: F) -> Result<Vec<usize>>
 where
     F: Fn(f64, f64) -> bool,
 {
     let frac_coords: Option<Vec<_>> = mol.get_scaled_positions().map(|x| x.collect_vec());
     if let Some(frac_coords) = frac_coords {
-        match selection.parse() {
-            Ok(fz_) => {
-                let selected = mol
-                    .numbers()
-                    .zip(frac_coords)
-                    .filter_map(|(n, [_fx, _fy, fz])| if cmp(fz, fz_) { Some(n) } else { None })
-                    .collect_vec();
-                Ok(selected)
-            }
-            Err(e) => {
-                bail!("parse fz value failure: {}", selection);
-            }
-        }
+        let selected = mol
+            .numbers()
+            .zip(frac_coords)
+            .filter_map(|(n, [_fx, _fy, fz])| if cmp(fz, fz_) { Some(n) } else { None })
+            .collect_vec();
+        Ok(selected)
     } else {
         bail!("not a periodic system!");
     }
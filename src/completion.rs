@@ -0,0 +1,271 @@
+// [[file:../gosh.note::315683af][315683af]]
+use super::*;
+use crate::cli::GoshCmd;
+
+use clap::{Arg, Command, CommandFactory, ValueHint};
+use rustyline::completion::{Completer, FilenameCompleter, Pair};
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Context, Helper};
+// 315683af ends here
+
+// [[file:../gosh.note::ea5be7a1][ea5be7a1]]
+/// File extensions of template files for `format` command.
+const TEMPLATE_EXTENSIONS: &[&str] = &[".hbs", ".tera", ".jinja", ".j2"];
+
+/// File extensions of molecule formats supported by gchemol backends.
+fn format_extensions() -> Vec<&'static str> {
+    crate::cli::BACKEND_FORMATS
+        .iter()
+        .flat_map(|(_, extensions)| extensions.iter().copied())
+        .unique()
+        .sorted()
+        .collect()
+}
+
+/// Element symbols in periodic table.
+const ELEMENT_SYMBOLS: &[&str] = &[
+    "H", "He", "Li", "Be", "B", "C", "N", "O", "F", "Ne", "Na", "Mg", "Al", "Si", "P", "S", "Cl", "Ar", "K", "Ca", "Sc",
+    "Ti", "V", "Cr", "Mn", "Fe", "Co", "Ni", "Cu", "Zn", "Ga", "Ge", "As", "Se", "Br", "Kr", "Rb", "Sr", "Y", "Zr",
+    "Nb", "Mo", "Tc", "Ru", "Rh", "Pd", "Ag", "Cd", "In", "Sn", "Sb", "Te", "I", "Xe", "Cs", "Ba", "La", "Ce", "Pr",
+    "Nd", "Pm", "Sm", "Eu", "Gd", "Tb", "Dy", "Ho", "Er", "Tm", "Yb", "Lu", "Hf", "Ta", "W", "Re", "Os", "Ir", "Pt",
+    "Au", "Hg", "Tl", "Pb", "Bi", "Po", "At", "Rn", "Fr", "Ra", "Ac", "Th", "Pa", "U", "Np", "Pu", "Am", "Cm", "Bk",
+    "Cf", "Es", "Fm", "Md", "No", "Lr", "Rf", "Db", "Sg", "Bh", "Hs", "Mt", "Ds", "Rg", "Cn", "Nh", "Fl", "Mc", "Lv",
+    "Ts", "Og",
+];
+
+/// What to complete for an argument value
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ValueKind {
+    Path,
+    Extension,
+    Element,
+    Template,
+    Nothing,
+}
+
+/// Arguments completed with other than paths, keyed by subcommand name and
+/// argument id. Path arguments are found from their `value_hint`.
+const VALUE_KINDS: &[(&str, &str, ValueKind)] = &[
+    ("convert", "format_to", ValueKind::Extension),
+    ("format", "TEMPLATE_NAME", ValueKind::Template),
+    ("add", "element", ValueKind::Element),
+    ("substitute", "spec", ValueKind::Element),
+    ("select", "selection", ValueKind::Element),
+];
+
+fn value_kind(cmd: &Command, arg: &Arg) -> ValueKind {
+    match arg.get_value_hint() {
+        ValueHint::AnyPath | ValueHint::FilePath | ValueHint::DirPath => return ValueKind::Path,
+        _ => {}
+    }
+    VALUE_KINDS
+        .iter()
+        .find(|(name, id, _)| cmd.get_name() == *name && arg.get_id() == *id)
+        .map_or(ValueKind::Nothing, |(.., kind)| *kind)
+}
+
+/// Find the argument of `cmd` for the value being typed, given the preceding
+/// `words` (excluding the subcommand name).
+fn find_arg<'a>(cmd: &'a Command, words: &[&str]) -> Option<&'a Arg> {
+    let find_option = |w: &str| {
+        cmd.get_arguments().find(|a| {
+            if let Some(long) = w.strip_prefix("--") {
+                a.get_long() == Some(long)
+            } else if let Some(short) = w.strip_prefix('-') {
+                short.len() == 1 && a.get_short() == short.chars().next()
+            } else {
+                false
+            }
+        })
+    };
+
+    // the previous word is an option expecting a value
+    if let Some(last) = words.last() {
+        if let Some(a) = find_option(last) {
+            if a.get_action().takes_values() {
+                return Some(a);
+            }
+        }
+    }
+
+    // count positional values, skipping options and their values
+    let mut npos = 0;
+    let mut iter = words.iter();
+    while let Some(w) = iter.next() {
+        if w.starts_with('-') && w.len() > 1 {
+            if let Some(a) = find_option(w) {
+                if a.get_action().takes_values() {
+                    iter.next();
+                }
+            }
+        } else {
+            npos += 1;
+        }
+    }
+    let positionals = cmd.get_positionals().collect_vec();
+    // the last positional argument may take multiple values
+    positionals.get(npos).or_else(|| positionals.last().filter(|a| a.get_num_args().map_or(false, |r| r.max_values() > 1))).copied()
+}
+
+fn candidates<'a>(words: impl IntoIterator<Item = &'a str>, prefix: &str) -> Vec<Pair> {
+    words
+        .into_iter()
+        .filter(|w| w.starts_with(prefix))
+        .map(|w| Pair {
+            display: w.to_owned(),
+            replacement: w.to_owned(),
+        })
+        .collect()
+}
+// ea5be7a1 ends here
+
+// [[file:../gosh.note::11a93989][11a93989]]
+/// Context-aware completion for gosh shell derived from clap command tree.
+pub(crate) struct GoshHelper {
+    command: Command,
+    filename_completer: FilenameCompleter,
+}
+
+impl GoshHelper {
+    pub fn new() -> Self {
+        let mut command = GoshCmd::command();
+        // make sure all argument settings are finalized
+        command.build();
+        Self {
+            command,
+            filename_completer: FilenameCompleter::new(),
+        }
+    }
+}
+
+impl Completer for GoshHelper {
+    type Candidate = Pair;
+
+    fn complete(&self, line: &str, pos: usize, ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let head = &line[..pos];
+        let start = head.rfind(|c: char| c.is_whitespace()).map_or(0, |i| i + 1);
+        let prefix = &head[start..];
+        let words = head[..start].split_whitespace().collect_vec();
+
+        // complete subcommand names and aliases
+        if words.is_empty() {
+            let names = self
+                .command
+                .get_subcommands()
+                .flat_map(|c| std::iter::once(c.get_name()).chain(c.get_all_aliases()))
+                .sorted();
+            return Ok((start, candidates(names, prefix)));
+        }
+
+        let cmd = match self.command.find_subcommand(words[0]) {
+            Some(cmd) => cmd,
+            None => return Ok((start, vec![])),
+        };
+
+        // complete option names
+        if prefix.starts_with('-') {
+            let options = cmd
+                .get_arguments()
+                .filter_map(|a| a.get_long())
+                .map(|long| format!("--{}", long))
+                .sorted()
+                .collect_vec();
+            return Ok((start, candidates(options.iter().map(|s| s.as_str()), prefix)));
+        }
+
+        match find_arg(cmd, &words[1..]).map(|a| value_kind(cmd, a)) {
+            Some(ValueKind::Path) => self.filename_completer.complete(line, pos, ctx),
+            Some(ValueKind::Extension) => Ok((start, candidates(format_extensions(), prefix))),
+            Some(ValueKind::Element) => {
+                // complete the last element in expression such as "Pt->Au"
+                // or "Pt,Au"
+                let i = prefix.rfind(|c| c == '>' || c == ',').map_or(0, |i| i + 1);
+                Ok((start + i, candidates(ELEMENT_SYMBOLS.iter().copied(), &prefix[i..])))
+            }
+            Some(ValueKind::Template) => {
+                // only directories and template files
+                let (start, mut pairs) = self.filename_completer.complete(line, pos, ctx)?;
+                pairs.retain(|p| p.replacement.ends_with(std::path::MAIN_SEPARATOR) || TEMPLATE_EXTENSIONS.iter().any(|ext| p.replacement.ends_with(ext)));
+                Ok((start, pairs))
+            }
+            _ => Ok((start, vec![])),
+        }
+    }
+}
+
+impl Hinter for GoshHelper {
+    type Hint = String;
+}
+
+impl Highlighter for GoshHelper {}
+
+impl Validator for GoshHelper {}
+
+impl Helper for GoshHelper {}
+// 11a93989 ends here
+
+// [[file:../gosh.note::7c2e94b1][7c2e94b1]]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn arg_id<'a>(cmd: &'a Command, words: &[&str]) -> Option<&'a str> {
+        find_arg(cmd, words).map(|a| a.get_id().as_str())
+    }
+
+    #[test]
+    fn test_find_arg() {
+        let mut app = GoshCmd::command();
+        app.build();
+
+        let load = app.find_subcommand("load").unwrap();
+        assert_eq!(arg_id(load, &[]), Some("MOLECULE-NAME"));
+
+        // option values
+        let convert = app.find_subcommand("convert").unwrap();
+        assert_eq!(arg_id(convert, &["-e"]), Some("format_to"));
+        assert_eq!(arg_id(convert, &["a.cif", "-e"]), Some("format_to"));
+        // multiple positional values
+        assert_eq!(arg_id(convert, &[]), Some("files"));
+        assert_eq!(arg_id(convert, &["-e", ".xyz", "a.cif", "b.cif"]), Some("files"));
+
+        let format = app.find_subcommand("format").unwrap();
+        assert_eq!(find_arg(format, &[]).map(|a| value_kind(format, a)), Some(ValueKind::Template));
+        assert_eq!(arg_id(format, &["a.hbs", "-o"]), Some("OUTPUT_FILE_NAME"));
+        // no more positional argument
+        assert_eq!(arg_id(format, &["a.hbs"]), None);
+
+        let substitute = app.find_subcommand("substitute").unwrap();
+        assert_eq!(find_arg(substitute, &[]).map(|a| value_kind(substitute, a)), Some(ValueKind::Element));
+        assert_eq!(find_arg(convert, &["-e"]).map(|a| value_kind(convert, a)), Some(ValueKind::Extension));
+        assert_eq!(find_arg(convert, &[]).map(|a| value_kind(convert, a)), Some(ValueKind::Path));
+        let select = app.find_subcommand("select").unwrap();
+        assert_eq!(find_arg(select, &[]).map(|a| value_kind(select, a)), Some(ValueKind::Element));
+    }
+
+    #[test]
+    fn test_value_kinds() {
+        let mut app = GoshCmd::command();
+        app.build();
+
+        // make sure the table is in sync with the command definitions
+        for (name, id, _) in VALUE_KINDS {
+            let cmd = app.find_subcommand(name).unwrap();
+            assert!(cmd.get_arguments().any(|a| a.get_id() == *id), "{} has no argument {}", name, id);
+        }
+    }
+
+    #[test]
+    fn test_format_extensions() {
+        let extensions = format_extensions();
+        assert!(extensions.contains(&".xyz"));
+        assert!(extensions.contains(&".poscar"));
+        assert!(extensions.iter().all(|x| x.starts_with('.')));
+        // an extension maps to only one backend format
+        let n = crate::cli::BACKEND_FORMATS.iter().map(|(_, x)| x.len()).sum::<usize>();
+        assert_eq!(extensions.len(), n);
+    }
+}
+// 7c2e94b1 ends here
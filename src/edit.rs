@@ -0,0 +1,317 @@
+// [[file:../gosh.note::4d9a8f9f][4d9a8f9f]]
+use super::*;
+
+use crate::reorder::PERMUTATION_KEY;
+use crate::smiles::SMILES_KEY;
+use crate::symmetry::{POINT_GROUP_KEY, SPACE_GROUP_KEY};
+
+use gchemol::{Atom, Molecule};
+use rand::prelude::*;
+use std::collections::{HashMap, HashSet};
+use vecfx::*;
+// 4d9a8f9f ends here
+
+// [[file:../gosh.note::366dbb2b][366dbb2b]]
+/// Keys of molecule properties depending on atom indices or derived from
+/// the structure, which are dropped once atoms are reordered or edited.
+const DERIVED_KEYS: &[&str] = &[PERMUTATION_KEY, SMILES_KEY, POINT_GROUP_KEY, SPACE_GROUP_KEY];
+
+/// Remove properties of `mol` that are no longer valid after changing its
+/// composition or structure, including the energy.
+pub(crate) fn discard_stale_properties(mol: &mut Molecule) {
+    for key in DERIVED_KEYS.iter().chain(&["energy"]) {
+        mol.properties.discard(key);
+    }
+}
+
+/// Return a new molecule with atoms in `order` (serial numbers),
+/// renumbered consecutively from 1. Bonds between the atoms in `order` are
+/// carried over. The title, lattice and properties are kept, except those
+/// depending on atom indices such as the permutation and SMILES.
+pub(crate) fn reordered(mol: &Molecule, order: &[usize]) -> Result<Molecule> {
+    let atoms: Vec<_> = order
+        .iter()
+        .map(|&i| mol.get_atom(i).cloned().ok_or_else(|| format_err!("no such atom: {}", i)))
+        .collect::<Result<_>>()?;
+    let mut new = Molecule::from_atoms(atoms);
+    new.set_title(&mol.title());
+    new.lattice = mol.lattice.clone();
+    new.properties = mol.properties.clone();
+    for key in DERIVED_KEYS {
+        new.properties.discard(key);
+    }
+
+    let mapping: HashMap<usize, usize> = order.iter().enumerate().map(|(k, &i)| (i, k + 1)).collect();
+    for (i, j, bond) in mol.bonds() {
+        if let (Some(&i), Some(&j)) = (mapping.get(&i), mapping.get(&j)) {
+            new.add_bond(i, j, bond.clone());
+        }
+    }
+    Ok(new)
+}
+
+/// Renumber atoms in `mol` consecutively from 1, keeping existing bonds.
+/// The title, lattice and properties are kept.
+pub(crate) fn renumbered(mol: &Molecule) -> Molecule {
+    let order = mol.numbers().collect_vec();
+    reordered(mol, &order).expect("renumber atoms")
+}
+
+/// Remove `atoms` from `mol`. Bonds between remaining atoms are kept.
+pub(crate) fn delete_atoms(mol: &mut Molecule, atoms: &[usize]) -> Result<()> {
+    if let Some(n) = atoms.iter().find(|&&n| mol.get_atom(n).is_none()) {
+        bail!("no such atom: {}", n);
+    }
+    let removed: HashSet<usize> = atoms.iter().copied().collect();
+    let order = mol.numbers().filter(|n| !removed.contains(n)).collect_vec();
+    *mol = reordered(mol, &order)?;
+    discard_stale_properties(mol);
+
+    Ok(())
+}
+
+/// Add a new atom of `element` at `position`, which is in fractional
+/// coordinates if `frac` is true. Bonds are rebuilt afterwards.
+pub(crate) fn add_atom(mol: &mut Molecule, element: &str, position: [f64; 3], frac: bool) -> Result<()> {
+    let position = if frac {
+        let lat = mol.lattice.as_ref().ok_or_else(|| format_err!("not a periodic system!"))?;
+        lat.to_cart(position).into()
+    } else {
+        position
+    };
+    let n = mol.numbers().max().unwrap_or(0) + 1;
+    mol.add_atom(n, Atom::new(element, position));
+    mol.rebond();
+    discard_stale_properties(mol);
+
+    Ok(())
+}
+// 366dbb2b ends here
+
+// [[file:../gosh.note::7d4fe3eb][7d4fe3eb]]
+/// Parse a fraction from string such as "25%" or "0.25".
+pub(crate) fn parse_fraction(s: &str) -> Result<f64> {
+    let x = if let Some(p) = s.strip_suffix('%') {
+        p.trim().parse::<f64>().map(|x| x / 100.0)
+    } else {
+        s.trim().parse::<f64>()
+    };
+    let x = x.map_err(|_| format_err!("invalid fraction: {:?}", s))?;
+    if !(0.0..=1.0).contains(&x) {
+        bail!("fraction out of range: {:?}", s);
+    }
+
+    Ok(x)
+}
+
+/// Substitute elements of `atoms` in `mol` according to `spec`.
+///
+/// `spec` is either a target element ("Au") for replacing all `atoms`, or
+/// "A->B" for replacing atoms of element A. When `fraction` is given, only a
+/// random part of the candidate atoms will be substituted. Bonds are rebuilt
+/// afterwards. Return the number of substituted atoms.
+pub(crate) fn substitute(
+    mol: &mut Molecule,
+    atoms: &[usize],
+    spec: &str,
+    fraction: Option<&str>,
+    rng: &mut StdRng,
+) -> Result<usize> {
+    let (from, to) = match spec.split_once("->") {
+        Some((a, b)) => (Some(a.trim()), b.trim()),
+        None => (None, spec.trim()),
+    };
+    if to.is_empty() {
+        bail!("invalid substitution: {:?}", spec);
+    }
+
+    let candidates = atoms
+        .iter()
+        .copied()
+        .filter(|&n| match (from, mol.get_atom(n)) {
+            (Some(from), Some(a)) => a.symbol() == from,
+            (None, Some(_)) => true,
+            _ => false,
+        })
+        .collect_vec();
+
+    let chosen = if let Some(fraction) = fraction {
+        let k = (parse_fraction(fraction)? * candidates.len() as f64).round() as usize;
+        candidates.choose_multiple(rng, k).copied().sorted().collect_vec()
+    } else {
+        candidates
+    };
+
+    for &n in &chosen {
+        mol.get_atom_mut(n).unwrap().set_symbol(to);
+    }
+    if !chosen.is_empty() {
+        mol.rebond();
+        discard_stale_properties(mol);
+    }
+
+    Ok(chosen.len())
+}
+
+/// Create random number generator from an optional seed.
+pub(crate) fn new_rng(seed: Option<u64>) -> StdRng {
+    match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    }
+}
+// 7d4fe3eb ends here
+
+// [[file:../gosh.note::f20b9c34][f20b9c34]]
+/// Merge `mols` into a single molecule, keeping the title, lattice and
+/// properties of the first one. Properties derived from the structure, such
+/// as energy and SMILES, are dropped. Molecules after the first are
+/// translated by `offsets` in order, which are in fractional coordinates of
+/// the first lattice if `frac` is true.
+pub(crate) fn merge_molecules(mols: &[Molecule], offsets: &[Vector3f], frac: bool) -> Result<Molecule> {
+    let first = mols.first().ok_or_else(|| format_err!("no molecules to merge"))?;
+    if offsets.len() > mols.len() - 1 {
+        bail!("too many offsets: {} offsets for {} molecules", offsets.len(), mols.len() - 1);
+    }
+
+    let mut atoms = first.atoms().map(|(_, a)| a.clone()).collect_vec();
+    // bonds with serial numbers in merged molecule
+    let mut bonds = vec![];
+    let mut bond_mapped = |mol: &Molecule, start: usize| {
+        let mapping: HashMap<usize, usize> = mol.numbers().enumerate().map(|(k, n)| (n, start + k + 1)).collect();
+        for (i, j, b) in mol.bonds() {
+            bonds.push((mapping[&i], mapping[&j], b.clone()));
+        }
+    };
+    bond_mapped(first, 0);
+    for (k, mol) in mols.iter().enumerate().skip(1) {
+        bond_mapped(mol, atoms.len());
+        let offset = match offsets.get(k - 1) {
+            Some(v) if frac => {
+                let lat = first.lattice.as_ref().ok_or_else(|| format_err!("not a periodic system!"))?;
+                lat.to_cart((*v).into()).into()
+            }
+            Some(v) => *v,
+            None => Vector3f::zeros(),
+        };
+        for (_, a) in mol.atoms() {
+            let mut a = a.clone();
+            let p: Vector3f = a.position().into();
+            a.set_position((p + offset).into());
+            atoms.push(a);
+        }
+    }
+
+    let mut merged = Molecule::from_atoms(atoms);
+    merged.set_title(&first.title());
+    merged.lattice = first.lattice.clone();
+    merged.properties = first.properties.clone();
+    discard_stale_properties(&mut merged);
+    for (i, j, b) in bonds {
+        merged.add_bond(i, j, b);
+    }
+
+    Ok(merged)
+}
+
+/// Return atom pairs in `mol` closer than `cutoff`, with their distances.
+pub(crate) fn find_overlaps(mol: &Molecule, cutoff: f64) -> Vec<(usize, usize, f64)> {
+    let atoms: Vec<(usize, Vector3f)> = mol.atoms().map(|(n, a)| (n, a.position().into())).collect();
+    let mut pairs = vec![];
+    for ((i, pi), (j, pj)) in atoms.iter().tuple_combinations() {
+        let d = crate::geometry::mic_vector(mol, *pi, *pj).norm();
+        if d < cutoff {
+            pairs.push((*i, *j, d));
+        }
+    }
+    pairs
+}
+// f20b9c34 ends here
+
+// [[file:../gosh.note::b4e0f7a2][b4e0f7a2]]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_molecules() -> Result<()> {
+        let mut h2 = Molecule::from_atoms([Atom::new("H", [0.0; 3]), Atom::new("H", [0.74, 0.0, 0.0])]);
+        h2.add_bond(1, 2, gchemol::Bond::single());
+        h2.properties.store("charge", &0.0)?;
+        h2.properties.store("energy", &-1.17)?;
+        let mut oh = Molecule::from_atoms([Atom::new("O", [0.0; 3]), Atom::new("H", [0.97, 0.0, 0.0])]);
+        oh.add_bond(1, 2, gchemol::Bond::single());
+        oh.properties.store("charge", &-1.0)?;
+        oh.properties.store("energy", &-75.0)?;
+
+        let merged = merge_molecules(&[h2, oh], &[Vector3f::new(0.0, 0.0, 3.0)], false)?;
+        assert_eq!(merged.natoms(), 4);
+        assert_eq!(merged.get_atom(3).unwrap().position(), [0.0, 0.0, 3.0]);
+        // bonds are kept as is
+        assert_eq!(merged.nbonds(), 2);
+        assert!(merged.get_bond(1, 2).is_some());
+        assert!(merged.get_bond(3, 4).is_some());
+        // only properties of the first molecule are kept, except the energy
+        assert_eq!(merged.properties.load::<f64>("charge")?, 0.0);
+        assert!(merged.properties.load::<f64>("energy").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_edit_atoms() -> Result<()> {
+        // water with derived properties
+        let mut mol = Molecule::from_atoms([
+            Atom::new("O", [0.0, 0.0, 0.0]),
+            Atom::new("H", [0.96, 0.0, 0.0]),
+            Atom::new("H", [-0.24, 0.93, 0.0]),
+        ]);
+        mol.rebond();
+        mol.properties.store("charge", &0.0)?;
+        mol.properties.store("energy", &-76.0)?;
+        mol.properties.store(SMILES_KEY, &"O")?;
+        mol.properties.store(POINT_GROUP_KEY, &"C2v")?;
+        mol.properties.store(PERMUTATION_KEY, &[2, 1, 3])?;
+
+        // reordering keeps the energy, but not index-dependent properties
+        let new = reordered(&mol, &[3, 1, 2])?;
+        assert_eq!(new.get_atom(2).unwrap().symbol(), "O");
+        assert!(new.get_bond(1, 2).is_some());
+        assert!(new.get_bond(2, 3).is_some());
+        assert_eq!(new.properties.load::<f64>("energy")?, -76.0);
+        assert!(new.properties.load::<String>(SMILES_KEY).is_err());
+        assert!(new.properties.load::<String>(POINT_GROUP_KEY).is_err());
+        assert!(new.properties.load::<Vec<usize>>(PERMUTATION_KEY).is_err());
+
+        let mut new = mol.clone();
+        delete_atoms(&mut new, &[2])?;
+        assert_eq!(new.natoms(), 2);
+        assert_eq!(new.nbonds(), 1);
+        assert!(new.properties.load::<f64>("energy").is_err());
+        assert_eq!(new.properties.load::<f64>("charge")?, 0.0);
+        assert!(delete_atoms(&mut new, &[5]).is_err());
+
+        // the new atom is bonded
+        let mut new = mol.clone();
+        add_atom(&mut new, "H", [0.0, 0.0, 1.0], false)?;
+        assert_eq!(new.natoms(), 4);
+        assert!(new.get_bond(1, 4).is_some());
+        assert!(new.properties.load::<f64>("energy").is_err());
+        assert!(add_atom(&mut new, "H", [0.5; 3], true).is_err());
+
+        // too long for O-H bonds, but fine for O-F bonds
+        let mut new = mol.clone();
+        new.get_atom_mut(2).unwrap().set_position([1.6, 0.0, 0.0]);
+        new.get_atom_mut(3).unwrap().set_position([-0.4, 1.549, 0.0]);
+        new.rebond();
+        assert_eq!(new.nbonds(), 0);
+        let n = substitute(&mut new, &[1, 2, 3], "H->F", None, &mut new_rng(Some(1)))?;
+        assert_eq!(n, 2);
+        assert_eq!(new.atoms().filter(|(_, a)| a.symbol() == "F").count(), 2);
+        assert_eq!(new.nbonds(), 2);
+        assert!(new.properties.load::<String>(SMILES_KEY).is_err());
+
+        Ok(())
+    }
+}
+// b4e0f7a2 ends here
@@ -0,0 +1,147 @@
+// [[file:../gosh.note::0d4b7f62][0d4b7f62]]
+use super::*;
+use crate::info::get_energy;
+
+use gchemol::Molecule;
+// 0d4b7f62 ends here
+
+// [[file:../gosh.note::93c2a5e8][93c2a5e8]]
+/// Parse frame slice such as "10:100:5" into frame indices (starting from
+/// 0) for `nframes` frames. The start and end are 1-based and inclusive,
+/// and can be omitted or negative to count from the last frame.
+pub(crate) fn parse_slice(s: &str, nframes: usize) -> Result<Vec<usize>> {
+    let parts: Vec<&str> = s.split(':').collect();
+    if parts.len() > 3 {
+        bail!("invalid slice: {:?}", s);
+    }
+    let n = nframes as i64;
+    let parse = |x: Option<&&str>, default: i64| -> Result<i64> {
+        match x.map(|x| x.trim()).filter(|x| !x.is_empty()) {
+            Some(x) => {
+                let i: i64 = x.parse().map_err(|_| format_err!("invalid slice: {:?}", s))?;
+                if i == 0 {
+                    bail!("frame index starts from 1: {:?}", s);
+                }
+                Ok(if i < 0 { n + i + 1 } else { i })
+            }
+            None => Ok(default),
+        }
+    };
+    let start = parse(parts.get(0), 1)?.max(1);
+    let end = parse(parts.get(1), n)?.min(n);
+    let step = parse(parts.get(2), 1)?;
+    if step <= 0 {
+        bail!("invalid slice step: {:?}", s);
+    }
+
+    let mut indices = vec![];
+    let mut i = start;
+    while i <= end {
+        indices.push((i - 1) as usize);
+        i += step;
+    }
+    Ok(indices)
+}
+
+/// Parse frame selection such as "1,5-9" into frame indices (starting from
+/// 0) for `nframes` frames.
+pub(crate) fn parse_frames(s: &str, nframes: usize) -> Result<Vec<usize>> {
+    let selected = gut::utils::parse_numbers_human_readable(s)?;
+    for &i in selected.iter() {
+        if i == 0 || i > nframes {
+            bail!("frame {} out of range: {} frames available", i, nframes);
+        }
+    }
+    Ok(selected.into_iter().map(|i| i - 1).collect())
+}
+
+/// Print a table of `mols` with frame index, formula, number of atoms, and
+/// energy or title.
+pub(crate) fn print_frames(mols: &[Molecule]) {
+    println!("{:>6} {:>20} {:>8}  {}", "#", "formula", "natoms", "energy/title");
+    for (i, mol) in mols.iter().enumerate() {
+        let label = match get_energy(mol) {
+            Some(e) => format!("{:-16.6}", e),
+            None => mol.title(),
+        };
+        println!("{:>6} {:>20} {:>8}  {}", i + 1, mol.formula(), mol.natoms(), label);
+    }
+}
+
+/// The keys available for sorting frames.
+pub(crate) const FRAME_SORT_KEYS: &[&str] = &["energy", "natoms"];
+
+/// Sort frames `mols` by `key`. Frames without energy are put at the end
+/// when sorting by energy.
+pub(crate) fn sort_frames(mols: &mut Vec<Molecule>, key: &str, reverse: bool) -> Result<()> {
+    let values: Vec<f64> = match key {
+        "energy" => mols.iter().map(|m| get_energy(m).unwrap_or(f64::NAN)).collect(),
+        "natoms" => mols.iter().map(|m| m.natoms() as f64).collect(),
+        _ => bail!("invalid sort key: {:?}. Possible values: {}", key, FRAME_SORT_KEYS.join(", ")),
+    };
+    if key == "energy" && values.iter().any(|x| x.is_nan()) {
+        eprintln!("{} frames have no energy.", values.iter().filter(|x| x.is_nan()).count());
+    }
+
+    let mut indices = (0..mols.len()).collect_vec();
+    indices.sort_by(|&i, &j| {
+        let (x, y) = (values[i], values[j]);
+        match (x.is_nan(), y.is_nan()) {
+            (false, false) if reverse => y.partial_cmp(&x).unwrap(),
+            (false, false) => x.partial_cmp(&y).unwrap(),
+            (a, b) => a.cmp(&b),
+        }
+    });
+    let old = std::mem::take(mols);
+    let mut old: Vec<Option<Molecule>> = old.into_iter().map(Some).collect();
+    *mols = indices.into_iter().map(|i| old[i].take().unwrap()).collect();
+
+    Ok(())
+}
+// 93c2a5e8 ends here
+
+// [[file:../gosh.note::e2c95b07][e2c95b07]]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_slice() -> Result<()> {
+        assert_eq!(parse_slice("10:100:5", 30)?, [9, 14, 19, 24, 29]);
+        assert_eq!(parse_slice(":", 3)?, [0, 1, 2]);
+        assert_eq!(parse_slice("::2", 5)?, [0, 2, 4]);
+        assert_eq!(parse_slice("3", 5)?, [2, 3, 4]);
+        // negative index counts from the last frame
+        assert_eq!(parse_slice("-2:", 5)?, [3, 4]);
+        assert_eq!(parse_slice("1:-2", 5)?, [0, 1, 2, 3]);
+        assert!(parse_slice("4:2", 5)?.is_empty());
+
+        assert!(parse_slice("0:3", 5).is_err());
+        assert!(parse_slice("1:3:0", 5).is_err());
+        assert!(parse_slice("1:3:-1", 5).is_err());
+        assert!(parse_slice("a:3", 5).is_err());
+        assert!(parse_slice("1:2:3:4", 5).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_frames() -> Result<()> {
+        assert_eq!(parse_frames("1,3-4", 5)?, [0, 2, 3]);
+        assert_eq!(parse_frames("5", 5)?, [4]);
+        assert!(parse_frames("6", 5).is_err());
+        assert!(parse_frames("0", 5).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_sort_frames() -> Result<()> {
+        let mut mols = ["-1.0", "no energy", "-3.0", "-2.0"].iter().map(|&t| Molecule::new(t)).collect_vec();
+        sort_frames(&mut mols, "energy", false)?;
+        assert_eq!(mols.iter().map(|m| m.title()).collect_vec(), ["-3.0", "-2.0", "-1.0", "no energy"]);
+        sort_frames(&mut mols, "energy", true)?;
+        assert_eq!(mols.iter().map(|m| m.title()).collect_vec(), ["-1.0", "-2.0", "-3.0", "no energy"]);
+        assert!(sort_frames(&mut mols, "title", false).is_err());
+        Ok(())
+    }
+}
+// e2c95b07 ends here
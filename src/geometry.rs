@@ -0,0 +1,284 @@
+// [[file:../gosh.note::b9bac0c2][b9bac0c2]]
+use super::*;
+
+use gchemol::Molecule;
+use vecfx::nalgebra::{Rotation3, Unit};
+use vecfx::*;
+
+use std::collections::HashSet;
+// b9bac0c2 ends here
+
+// [[file:../gosh.note::60b68c79][60b68c79]]
+/// Return the cartesian position of atom `n` in `mol`.
+pub(crate) fn atom_position(mol: &Molecule, n: usize) -> Result<Vector3f> {
+    let a = mol.get_atom(n).ok_or_else(|| format_err!("no such atom: {}", n))?;
+    Ok(a.position().into())
+}
+
+/// Return the vector pointing from `pi` to `pj`. For periodic system, the
+/// minimum image convention will be applied.
+pub(crate) fn mic_vector(mol: &Molecule, pi: Vector3f, pj: Vector3f) -> Vector3f {
+    let d = pj - pi;
+    if let Some(lat) = &mol.lattice {
+        let f: Vector3f = lat.to_frac(d.into()).into();
+        let f = f.map(|x| x - x.round());
+        // the rounded image is not always the nearest one for skewed cells
+        let mut best: Vector3f = lat.to_cart(f.into()).into();
+        for i in -1..=1 {
+            for j in -1..=1 {
+                for k in -1..=1 {
+                    let image = f + Vector3f::new(i as f64, j as f64, k as f64);
+                    let v: Vector3f = lat.to_cart(image.into()).into();
+                    if v.norm() < best.norm() {
+                        best = v;
+                    }
+                }
+            }
+        }
+        best
+    } else {
+        d
+    }
+}
+
+/// Return the vector pointing from atom `i` to atom `j` under the minimum
+/// image convention.
+fn atom_vector(mol: &Molecule, i: usize, j: usize) -> Result<Vector3f> {
+    let pi = atom_position(mol, i)?;
+    let pj = atom_position(mol, j)?;
+    Ok(mic_vector(mol, pi, pj))
+}
+
+/// Return the distance between atom `i` and atom `j`.
+pub(crate) fn get_distance(mol: &Molecule, i: usize, j: usize) -> Result<f64> {
+    Ok(atom_vector(mol, i, j)?.norm())
+}
+
+/// Return the angle i-j-k in degrees.
+pub(crate) fn get_angle(mol: &Molecule, i: usize, j: usize, k: usize) -> Result<f64> {
+    let vji = atom_vector(mol, j, i)?;
+    let vjk = atom_vector(mol, j, k)?;
+    Ok(vji.angle(&vjk).to_degrees())
+}
+
+/// Return the dihedral angle i-j-k-l in degrees, following the IUPAC sign
+/// convention.
+pub(crate) fn get_dihedral(mol: &Molecule, i: usize, j: usize, k: usize, l: usize) -> Result<f64> {
+    let b1 = atom_vector(mol, i, j)?;
+    let b2 = atom_vector(mol, j, k)?;
+    let b3 = atom_vector(mol, k, l)?;
+    let y = b2.norm() * b1.dot(&b2.cross(&b3));
+    let x = b1.cross(&b2).dot(&b2.cross(&b3));
+    Ok(y.atan2(x).to_degrees())
+}
+// 60b68c79 ends here
+
+// [[file:../gosh.note::d4592af9][d4592af9]]
+/// Return atoms connected to atom `j` without crossing the bond between atom
+/// `i` and atom `j`. The returned fragment includes atom `j` itself.
+pub(crate) fn fragment_beyond(mol: &Molecule, i: usize, j: usize) -> Result<Vec<usize>> {
+    if mol.nbonds() == 0 {
+        bail!("no bonds found. Please run `rebond` first.");
+    }
+
+    let mut visited: HashSet<usize> = HashSet::new();
+    let mut stack = vec![j];
+    visited.insert(j);
+    while let Some(n) = stack.pop() {
+        for m in mol.connected(n) {
+            if n == j && m == i {
+                continue;
+            }
+            if visited.insert(m) {
+                stack.push(m);
+            }
+        }
+    }
+
+    if visited.contains(&i) {
+        bail!("atom {} and atom {} are in the same ring.", i, j);
+    }
+
+    Ok(visited.into_iter().sorted().collect())
+}
+
+/// Rotate atoms in `fragment` about `axis` passing through `origin` by
+/// `angle` in degrees. For periodic system, the fragment is made whole by
+/// following bonds and moved to the periodic image nearest to `origin`
+/// before a rigid rotation.
+pub(crate) fn rotate_atoms(mol: &mut Molecule, fragment: &[usize], origin: Vector3f, axis: Vector3f, angle: f64) -> Result<()> {
+    if axis.norm() < 1e-6 {
+        bail!("invalid rotation axis: {:?}", axis);
+    }
+    if mol.lattice.is_some() && !fragment.is_empty() {
+        if mol.nbonds() > 0 {
+            crate::pbc::make_whole(mol, fragment)?;
+        }
+        // shift the fragment by a lattice vector, bringing its atom closest
+        // to the origin into the nearest image
+        let positions: Vec<Vector3f> = fragment.iter().map(|&n| atom_position(mol, n)).collect::<Result<_>>()?;
+        let p = positions
+            .iter()
+            .min_by(|a, b| mic_vector(mol, origin, **a).norm().total_cmp(&mic_vector(mol, origin, **b).norm()))
+            .unwrap();
+        let shift = origin + mic_vector(mol, origin, *p) - p;
+        translate_atoms(mol, fragment, shift)?;
+    }
+
+    let rot = Rotation3::from_axis_angle(&Unit::new_normalize(axis), angle.to_radians());
+    for &n in fragment {
+        let p = atom_position(mol, n)?;
+        let p = origin + rot * (p - origin);
+        mol.get_atom_mut(n).unwrap().set_position(p.into());
+    }
+
+    Ok(())
+}
+
+/// Translate atoms in `fragment` by displacement `disp`.
+pub(crate) fn translate_atoms(mol: &mut Molecule, fragment: &[usize], disp: Vector3f) -> Result<()> {
+    for &n in fragment {
+        let p = atom_position(mol, n)? + disp;
+        mol.get_atom_mut(n).unwrap().set_position(p.into());
+    }
+
+    Ok(())
+}
+// d4592af9 ends here
+
+// [[file:../gosh.note::498e8d9c][498e8d9c]]
+/// Set the distance between atom `i` and atom `j` to `value` by moving the
+/// fragment on the side of atom `j`.
+pub(crate) fn set_distance(mol: &mut Molecule, i: usize, j: usize, value: f64) -> Result<()> {
+    if value <= 0.0 {
+        bail!("invalid distance: {}", value);
+    }
+    let v = atom_vector(mol, i, j)?;
+    let fragment = fragment_beyond(mol, i, j)?;
+    let disp = v.normalize() * (value - v.norm());
+    translate_atoms(mol, &fragment, disp)
+}
+
+/// Set the angle i-j-k to `value` in degrees by rotating the fragment on the
+/// side of atom `k`.
+pub(crate) fn set_angle(mol: &mut Molecule, i: usize, j: usize, k: usize, value: f64) -> Result<()> {
+    let vji = atom_vector(mol, j, i)?;
+    let vjk = atom_vector(mol, j, k)?;
+    let mut axis = vji.cross(&vjk);
+    // linear arrangement: pick any direction perpendicular to the bond
+    if axis.norm() < 1e-6 {
+        axis = vji.cross(&Vector3f::x());
+        if axis.norm() < 1e-6 {
+            axis = vji.cross(&Vector3f::y());
+        }
+    }
+    let delta = value - get_angle(mol, i, j, k)?;
+    let fragment = fragment_beyond(mol, j, k)?;
+    let origin = atom_position(mol, j)?;
+    rotate_atoms(mol, &fragment, origin, axis, delta)
+}
+
+/// Set the dihedral angle i-j-k-l to `value` in degrees by rotating the
+/// fragment on the side of atom `k` about the j-k bond.
+pub(crate) fn set_dihedral(mol: &mut Molecule, i: usize, j: usize, k: usize, l: usize, value: f64) -> Result<()> {
+    let axis = atom_vector(mol, j, k)?;
+    let delta = value - get_dihedral(mol, i, j, k, l)?;
+    let fragment = fragment_beyond(mol, j, k)?;
+    if fragment.contains(&i) {
+        bail!("atom {} should not be on the side of atom {}", i, k);
+    }
+    let origin = atom_position(mol, j)?;
+    rotate_atoms(mol, &fragment, origin, axis, delta)
+}
+// 498e8d9c ends here
+
+// [[file:../gosh.note::f3a8c21d][f3a8c21d]]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gchemol::{Atom, Bond, Lattice};
+
+    // H-O-O-H with bonds
+    fn hooh() -> Molecule {
+        let mut mol = Molecule::from_atoms([
+            Atom::new("H", [-0.3, 0.9, 0.0]),
+            Atom::new("O", [0.0, 0.0, 0.0]),
+            Atom::new("O", [1.45, 0.0, 0.0]),
+            Atom::new("H", [1.75, 0.3, 0.85]),
+        ]);
+        mol.add_bond(1, 2, Bond::single());
+        mol.add_bond(2, 3, Bond::single());
+        mol.add_bond(3, 4, Bond::single());
+        mol
+    }
+
+    fn positions(mol: &Molecule, atoms: &[usize]) -> Vec<[f64; 3]> {
+        atoms.iter().map(|&n| mol.get_atom(n).unwrap().position()).collect()
+    }
+
+    #[test]
+    fn test_fragment_beyond() -> Result<()> {
+        let mol = hooh();
+        assert_eq!(fragment_beyond(&mol, 2, 3)?, [3, 4]);
+        assert_eq!(fragment_beyond(&mol, 3, 2)?, [1, 2]);
+        let mut ring = mol.clone();
+        ring.add_bond(1, 4, Bond::single());
+        assert!(fragment_beyond(&ring, 2, 3).is_err());
+        assert!(fragment_beyond(&Molecule::from_atoms(mol.atoms().map(|(_, a)| a.clone())), 2, 3).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_internal_coordinates() -> Result<()> {
+        let mut mol = hooh();
+        let d34 = get_distance(&mol, 3, 4)?;
+
+        set_distance(&mut mol, 2, 3, 1.5)?;
+        assert_relative_eq!(get_distance(&mol, 2, 3)?, 1.5, epsilon = 1e-8);
+        assert_eq!(positions(&mol, &[1, 2]), positions(&hooh(), &[1, 2]));
+        assert_relative_eq!(get_distance(&mol, 3, 4)?, d34, epsilon = 1e-8);
+
+        let fixed = positions(&mol, &[1, 2]);
+        set_angle(&mut mol, 1, 2, 3, 120.0)?;
+        assert_relative_eq!(get_angle(&mol, 1, 2, 3)?, 120.0, epsilon = 1e-6);
+        assert_eq!(positions(&mol, &[1, 2]), fixed);
+        assert_relative_eq!(get_distance(&mol, 2, 3)?, 1.5, epsilon = 1e-8);
+        assert_relative_eq!(get_distance(&mol, 3, 4)?, d34, epsilon = 1e-8);
+
+        let fixed = positions(&mol, &[1, 2]);
+        let a234 = get_angle(&mol, 2, 3, 4)?;
+        set_dihedral(&mut mol, 1, 2, 3, 4, -90.0)?;
+        assert_relative_eq!(get_dihedral(&mol, 1, 2, 3, 4)?, -90.0, epsilon = 1e-6);
+        assert_eq!(positions(&mol, &[1, 2]), fixed);
+        assert_relative_eq!(get_angle(&mol, 1, 2, 3)?, 120.0, epsilon = 1e-6);
+        assert_relative_eq!(get_angle(&mol, 2, 3, 4)?, a234, epsilon = 1e-6);
+
+        assert!(set_distance(&mut mol, 2, 3, 0.0).is_err());
+        // atom 1 is on the side of atom 2
+        assert!(set_dihedral(&mut mol, 1, 3, 2, 4, 0.0).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rotate_periodic() -> Result<()> {
+        // a molecule split across the cell boundary along x
+        let mut mol = Molecule::from_atoms([Atom::new("O", [9.5, 5.0, 5.0]), Atom::new("O", [0.7, 5.0, 5.0])]);
+        mol.set_lattice(Lattice::new([[10.0, 0.0, 0.0], [0.0, 10.0, 0.0], [0.0, 0.0, 10.0]]));
+        mol.add_bond(1, 2, Bond::double());
+        let d = get_distance(&mol, 1, 2)?;
+        assert_relative_eq!(d, 1.2, epsilon = 1e-8);
+
+        rotate_atoms(&mut mol, &[1, 2], Vector3f::new(0.1, 5.0, 5.0), Vector3f::z(), 90.0)?;
+        let [p1, p2] = [1, 2].map(|n| atom_position(&mol, n).unwrap());
+        // the raw distance stays unchanged after rigid rotation
+        assert_relative_eq!((p2 - p1).norm(), d, epsilon = 1e-8);
+        assert_relative_eq!(p1.x, 0.1, epsilon = 1e-8);
+        assert_relative_eq!(p2.x, 0.1, epsilon = 1e-8);
+        assert_relative_eq!(p1.y, 4.4, epsilon = 1e-8);
+        assert_relative_eq!(p2.y, 5.6, epsilon = 1e-8);
+
+        Ok(())
+    }
+}
+// f3a8c21d ends here
@@ -0,0 +1,146 @@
+// [[file:../gosh.note::5f582421][5f582421]]
+use super::*;
+
+use gchemol::Molecule;
+// 5f582421 ends here
+
+// [[file:../gosh.note::5f462444][5f462444]]
+/// Conversion factor from amu/Å^3 to g/cm^3
+const AMU_PER_A3_TO_G_PER_CM3: f64 = 1.66053907;
+
+/// Return the density of periodic `mol` in g/cm^3.
+pub(crate) fn get_density(mol: &Molecule) -> Option<f64> {
+    let lat = mol.lattice.as_ref()?;
+    let mass: f64 = mol.masses().sum();
+    Some(mass * AMU_PER_A3_TO_G_PER_CM3 / lat.volume())
+}
+
+/// Print a summary of `mol`.
+pub(crate) fn print_summary(mol: &Molecule) {
+    println!("title: {}", mol.title());
+    println!("formula: {}", mol.formula());
+    println!("number of atoms: {}", mol.natoms());
+    match mol.properties.load::<f64>("charge") {
+        Ok(charge) => println!("charge: {}", charge),
+        Err(_) => println!("charge: n/a"),
+    }
+    if let Some(lat) = &mol.lattice {
+        let [a, b, c] = lat.lengths();
+        let [alpha, beta, gamma] = lat.angles();
+        let volume = lat.volume();
+        println!("lattice a, b, c: {:-10.4} {:-10.4} {:-10.4}", a, b, c);
+        println!("lattice α, β, γ: {:-10.4} {:-10.4} {:-10.4}", alpha, beta, gamma);
+        println!("volume: {:-10.4} Å^3", volume);
+        println!("density: {:-10.4} g/cm^3", get_density(mol).unwrap());
+    }
+    let [x, y, z] = mol.center_of_mass();
+    println!("center of mass: {:-10.4} {:-10.4} {:-10.4}", x, y, z);
+    let nfrozen = mol.atoms().filter(|(_, a)| a.freezing().iter().any(|&f| f)).count();
+    println!("frozen atoms: {}", nfrozen);
+    println!("number of bonds: {}", mol.nbonds());
+    if let Ok(smiles) = mol.properties.load::<String>(crate::smiles::SMILES_KEY) {
+        println!("smiles: {}", smiles);
+    }
+}
+
+/// Print a table of coordinates and freezing flags for `atoms` in `mol`.
+pub(crate) fn print_atoms(mol: &Molecule, atoms: &[usize]) -> Result<()> {
+    let periodic = mol.lattice.is_some();
+    if periodic {
+        println!(
+            "{:>6} {:>4} {:>12} {:>12} {:>12} {:>10} {:>10} {:>10} {:>6}",
+            "#", "sym", "x", "y", "z", "fx", "fy", "fz", "frozen"
+        );
+    } else {
+        println!("{:>6} {:>4} {:>12} {:>12} {:>12} {:>6}", "#", "sym", "x", "y", "z", "frozen");
+    }
+    for &n in atoms {
+        let a = mol.get_atom(n).ok_or_else(|| format_err!("no such atom: {}", n))?;
+        let [x, y, z] = a.position();
+        // show frozen axes, e.g.: "xyz" or "--z"
+        let frozen: String = a
+            .freezing()
+            .iter()
+            .zip(['x', 'y', 'z'])
+            .map(|(&f, c)| if f { c } else { '-' })
+            .collect();
+        if let Some(lat) = &mol.lattice {
+            let f: [f64; 3] = lat.to_frac([x, y, z]).into();
+            println!(
+                "{:>6} {:>4} {:-12.5} {:-12.5} {:-12.5} {:-10.5} {:-10.5} {:-10.5} {:>6}",
+                n,
+                a.symbol(),
+                x,
+                y,
+                z,
+                f[0],
+                f[1],
+                f[2],
+                frozen
+            );
+        } else {
+            println!("{:>6} {:>4} {:-12.5} {:-12.5} {:-12.5} {:>6}", n, a.symbol(), x, y, z, frozen);
+        }
+    }
+
+    Ok(())
+}
+// 5f462444 ends here
+
+// [[file:../gosh.note::a83e61c9][a83e61c9]]
+/// Return the energy of `mol` from molecule properties, or from its title
+/// such as "energy = -123.4567" (written by `compute` command) or a plain
+/// number.
+pub(crate) fn get_energy(mol: &Molecule) -> Option<f64> {
+    if let Ok(e) = mol.properties.load::<f64>("energy") {
+        return Some(e);
+    }
+    let title = mol.title();
+    if let Ok(e) = title.trim().parse() {
+        return Some(e);
+    }
+    let title = title.to_lowercase();
+    let i = title.find("energy")?;
+    let rest = title[i + "energy".len()..].trim_start_matches(|c: char| c.is_whitespace() || c == '=' || c == ':');
+    rest.split_whitespace().next()?.parse().ok()
+}
+// a83e61c9 ends here
+
+// [[file:../gosh.note::2c7d90e5][2c7d90e5]]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gchemol::{Atom, Lattice};
+
+    #[test]
+    fn test_get_energy() {
+        let mut mol = Molecule::from_atoms([Atom::new("H", [0.0; 3])]);
+        mol.set_title("energy = -123.4567");
+        assert_eq!(get_energy(&mol), Some(-123.4567));
+        mol.set_title("-1.5");
+        assert_eq!(get_energy(&mol), Some(-1.5));
+        mol.set_title("H atom");
+        assert_eq!(get_energy(&mol), None);
+        // properties take precedence over title
+        mol.set_title("Energy: -1.5 eV");
+        assert_eq!(get_energy(&mol), Some(-1.5));
+        mol.properties.store("energy", -2.0).unwrap();
+        assert_eq!(get_energy(&mol), Some(-2.0));
+    }
+
+    #[test]
+    fn test_get_density() {
+        // rock salt NaCl: 4 formula units in a cubic cell of 5.64 Å
+        let mut atoms = vec![];
+        for [x, y, z] in [[0.0, 0.0, 0.0], [0.5, 0.5, 0.0], [0.5, 0.0, 0.5], [0.0, 0.5, 0.5]] {
+            atoms.push(Atom::new("Na", [x * 5.64, y * 5.64, z * 5.64]));
+            atoms.push(Atom::new("Cl", [(x + 0.5) * 5.64, y * 5.64, z * 5.64]));
+        }
+        let mut mol = Molecule::from_atoms(atoms);
+        assert_eq!(get_density(&mol), None);
+        mol.set_lattice(Lattice::new([[5.64, 0.0, 0.0], [0.0, 5.64, 0.0], [0.0, 0.0, 5.64]]));
+        let density = get_density(&mol).unwrap();
+        assert_relative_eq!(density, 2.164, epsilon = 1e-3);
+    }
+}
+// 2c7d90e5 ends here
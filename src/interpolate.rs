@@ -0,0 +1,288 @@
+// [[file:../gosh.note::db44a846][db44a846]]
+use super::*;
+use crate::geometry::mic_vector;
+
+use gchemol::Molecule;
+use vecfx::*;
+// db44a846 ends here
+
+// [[file:../gosh.note::580899a1][580899a1]]
+/// Generate `nimages` intermediate images between `initial` and `final_`
+/// by linear interpolation in cartesian coordinates. For periodic system,
+/// the displacements follow the minimum image convention. The returned
+/// images include the two end points, which keep the properties of
+/// `initial` and `final_`. Properties of intermediate images are cleared.
+pub(crate) fn interpolate_linear(initial: &Molecule, final_: &Molecule, nimages: usize) -> Result<Vec<Molecule>> {
+    if initial.natoms() != final_.natoms() {
+        bail!("different number of atoms: {} vs {}", initial.natoms(), final_.natoms());
+    }
+    for ((i, a), (_, b)) in initial.atoms().zip(final_.atoms()) {
+        if a.symbol() != b.symbol() {
+            bail!("atom {} has different elements: {} vs {}", i, a.symbol(), b.symbol());
+        }
+    }
+
+    let p0: Vec<Vector3f> = initial.positions().map(|p| p.into()).collect();
+    let disp = final_
+        .positions()
+        .zip(p0.iter())
+        .map(|(p, &q)| mic_vector(initial, q, p.into()))
+        .collect_vec();
+
+    let mut images = vec![];
+    for k in 0..=nimages + 1 {
+        let t = k as f64 / (nimages + 1) as f64;
+        let mut mol = initial.clone();
+        let positions = p0.iter().zip(disp.iter()).map(|(p, d)| -> [f64; 3] { (p + t * d).into() });
+        mol.set_positions(positions);
+        mol.properties = match k {
+            0 => initial.properties.clone(),
+            k if k == nimages + 1 => final_.properties.clone(),
+            _ => Default::default(),
+        };
+        mol.set_title(&format!("image {}", k));
+        images.push(mol);
+    }
+
+    Ok(images)
+}
+// 580899a1 ends here
+
+// [[file:../gosh.note::e15a9240][e15a9240]]
+/// Return IDPP objective function value and forces for an image.
+///
+/// S = sum_{i<j} w(d_ij) (d_ij^target - d_ij)^2, with w(d) = 1/d^4
+///
+/// Pairs of overlapping atoms are skipped, for which the weight and the
+/// direction of forces are undefined.
+fn idpp_forces(mol: &Molecule, positions: &[Vector3f], targets: &[Vec<f64>]) -> (f64, Vec<Vector3f>) {
+    let n = positions.len();
+    let mut s = 0.0;
+    let mut forces = vec![Vector3f::zeros(); n];
+    for i in 0..n {
+        for j in (i + 1)..n {
+            // pointing from j to i
+            let rij = mic_vector(mol, positions[j], positions[i]);
+            let d = rij.norm();
+            if d < 1e-6 {
+                continue;
+            }
+            let dt = targets[i][j];
+            let x = dt - d;
+            s += x.powi(2) / d.powi(4);
+            let ds_dd = -4.0 * x.powi(2) / d.powi(5) - 2.0 * x / d.powi(4);
+            let f = -ds_dd * rij / d;
+            forces[i] += f;
+            forces[j] -= f;
+        }
+    }
+    (s, forces)
+}
+
+fn pair_distances(mol: &Molecule, positions: &[Vector3f]) -> Vec<Vec<f64>> {
+    let n = positions.len();
+    let mut d = vec![vec![0.0; n]; n];
+    for i in 0..n {
+        for j in (i + 1)..n {
+            d[i][j] = mic_vector(mol, positions[i], positions[j]).norm();
+            d[j][i] = d[i][j];
+        }
+    }
+    d
+}
+
+/// Refine interpolated `images` (including end points) using the image
+/// dependent pair potential (IDPP) method, optimizing the intermediate
+/// images as a nudged elastic band. Frozen coordinates of atoms in the
+/// initial image are kept fixed in all images.
+///
+/// Return true if the max force is below `fmax` within `nmax` steps.
+///
+/// Reference: Smidstrup et al., J. Chem. Phys. 140, 214106 (2014)
+pub(crate) fn refine_idpp(images: &mut [Molecule], fmax: f64, nmax: usize) -> Result<bool> {
+    let nimages = images.len();
+    if nimages < 3 {
+        return Ok(true);
+    }
+
+    let mut coords: Vec<Vec<Vector3f>> = images.iter().map(|m| m.positions().map(|p| p.into()).collect()).collect();
+    let d0 = pair_distances(&images[0], &coords[0]);
+    let d1 = pair_distances(&images[nimages - 1], &coords[nimages - 1]);
+    let natoms = coords[0].len();
+    let frozen = images[0].atoms().map(|(_, a)| a.freezing()).collect_vec();
+    let targets: Vec<Vec<Vec<f64>>> = (0..nimages)
+        .map(|k| {
+            let t = k as f64 / (nimages - 1) as f64;
+            (0..natoms)
+                .map(|i| (0..natoms).map(|j| d0[i][j] + t * (d1[i][j] - d0[i][j])).collect())
+                .collect()
+        })
+        .collect();
+
+    // spring constant between neighboring images, steepest descent step
+    // factor and max step size. A unit step factor oscillates around the
+    // minimum for short pair distances.
+    let k_spring = 1.0;
+    let dt = 0.1;
+    let max_step = 0.05;
+    let mut converged = false;
+    for istep in 0..nmax {
+        let mut fmax_ = 0.0f64;
+        let mut new_coords = coords.clone();
+        for k in 1..nimages - 1 {
+            let (_, forces) = idpp_forces(&images[k], &coords[k], &targets[k]);
+
+            // tangent along the band
+            let tau: Vec<Vector3f> = (0..natoms)
+                .map(|i| mic_vector(&images[k], coords[k - 1][i], coords[k + 1][i]))
+                .collect();
+            let tau_norm = tau.iter().map(|v| v.norm_squared()).sum::<f64>().sqrt();
+            let dist_next = (0..natoms)
+                .map(|i| mic_vector(&images[k], coords[k][i], coords[k + 1][i]).norm_squared())
+                .sum::<f64>()
+                .sqrt();
+            let dist_prev = (0..natoms)
+                .map(|i| mic_vector(&images[k], coords[k - 1][i], coords[k][i]).norm_squared())
+                .sum::<f64>()
+                .sqrt();
+
+            // remove force component parallel to tangent, and add spring force
+            let f_par: f64 = forces.iter().zip(tau.iter()).map(|(f, t)| f.dot(t)).sum::<f64>() / tau_norm;
+            let f_spring = k_spring * (dist_next - dist_prev);
+            for i in 0..natoms {
+                let t = tau[i] / tau_norm;
+                let mut f = forces[i] - f_par * t + f_spring * t;
+                for x in 0..3 {
+                    if frozen[i][x] {
+                        f[x] = 0.0;
+                    }
+                }
+                fmax_ = fmax_.max(f.norm());
+                let step = f * dt;
+                let step = if step.norm() > max_step { step.normalize() * max_step } else { step };
+                new_coords[k][i] = coords[k][i] + step;
+            }
+        }
+        coords = new_coords;
+        debug!("IDPP step {}: fmax = {}", istep, fmax_);
+        if fmax_ < fmax {
+            debug!("IDPP converged in {} steps.", istep + 1);
+            converged = true;
+            break;
+        }
+    }
+
+    for (mol, positions) in images.iter_mut().zip(coords) {
+        mol.set_positions(positions.into_iter().map(|p| -> [f64; 3] { p.into() }));
+    }
+
+    Ok(converged)
+}
+// e15a9240 ends here
+
+// [[file:../gosh.note::a3f6c218][a3f6c218]]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gchemol::{Atom, Lattice};
+
+    fn h3(positions: &[[f64; 3]]) -> Molecule {
+        Molecule::from_atoms(positions.iter().map(|&p| Atom::new("H", p)))
+    }
+
+    #[test]
+    fn test_interpolate_linear() -> Result<()> {
+        let initial = h3(&[[0.0; 3], [1.0, 0.0, 0.0]]);
+        let final_ = h3(&[[0.0; 3], [3.0, 0.0, 0.0]]);
+        let images = interpolate_linear(&initial, &final_, 3)?;
+        assert_eq!(images.len(), 5);
+        let x = images.iter().map(|m| m.get_atom(2).unwrap().position()[0]).collect_vec();
+        for (x, y) in x.iter().zip([1.0, 1.5, 2.0, 2.5, 3.0]) {
+            assert_relative_eq!(*x, y, epsilon = 1e-8);
+        }
+
+        // displacements follow minimum image convention
+        let mut initial = initial;
+        let mut final_ = h3(&[[0.0; 3], [9.0, 0.0, 0.0]]);
+        let lat = Lattice::new([[10.0, 0.0, 0.0], [0.0, 10.0, 0.0], [0.0, 0.0, 10.0]]);
+        initial.set_lattice(lat.clone());
+        final_.set_lattice(lat);
+        let images = interpolate_linear(&initial, &final_, 1)?;
+        assert_relative_eq!(images[1].get_atom(2).unwrap().position()[0], 0.0, epsilon = 1e-8);
+
+        let oh = Molecule::from_atoms([Atom::new("O", [0.0; 3]), Atom::new("H", [1.0, 0.0, 0.0])]);
+        assert!(interpolate_linear(&initial, &oh, 1).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_idpp_forces() {
+        let mol = h3(&[[0.0; 3], [1.0, 0.2, 0.0], [0.1, 1.3, 0.4]]);
+        let positions: Vec<Vector3f> = mol.positions().map(|p| p.into()).collect();
+        let targets = vec![vec![0.0, 0.9, 1.2], vec![0.9, 0.0, 1.5], vec![1.2, 1.5, 0.0]];
+        let (_, forces) = idpp_forces(&mol, &positions, &targets);
+
+        // compare with numerical forces by central difference
+        let h = 1e-5;
+        for i in 0..3 {
+            for k in 0..3 {
+                let mut p = positions.clone();
+                p[i][k] += h;
+                let (s_plus, _) = idpp_forces(&mol, &p, &targets);
+                p[i][k] -= 2.0 * h;
+                let (s_minus, _) = idpp_forces(&mol, &p, &targets);
+                let f = -(s_plus - s_minus) / (2.0 * h);
+                assert_relative_eq!(forces[i][k], f, epsilon = 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn test_refine_idpp() -> Result<()> {
+        // rotation of a H2 molecule: linear interpolation shrinks the bond
+        let initial = h3(&[[-0.5, 0.0, 0.0], [0.5, 0.0, 0.0]]);
+        let final_ = h3(&[[0.0, -0.5, 0.0], [0.0, 0.5, 0.0]]);
+        let mut images = interpolate_linear(&initial, &final_, 1)?;
+        let d = |m: &Molecule| crate::geometry::get_distance(m, 1, 2).unwrap();
+        assert!(d(&images[1]) < 0.8);
+        assert!(refine_idpp(&mut images, 0.01, 1000)?);
+        assert_relative_eq!(d(&images[1]), 1.0, epsilon = 0.05);
+        // end points are kept
+        assert_eq!(images[0].positions().collect_vec(), initial.positions().collect_vec());
+
+        // frozen atoms are kept fixed
+        let mut initial = h3(&[[-0.5, 0.0, 0.0], [0.5, 0.0, 0.0]]);
+        initial.get_atom_mut(1).unwrap().set_freezing([true; 3]);
+        let final_ = h3(&[[-0.5, 0.0, 0.0], [-0.5, 1.0, 0.0]]);
+        let mut images = interpolate_linear(&initial, &final_, 1)?;
+        refine_idpp(&mut images, 0.01, 1000)?;
+        assert_eq!(images[1].get_atom(1).unwrap().position(), [-0.5, 0.0, 0.0]);
+        assert_relative_eq!(d(&images[1]), 1.0, epsilon = 0.05);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_overlapping_atoms() -> Result<()> {
+        let mol = h3(&[[0.0; 3], [0.0; 3], [1.0, 0.0, 0.0]]);
+        let positions: Vec<Vector3f> = mol.positions().map(|p| p.into()).collect();
+        let targets = vec![vec![0.0, 0.5, 1.0], vec![0.5, 0.0, 1.0], vec![1.0, 1.0, 0.0]];
+        let (s, forces) = idpp_forces(&mol, &positions, &targets);
+        assert!(s.is_finite());
+        assert!(forces.iter().all(|f| f.iter().all(|x| x.is_finite())));
+
+        // properties are not copied into intermediate images
+        let mut initial = h3(&[[0.0; 3], [1.0, 0.0, 0.0]]);
+        initial.properties.store("energy", &-1.0)?;
+        let mut final_ = h3(&[[0.0; 3], [2.0, 0.0, 0.0]]);
+        final_.properties.store("energy", &-2.0)?;
+        let images = interpolate_linear(&initial, &final_, 1)?;
+        assert_eq!(images[0].properties.load::<f64>("energy")?, -1.0);
+        assert!(images[1].properties.load::<f64>("energy").is_err());
+        assert_eq!(images[2].properties.load::<f64>("energy")?, -2.0);
+
+        Ok(())
+    }
+}
+// a3f6c218 ends here
@@ -6,8 +6,28 @@ use std::path::{Path, PathBuf};
 // 3552d6e4 ends here
 
 // [[file:../gosh.note::e4bba37b][e4bba37b]]
+mod adsorb;
 mod bbm;
+mod cell;
+mod completion;
+mod edit;
+mod frames;
+mod geometry;
+mod info;
+mod interpolate;
+mod pbc;
+mod perturb;
+mod reorder;
 mod repl;
+mod script;
+mod similarity;
+mod slab;
+mod smiles;
+mod symmetry;
+mod template;
+mod transform;
+mod update;
+mod valence;
 
 pub mod cli;
 // e4bba37b ends here
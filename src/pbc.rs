@@ -0,0 +1,149 @@
+// [[file:../gosh.note::0e6b4d37][0e6b4d37]]
+use super::*;
+use crate::geometry::mic_vector;
+
+use gchemol::Molecule;
+use vecfx::*;
+
+use std::collections::{HashMap, HashSet};
+// 0e6b4d37 ends here
+
+// [[file:../gosh.note::a91c5f28][a91c5f28]]
+/// Wrap `atoms` of `mol` into the unit cell, making all fractional
+/// coordinates in [0, 1).
+pub(crate) fn wrap_atoms(mol: &mut Molecule, atoms: &[usize]) -> Result<()> {
+    let lat = mol.lattice.clone().ok_or_else(|| format_err!("not a periodic system!"))?;
+    for &n in atoms {
+        let a = mol.get_atom_mut(n).ok_or_else(|| format_err!("no such atom: {}", n))?;
+        let f: Vector3f = lat.to_frac(a.position()).into();
+        // avoid 1.0 due to rounding of tiny negative values
+        let f = f.map(|x| {
+            let y = x - x.floor();
+            if y >= 1.0 {
+                0.0
+            } else {
+                y
+            }
+        });
+        let p: [f64; 3] = lat.to_cart(f.into()).into();
+        a.set_position(p);
+    }
+
+    Ok(())
+}
+
+/// Make molecules whole across periodic boundaries by following bonds:
+/// each bonded atom is moved to the periodic image nearest to its bonded
+/// neighbor. Only `atoms` will be moved, and bonds to other atoms are not
+/// followed. Return the number of moved atoms.
+pub(crate) fn make_whole(mol: &mut Molecule, atoms: &[usize]) -> Result<usize> {
+    if mol.lattice.is_none() {
+        bail!("not a periodic system!");
+    }
+    if mol.nbonds() == 0 {
+        bail!("no bonds found. Please run `rebond` first.");
+    }
+
+    let allowed: HashSet<usize> = atoms.iter().copied().collect();
+    let mut positions: HashMap<usize, Vector3f> = HashMap::new();
+    for &n in atoms {
+        let a = mol.get_atom(n).ok_or_else(|| format_err!("no such atom: {}", n))?;
+        positions.insert(n, a.position().into());
+    }
+
+    let mut visited = HashSet::new();
+    let mut nmoved = 0;
+    for &root in atoms.iter().sorted() {
+        if !visited.insert(root) {
+            continue;
+        }
+        let mut stack = vec![root];
+        while let Some(i) = stack.pop() {
+            let pi = positions[&i];
+            for j in mol.connected(i).collect_vec() {
+                if !allowed.contains(&j) || !visited.insert(j) {
+                    continue;
+                }
+                let pj = pi + mic_vector(mol, pi, positions[&j]);
+                if (pj - positions[&j]).norm() > 1e-6 {
+                    nmoved += 1;
+                }
+                positions.insert(j, pj);
+                stack.push(j);
+            }
+        }
+    }
+
+    for (n, p) in positions {
+        mol.get_atom_mut(n).unwrap().set_position(p.into());
+    }
+
+    Ok(nmoved)
+}
+// a91c5f28 ends here
+
+// [[file:../gosh.note::b8e3f6a1][b8e3f6a1]]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gchemol::{Atom, Bond, Lattice};
+
+    // a water molecule split across the boundary of a 10 Å cubic cell
+    fn split_water() -> Molecule {
+        let mut mol = Molecule::from_atoms([
+            Atom::new("O", [9.8, 5.0, 5.0]),
+            Atom::new("H", [0.56, 5.0, 5.0]),
+            Atom::new("H", [9.56, 5.93, 5.0]),
+        ]);
+        mol.set_lattice(Lattice::new([[10.0, 0.0, 0.0], [0.0, 10.0, 0.0], [0.0, 0.0, 10.0]]));
+        mol.add_bond(1, 2, Bond::single());
+        mol.add_bond(1, 3, Bond::single());
+        mol
+    }
+
+    fn position(mol: &Molecule, n: usize) -> Vector3f {
+        mol.get_atom(n).unwrap().position().into()
+    }
+
+    #[test]
+    fn test_wrap_atoms() -> Result<()> {
+        let mut mol = split_water();
+        mol.get_atom_mut(1).unwrap().set_position([-0.2, 15.0, 5.0]);
+        mol.get_atom_mut(3).unwrap().set_position([-1e-17, 5.93, 5.0]);
+        wrap_atoms(&mut mol, &[1, 2, 3])?;
+        assert!((position(&mol, 1) - Vector3f::new(9.8, 5.0, 5.0)).norm() < 1e-8);
+        assert!((position(&mol, 2) - Vector3f::new(0.56, 5.0, 5.0)).norm() < 1e-8);
+        // tiny negative values should not be wrapped to 1.0
+        let x = position(&mol, 3).x;
+        assert!((0.0..10.0).contains(&x));
+
+        let mut mol = Molecule::from_atoms([Atom::new("H", [0.0; 3])]);
+        assert!(wrap_atoms(&mut mol, &[1]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_make_whole() -> Result<()> {
+        let mut mol = split_water();
+        let nmoved = make_whole(&mut mol, &[1, 2, 3])?;
+        assert_eq!(nmoved, 1);
+        assert!((position(&mol, 1) - Vector3f::new(9.8, 5.0, 5.0)).norm() < 1e-8);
+        assert!((position(&mol, 2) - Vector3f::new(10.56, 5.0, 5.0)).norm() < 1e-8);
+        assert!((position(&mol, 3) - Vector3f::new(9.56, 5.93, 5.0)).norm() < 1e-8);
+
+        // only selected atoms are moved
+        let mut mol = split_water();
+        let nmoved = make_whole(&mut mol, &[1, 3])?;
+        assert_eq!(nmoved, 0);
+        assert!((position(&mol, 2) - Vector3f::new(0.56, 5.0, 5.0)).norm() < 1e-8);
+
+        // bonds are required
+        let mut mol = Molecule::from_atoms(split_water().atoms().map(|(_, a)| a.clone()));
+        mol.set_lattice(Lattice::new([[10.0, 0.0, 0.0], [0.0, 10.0, 0.0], [0.0, 0.0, 10.0]]));
+        assert!(make_whole(&mut mol, &[1, 2, 3]).is_err());
+
+        Ok(())
+    }
+}
+// b8e3f6a1 ends here
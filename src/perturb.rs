@@ -0,0 +1,156 @@
+// [[file:../gosh.note::6a8e0b15][6a8e0b15]]
+use super::*;
+
+use gchemol::{Lattice, Molecule};
+use rand::prelude::*;
+use vecfx::nalgebra::Matrix3;
+use vecfx::*;
+// 6a8e0b15 ends here
+
+// [[file:../gosh.note::b3d71f40][b3d71f40]]
+/// Draw a random number from standard normal distribution using Box-Muller
+/// transform.
+fn standard_normal(rng: &mut StdRng) -> f64 {
+    let u1: f64 = 1.0 - rng.gen::<f64>();
+    let u2: f64 = rng.gen();
+    (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+}
+
+/// Displace `atoms` in `mol` randomly with normal distribution of standard
+/// deviation `stdev` in Angstrom. Frozen coordinates are not changed.
+/// Return the number of displaced atoms.
+pub(crate) fn rattle(mol: &mut Molecule, atoms: &[usize], stdev: f64, rng: &mut StdRng) -> Result<usize> {
+    let mut n = 0;
+    for &i in atoms {
+        let a = mol.get_atom_mut(i).ok_or_else(|| format_err!("no such atom: {}", i))?;
+        let frozen = a.freezing();
+        if frozen.iter().all(|&f| f) {
+            continue;
+        }
+        let mut p = a.position();
+        for k in 0..3 {
+            if !frozen[k] {
+                p[k] += stdev * standard_normal(rng);
+            }
+        }
+        a.set_position(p);
+        n += 1;
+    }
+
+    Ok(n)
+}
+
+/// Deform the lattice of `mol` by applying strain tensor `strain` in
+/// cartesian coordinates: each cell vector v becomes (I + strain) v. Atoms
+/// follow the lattice in fractional coordinates.
+pub(crate) fn apply_strain(mol: &mut Molecule, strain: Matrix3<f64>) -> Result<()> {
+    let lat = mol.lattice.clone().ok_or_else(|| format_err!("not a periodic system!"))?;
+    let deform = Matrix3::identity() + strain;
+    if deform.determinant() <= 0.0 {
+        bail!("invalid strain: {:?}", strain);
+    }
+
+    let fcoords: Vec<[f64; 3]> = mol.positions().map(|p| lat.to_frac(p).into()).collect();
+    let cell = crate::cell::cell_vectors(&lat);
+    let tvs: [[f64; 3]; 3] = cell.map(|v| (deform * v).into());
+    let new = Lattice::new(tvs);
+    let numbers = mol.numbers().collect_vec();
+    for (n, f) in numbers.into_iter().zip(fcoords) {
+        let p: [f64; 3] = new.to_cart(f).into();
+        mol.get_atom_mut(n).unwrap().set_position(p);
+    }
+    mol.set_lattice(new);
+
+    Ok(())
+}
+
+/// Build symmetric strain tensor from its components. An isotropic `scale`
+/// of cell lengths, when given, is combined with the other components.
+///
+/// The shear components are tensor components, not engineering shear
+/// strains: `xy` is set to both ε_xy and ε_yx, which corresponds to an
+/// engineering shear γ_xy = 2 * xy.
+pub(crate) fn strain_tensor(xx: f64, yy: f64, zz: f64, xy: f64, xz: f64, yz: f64, scale: Option<f64>) -> Matrix3<f64> {
+    let mut strain = Matrix3::new(xx, xy, xz, xy, yy, yz, xz, yz, zz);
+    if let Some(s) = scale {
+        strain = (Matrix3::identity() + strain) * s - Matrix3::identity();
+    }
+    strain
+}
+// b3d71f40 ends here
+
+// [[file:../gosh.note::93f0c5d2][93f0c5d2]]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gchemol::Atom;
+
+    #[test]
+    fn test_rattle() -> Result<()> {
+        let atoms = (0..200).map(|i| Atom::new("Ar", [i as f64, 0.0, 0.0])).collect_vec();
+        let mol = Molecule::from_atoms(atoms);
+        let all = mol.numbers().collect_vec();
+
+        // the same seed gives the same displacements
+        let mut mol1 = mol.clone();
+        let mut mol2 = mol.clone();
+        rattle(&mut mol1, &all, 0.05, &mut crate::edit::new_rng(Some(42)))?;
+        rattle(&mut mol2, &all, 0.05, &mut crate::edit::new_rng(Some(42)))?;
+        let p1 = mol1.positions().collect_vec();
+        assert_eq!(p1, mol2.positions().collect_vec());
+
+        let d = mol.positions().zip(&p1).flat_map(|(p, q)| (0..3).map(move |k| q[k] - p[k])).collect_vec();
+        let mean = d.iter().sum::<f64>() / d.len() as f64;
+        let stdev = (d.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / d.len() as f64).sqrt();
+        assert!(mean.abs() < 0.01);
+        assert!((stdev - 0.05).abs() < 0.01);
+
+        // frozen coordinates are kept
+        let mut mol = mol.clone();
+        mol.get_atom_mut(1).unwrap().set_freezing([true; 3]);
+        mol.get_atom_mut(2).unwrap().set_freezing([true, true, false]);
+        let n = rattle(&mut mol, &[1, 2], 0.05, &mut crate::edit::new_rng(Some(42)))?;
+        assert_eq!(n, 1);
+        assert_eq!(mol.get_atom(1).unwrap().position(), [0.0, 0.0, 0.0]);
+        let [x, y, z] = mol.get_atom(2).unwrap().position();
+        assert_eq!([x, y], [1.0, 0.0]);
+        assert!(z != 0.0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_strain_tensor() {
+        let strain = strain_tensor(0.02, 0.0, 0.0, 0.0, 0.0, 0.01, None);
+        assert_eq!(strain, Matrix3::new(0.02, 0.0, 0.0, 0.0, 0.0, 0.01, 0.0, 0.01, 0.0));
+        assert_eq!(strain, strain.transpose());
+
+        // isotropic scaling combined with other components
+        let strain = strain_tensor(0.0, 0.0, 0.0, 0.0, 0.0, 0.0, Some(1.1));
+        assert!((strain - Matrix3::identity() * 0.1).norm() < 1e-12);
+        let strain = strain_tensor(0.1, 0.0, 0.0, 0.0, 0.0, 0.0, Some(2.0));
+        assert!((strain - Matrix3::from_diagonal(&Vector3f::new(1.2, 1.0, 1.0))).norm() < 1e-12);
+    }
+
+    #[test]
+    fn test_apply_strain() -> Result<()> {
+        let mut mol = Molecule::from_atoms([Atom::new("Ar", [5.0, 5.0, 5.0])]);
+        assert!(apply_strain(&mut mol, Matrix3::zeros()).is_err());
+
+        mol.set_lattice(Lattice::new([[10.0, 0.0, 0.0], [0.0, 10.0, 0.0], [0.0, 0.0, 10.0]]));
+        apply_strain(&mut mol, strain_tensor(0.02, 0.0, 0.0, 0.01, 0.0, 0.0, None))?;
+        let cell = crate::cell::cell_vectors(mol.lattice.as_ref().unwrap());
+        assert!((cell[0] - Vector3f::new(10.2, 0.1, 0.0)).norm() < 1e-8);
+        assert!((cell[1] - Vector3f::new(0.1, 10.0, 0.0)).norm() < 1e-8);
+        assert!((cell[2] - Vector3f::new(0.0, 0.0, 10.0)).norm() < 1e-8);
+        // atoms follow in fractional coordinates
+        let p: Vector3f = mol.get_atom(1).unwrap().position().into();
+        assert!((p - Vector3f::new(5.15, 5.05, 5.0)).norm() < 1e-8);
+
+        // collapsing the cell is not allowed
+        assert!(apply_strain(&mut mol, strain_tensor(-1.0, 0.0, 0.0, 0.0, 0.0, 0.0, None)).is_err());
+
+        Ok(())
+    }
+}
+// 93f0c5d2 ends here
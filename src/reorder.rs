@@ -0,0 +1,166 @@
+// [[file:../gosh.note::8a0c71d2][8a0c71d2]]
+use super::*;
+use crate::geometry::mic_vector;
+
+use gchemol::Molecule;
+use vecfx::*;
+// 8a0c71d2 ends here
+
+// [[file:../gosh.note::d3e5b6a4][d3e5b6a4]]
+/// The key in molecule properties for the permutation applied by `sort`.
+/// The k-th item is the serial number in original order of the k-th atom.
+pub(crate) const PERMUTATION_KEY: &str = "permutation";
+
+/// The keys available for sorting atoms.
+pub(crate) const SORT_KEYS: &[&str] = &["element", "z", "fz", "distance-to"];
+
+/// Return stored permutation of `mol`, or the identity if atoms were never
+/// sorted.
+pub(crate) fn get_permutation(mol: &Molecule) -> Vec<usize> {
+    match mol.properties.load::<Vec<usize>>(PERMUTATION_KEY) {
+        Ok(perm) if perm.len() == mol.natoms() => perm,
+        _ => (1..=mol.natoms()).collect(),
+    }
+}
+
+/// Sort atoms in `mol` by `key`. Atoms with the same key keep their
+/// relative order. The permutation with respect to the original order is
+/// stored in molecule properties, accumulating on previous sorting.
+///
+/// Possible keys:
+/// - element: group atoms by element in order of first appearance, as
+///   required by POSCAR format
+/// - z: cartesian z coordinate
+/// - fz: fractional z coordinate
+/// - distance-to: distance to atom `reference`
+pub(crate) fn sort_atoms(mol: &mut Molecule, key: &str, reference: Option<usize>, reverse: bool) -> Result<()> {
+    let numbers = mol.numbers().collect_vec();
+    let values: Vec<f64> = match key {
+        "element" => {
+            let mut symbols = vec![];
+            for (_, a) in mol.atoms() {
+                if !symbols.contains(&a.symbol()) {
+                    symbols.push(a.symbol());
+                }
+            }
+            mol.atoms()
+                .map(|(_, a)| symbols.iter().position(|s| *s == a.symbol()).unwrap() as f64)
+                .collect()
+        }
+        "z" => mol.positions().map(|p| p[2]).collect(),
+        "fz" => {
+            let lat = mol.lattice.as_ref().ok_or_else(|| format_err!("not a periodic system!"))?;
+            mol.positions().map(|p| lat.to_frac(p)[2]).collect()
+        }
+        "distance-to" => {
+            let i = reference.ok_or_else(|| format_err!("reference atom is required for sorting by distance."))?;
+            let pi: Vector3f = mol.get_atom(i).ok_or_else(|| format_err!("no such atom: {}", i))?.position().into();
+            mol.positions().map(|p| mic_vector(mol, pi, p.into()).norm()).collect()
+        }
+        _ => bail!("invalid sort key: {:?}. Possible values: {}", key, SORT_KEYS.join(", ")),
+    };
+
+    let mut indices = (0..numbers.len()).collect_vec();
+    indices.sort_by(|&a, &b| values[a].total_cmp(&values[b]));
+    if reverse {
+        indices.reverse();
+    }
+    let order = indices.iter().map(|&k| numbers[k]).collect_vec();
+
+    // accumulate permutation with respect to the original order
+    let perm = get_permutation(mol);
+    let new_perm = indices.iter().map(|&k| perm[k]).collect_vec();
+
+    let mut new = crate::edit::reordered(mol, &order)?;
+    new.properties.store(PERMUTATION_KEY, &new_perm)?;
+    *mol = new;
+
+    Ok(())
+}
+
+/// Restore the original atom order of `mol` using stored permutation.
+pub(crate) fn unsort_atoms(mol: &mut Molecule) -> Result<()> {
+    let perm: Vec<usize> = mol
+        .properties
+        .load(PERMUTATION_KEY)
+        .map_err(|_| format_err!("no stored permutation. Atoms were not sorted using `sort` command."))?;
+    if perm.len() != mol.natoms() {
+        bail!("stored permutation does not match the number of atoms: {} vs {}", perm.len(), mol.natoms());
+    }
+
+    let numbers = mol.numbers().collect_vec();
+    let mut indices = (0..perm.len()).collect_vec();
+    indices.sort_by_key(|&k| perm[k]);
+    let order = indices.iter().map(|&k| numbers[k]).collect_vec();
+
+    let mut new = crate::edit::reordered(mol, &order)?;
+    new.properties.discard(PERMUTATION_KEY);
+    *mol = new;
+
+    Ok(())
+}
+// d3e5b6a4 ends here
+
+// [[file:../gosh.note::0b7c93e5][0b7c93e5]]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gchemol::{Atom, Bond};
+
+    fn test_molecule() -> Molecule {
+        let atoms = [
+            ("O", [0.0, 0.0, 0.3]),
+            ("H", [0.0, 0.8, -0.2]),
+            ("C", [2.0, 0.0, 1.0]),
+            ("H", [0.0, -0.8, -0.1]),
+            ("O", [3.2, 0.0, 1.0]),
+        ];
+        let mut mol = Molecule::from_atoms(atoms.iter().map(|(s, p)| Atom::new(*s, *p)));
+        mol.add_bond(1, 2, Bond::single());
+        mol.add_bond(1, 4, Bond::single());
+        mol.add_bond(3, 5, Bond::double());
+        mol
+    }
+
+    fn symbols(mol: &Molecule) -> Vec<String> {
+        mol.atoms().map(|(_, a)| a.symbol().to_owned()).collect()
+    }
+
+    #[test]
+    fn test_sort_unsort() -> Result<()> {
+        let mol = test_molecule();
+        let mut sorted = mol.clone();
+        sort_atoms(&mut sorted, "element", None, false)?;
+        assert_eq!(symbols(&sorted), ["O", "O", "H", "H", "C"]);
+        assert_eq!(get_permutation(&sorted), [1, 5, 2, 4, 3]);
+        assert_eq!(sorted.nbonds(), 3);
+        // O1-H2 is now atom 1 bonded to atom 3
+        assert!(sorted.get_bond(1, 3).is_some());
+
+        // sorting accumulates
+        sort_atoms(&mut sorted, "z", None, true)?;
+        let perm = get_permutation(&sorted);
+        for (k, (_, a)) in sorted.atoms().enumerate() {
+            assert_eq!(a.position(), mol.get_atom(perm[k]).unwrap().position());
+        }
+
+        unsort_atoms(&mut sorted)?;
+        assert_eq!(symbols(&sorted), symbols(&mol));
+        assert_eq!(sorted.positions().collect_vec(), mol.positions().collect_vec());
+        assert_eq!(sorted.nbonds(), 3);
+        assert!(sorted.get_bond(1, 2).is_some());
+        assert!(sorted.get_bond(3, 5).is_some());
+        assert!(unsort_atoms(&mut sorted).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sort_invalid() {
+        let mut mol = test_molecule();
+        assert!(sort_atoms(&mut mol, "mass", None, false).is_err());
+        assert!(sort_atoms(&mut mol, "distance-to", None, false).is_err());
+        assert!(sort_atoms(&mut mol, "fz", None, false).is_err());
+    }
+}
+// 0b7c93e5 ends here
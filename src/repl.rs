@@ -1,6 +1,7 @@
 // [[file:../gosh.note::e3304fe2][e3304fe2]]
 use super::*;
 use crate::cli::Commander;
+use crate::completion::GoshHelper;
 // e3304fe2 ends here
 
 // [[file:../gosh.note::af47268b][af47268b]]
@@ -45,10 +46,76 @@ impl Actionable for Action {
         Ok(false)
     }
 }
+
+/// Return the path to the file keeping command history across sessions.
+fn history_file() -> Option<PathBuf> {
+    dirs::home_dir().map(|d| d.join(".gosh_history"))
+}
+
+impl Action {
+    /// Enter interactive shell with context-aware tab completion. We drive
+    /// rustyline here since `gosh_repl::Interpreter` has no way to plug in a
+    /// custom completer. Command history is loaded from and saved into
+    /// `~/.gosh_history`.
+    fn run_interactive(&mut self, prompt: &str) -> Result<()> {
+        use rustyline::error::ReadlineError;
+
+        let mut editor = rustyline::Editor::<GoshHelper>::new()?;
+        editor.set_helper(Some(GoshHelper::new()));
+        let history = history_file();
+        if let Some(path) = &history {
+            // the file does not exist on first run
+            let _ = editor.load_history(path);
+        }
+
+        loop {
+            match editor.readline(prompt) {
+                Ok(line) => {
+                    let line = line.trim();
+                    if line.is_empty() || line.starts_with('#') {
+                        continue;
+                    }
+                    editor.add_history_entry(line);
+                    let args = match shlex::split(line) {
+                        Some(args) => args,
+                        None => {
+                            eprintln!("invalid command line: {:?}", line);
+                            continue;
+                        }
+                    };
+                    match Cmd::try_parse_from(std::iter::once("gosh".to_owned()).chain(args)) {
+                        Ok(cmd) => {
+                            if self.act_on(&cmd)? {
+                                break;
+                            }
+                        }
+                        Err(e) => {
+                            let _ = e.print();
+                        }
+                    }
+                }
+                Err(ReadlineError::Interrupted) => continue,
+                Err(ReadlineError::Eof) => break,
+                Err(e) => {
+                    eprintln!("{:?}", e);
+                    break;
+                }
+            }
+        }
+        if let Some(path) = &history {
+            if let Err(e) = editor.save_history(path) {
+                eprintln!("failed to save history into {}: {:?}", path.display(), e);
+            }
+        }
+
+        Ok(())
+    }
+}
 // af47268b ends here
 
 // [[file:../gosh.note::4651ecd4][4651ecd4]]
-use gosh_repl::{Actionable, Interpreter};
+use crate::script::ScriptRunner;
+use gosh_repl::Actionable;
 
 use super::*;
 use gut::cli::*;
@@ -60,6 +127,11 @@ struct GoshCli {
     #[clap(short = 'x')]
     script_file: Option<PathBuf>,
 
+    /// Run gosh commands directly. Commands can be chained as a pipeline,
+    /// e.g.: load a.cif supercell 2 2 1 write b.vasp
+    #[clap(value_name = "COMMAND", trailing_var_arg = true, allow_hyphen_values = true)]
+    commands: Vec<String>,
+
     #[clap(flatten)]
     verbose: Verbosity,
 }
@@ -68,31 +140,235 @@ impl GoshCli {
     pub fn enter_main() -> Result<()> {
         let args: Vec<String> = std::env::args().collect();
 
-        let action = Action::new();
         // enter shell mode or subcommands mode
         if args.len() > 1 {
             let args = Self::parse();
             args.verbose.setup_logger();
 
+            let mut commander = Commander::new();
+            let mut runner = ScriptRunner::new(&mut commander);
             if let Some(script_file) = &args.script_file {
                 info!("Execute script file: {:?}", script_file);
-                Interpreter::new(action).interpret_script_file(script_file)?;
+                runner.run_script_file(script_file)?;
+            } else if !args.commands.is_empty() {
+                // parse all commands before running any of them
+                let mut cmds = vec![];
+                for cmd in split_commands(&args.commands)? {
+                    match Cmd::try_parse_from(std::iter::once("gosh".to_owned()).chain(cmd)) {
+                        Ok(cmd) => cmds.push(cmd),
+                        // such as --help
+                        Err(e) if !e.use_stderr() => {
+                            e.print()?;
+                            return Ok(());
+                        }
+                        Err(e) => bail!("{}", e),
+                    }
+                }
+                for cmd in cmds {
+                    if runner.run_cmd(cmd)? {
+                        break;
+                    }
+                }
             } else {
                 info!("Reading batch script from stdin ..");
                 let mut buffer = String::new();
                 std::io::stdin().read_to_string(&mut buffer)?;
-                Interpreter::new(action).interpret_script(&buffer)?;
+                runner.run_script(&buffer)?;
             }
         } else {
-            Interpreter::new(action).with_prompt("gosh> ").run()?;
+            Action::new().run_interactive("gosh> ")?;
         }
 
         Ok(())
     }
 }
 
+/// Split command line arguments into chained commands. An argument matching
+/// a command name or alias starts a new command, unless a required
+/// positional value of the current command is missing, or the argument is a
+/// valid value for the next positional argument. So `load info` loads a
+/// file named "info", while `sort by element write b.vasp` runs `write`
+/// after `sort`. A `;` argument always ends the current command. A single
+/// argument with commands separated by `;` is also accepted, e.g.: "load -
+/// --format xyz; write - --format poscar"
+fn split_commands(args: &[String]) -> Result<Vec<Vec<String>>> {
+    if let [line] = args {
+        if line.contains(';') {
+            return chain_commands(&tokenize(line)?);
+        }
+    }
+    chain_commands(args)
+}
+
+/// Split `line` into words like a shell does, with each unquoted `;` as a
+/// separate word.
+fn tokenize(line: &str) -> Result<Vec<String>> {
+    let mut words = vec![];
+    let mut word: Option<String> = None;
+    let mut chars = line.chars();
+    let unterminated = || format_err!("invalid command line: {:?}", line);
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                let w = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next().ok_or_else(unterminated)? {
+                        '\'' => break,
+                        c => w.push(c),
+                    }
+                }
+            }
+            '"' => {
+                let w = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next().ok_or_else(unterminated)? {
+                        '"' => break,
+                        '\\' => w.push(chars.next().ok_or_else(unterminated)?),
+                        c => w.push(c),
+                    }
+                }
+            }
+            '\\' => word.get_or_insert_with(String::new).push(chars.next().ok_or_else(unterminated)?),
+            ';' => {
+                words.extend(word.take());
+                words.push(";".to_owned());
+            }
+            c if c.is_whitespace() => words.extend(word.take()),
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+fn chain_commands(args: &[String]) -> Result<Vec<Vec<String>>> {
+    let mut app = Cmd::command();
+    app.build();
+
+    let mut commands: Vec<Vec<String>> = vec![];
+    // the current command, number of positional values, and whether the
+    // next argument is an option value
+    let mut current: Option<(&clap::Command, usize, bool)> = None;
+    for arg in args {
+        if arg == ";" {
+            current = None;
+            continue;
+        }
+        match current.as_mut() {
+            Some((cmd, npos, pending)) if *pending || app.find_subcommand(arg).is_none() || takes_positional(cmd, *npos, arg) => {
+                if *pending {
+                    *pending = false;
+                } else if let Some(a) = find_option(cmd, arg) {
+                    *pending = a.get_action().takes_values() && !arg.contains('=');
+                } else {
+                    *npos += 1;
+                }
+                commands.last_mut().unwrap().push(arg.to_owned());
+            }
+            _ => {
+                let cmd = app.find_subcommand(arg).ok_or_else(|| format_err!("unknown command: {:?}", arg))?;
+                current = Some((cmd, 0, false));
+                commands.push(vec![arg.to_owned()]);
+            }
+        }
+    }
+    Ok(commands)
+}
+
+/// Find the option of `cmd` named in `arg`, such as "-e" or "--format=xyz".
+fn find_option<'a>(cmd: &'a clap::Command, arg: &str) -> Option<&'a clap::Arg> {
+    if let Some(long) = arg.strip_prefix("--") {
+        let long = long.split('=').next().unwrap();
+        cmd.get_arguments().find(|a| a.get_long() == Some(long))
+    } else if let Some(short) = arg.strip_prefix('-') {
+        let short = short.chars().next()?;
+        cmd.get_arguments().find(|a| a.get_short() == Some(short))
+    } else {
+        None
+    }
+}
+
+/// Return true if `arg` should be taken as a positional value of `cmd` after
+/// `npos` values: a required positional value is still missing, or `arg` is
+/// a valid value for the next positional argument.
+fn takes_positional(cmd: &clap::Command, npos: usize, arg: &str) -> bool {
+    let nrequired: usize = cmd
+        .get_positionals()
+        .filter(|a| a.is_required_set())
+        .map(|a| a.get_num_args().map_or(1, |r| r.min_values()))
+        .sum();
+    let mut n = 0usize;
+    for a in cmd.get_positionals() {
+        n = n.saturating_add(a.get_num_args().map_or(1, |r| r.max_values()));
+        if npos < n {
+            let valid = a.get_value_parser().parse_ref(cmd, Some(a), std::ffi::OsStr::new(arg)).is_ok();
+            return npos < nrequired || valid;
+        }
+    }
+    false
+}
+
 pub fn repl_enter_main() -> Result<()> {
     GoshCli::enter_main()?;
     Ok(())
 }
 // 4651ecd4 ends here
+
+// [[file:../gosh.note::9d4e6b1f][9d4e6b1f]]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(line: &str) -> Vec<Vec<String>> {
+        let args = line.split_whitespace().map(|s| s.to_owned()).collect_vec();
+        split_commands(&args).unwrap()
+    }
+
+    #[test]
+    fn test_split_commands() {
+        assert_eq!(
+            split("load a.cif supercell 2 2 1 write b.vasp"),
+            [vec!["load", "a.cif"], vec!["supercell", "2", "2", "1"], vec!["write", "b.vasp"]]
+        );
+        // arguments equal to command names
+        assert_eq!(split("load info"), [vec!["load", "info"]]);
+        assert_eq!(split("load a.cif show info info"), [vec!["load", "a.cif"], vec!["show", "info"], vec!["info"]]);
+        // option values
+        assert_eq!(split("load - --format xyz info"), [vec!["load", "-", "--format", "xyz"], vec!["info"]]);
+        assert_eq!(split("convert a.cif -e xyz b.cif"), [vec!["convert", "a.cif", "-e", "xyz", "b.cif"]]);
+        // optional positional values are taken only when valid
+        assert_eq!(
+            split("load a.cif sort by element write b.vasp"),
+            [vec!["load", "a.cif"], vec!["sort", "by", "element"], vec!["write", "b.vasp"]]
+        );
+        assert_eq!(split("sort by distance-to 3 write"), [vec!["sort", "by", "distance-to", "3"], vec!["write"]]);
+        assert_eq!(split("delete 1 write"), [vec!["delete", "1"], vec!["write"]]);
+        assert_eq!(split("delete write"), [vec!["delete"], vec!["write"]]);
+        assert_eq!(split("substitute Au write"), [vec!["substitute", "Au"], vec!["write"]]);
+        assert_eq!(split("substitute Pt->Au 25% write"), [vec!["substitute", "Pt->Au", "25%"], vec!["write"]]);
+        assert_eq!(split("measure 1 2 info"), [vec!["measure", "1", "2"], vec!["info"]]);
+        assert_eq!(split("create-bounding-box info"), [vec!["create-bounding-box"], vec!["info"]]);
+        // explicit separator
+        assert_eq!(split("load a.cif write ; info"), [vec!["load", "a.cif"], vec!["write"], vec!["info"]]);
+
+        let args = vec!["a.cif".to_owned()];
+        assert!(split_commands(&args).is_err());
+
+        // commands in a single argument
+        let args = vec![r#"load - --format xyz; write "a;b.xyz";info"#.to_owned()];
+        assert_eq!(
+            split_commands(&args).unwrap(),
+            [vec!["load", "-", "--format", "xyz"], vec!["write", "a;b.xyz"], vec!["info"]]
+        );
+    }
+
+    #[test]
+    fn test_tokenize() -> Result<()> {
+        assert_eq!(tokenize(r#"load 'a b.xyz'; write a\ b.vasp"#)?, ["load", "a b.xyz", ";", "write", "a b.vasp"]);
+        assert_eq!(tokenize(r#"write "a\"b";"#)?, ["write", "a\"b", ";"]);
+        assert_eq!(tokenize("x '' y")?, ["x", "", "y"]);
+        assert!(tokenize("write 'a.xyz").is_err());
+        Ok(())
+    }
+}
+// 9d4e6b1f ends here
@@ -0,0 +1,442 @@
+// [[file:../gosh.note::95bf9210][95bf9210]]
+use super::*;
+use crate::cli::{Commander, GoshCmd};
+
+use gut::cli::*;
+use std::collections::HashMap;
+// 95bf9210 ends here
+
+// [[file:../gosh.note::192d9394][192d9394]]
+/// A statement in gosh script
+#[derive(Debug, Clone)]
+enum Stmt {
+    /// A gosh command. Failure is allowed when the command is prefixed with
+    /// `try`.
+    Command { line: String, lineno: usize, allow_fail: bool },
+    /// set name = value
+    Set { name: String, value: String, lineno: usize },
+    /// for var in items ... end
+    For { var: String, items: String, body: Vec<Stmt>, lineno: usize },
+    /// if cond ... else ... end
+    If {
+        cond: String,
+        then: Vec<Stmt>,
+        otherwise: Vec<Stmt>,
+        lineno: usize,
+    },
+}
+
+/// Parse statements from `lines` until reaching one of `terminators`. Return
+/// the parsed statements and the terminator found.
+fn parse_block<'a, I>(lines: &mut I, terminators: &[&str]) -> Result<(Vec<Stmt>, Option<String>)>
+where
+    I: Iterator<Item = (usize, &'a str)>,
+{
+    let mut stmts = vec![];
+    while let Some((lineno, line)) = lines.next() {
+        let line = line.trim();
+        // skip empty lines and comments
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (keyword, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let rest = rest.trim();
+        if terminators.contains(&keyword) {
+            return Ok((stmts, Some(keyword.to_owned())));
+        }
+        match keyword {
+            "set" => {
+                let (name, value) = rest
+                    .split_once('=')
+                    .ok_or_else(|| format_err!("line {}: invalid set statement: {:?}", lineno, line))?;
+                stmts.push(Stmt::Set {
+                    name: name.trim().to_owned(),
+                    value: value.trim().to_owned(),
+                    lineno,
+                });
+            }
+            "for" => {
+                let (var, items) = rest
+                    .split_once(" in ")
+                    .ok_or_else(|| format_err!("line {}: invalid for statement: {:?}", lineno, line))?;
+                let (body, end) = parse_block(lines, &["end"])?;
+                if end.is_none() {
+                    bail!("line {}: for statement without end", lineno);
+                }
+                stmts.push(Stmt::For {
+                    var: var.trim().to_owned(),
+                    items: items.trim().to_owned(),
+                    body,
+                    lineno,
+                });
+            }
+            "if" => {
+                let (then, end) = parse_block(lines, &["else", "end"])?;
+                let otherwise = match end.as_deref() {
+                    Some("else") => {
+                        let (otherwise, end) = parse_block(lines, &["end"])?;
+                        if end.is_none() {
+                            bail!("line {}: if statement without end", lineno);
+                        }
+                        otherwise
+                    }
+                    Some(_) => vec![],
+                    None => bail!("line {}: if statement without end", lineno),
+                };
+                stmts.push(Stmt::If {
+                    cond: rest.to_owned(),
+                    then,
+                    otherwise,
+                    lineno,
+                });
+            }
+            "try" => stmts.push(Stmt::Command {
+                line: rest.to_owned(),
+                lineno,
+                allow_fail: true,
+            }),
+            "end" | "else" => bail!("line {}: unexpected {:?}", lineno, keyword),
+            _ => stmts.push(Stmt::Command {
+                line: line.to_owned(),
+                lineno,
+                allow_fail: false,
+            }),
+        }
+    }
+
+    Ok((stmts, None))
+}
+
+fn parse_script(script: &str) -> Result<Vec<Stmt>> {
+    let mut lines = script.lines().enumerate().map(|(i, line)| (i + 1, line));
+    let (stmts, _) = parse_block(&mut lines, &[])?;
+    Ok(stmts)
+}
+// 192d9394 ends here
+
+// [[file:../gosh.note::3c969a78][3c969a78]]
+/// Interpreter for gosh scripts with variables, loops and conditionals.
+pub struct ScriptRunner<'a> {
+    commander: &'a mut Commander,
+    vars: HashMap<String, String>,
+}
+
+impl<'a> ScriptRunner<'a> {
+    pub fn new(commander: &'a mut Commander) -> Self {
+        Self {
+            commander,
+            vars: HashMap::new(),
+        }
+    }
+
+    /// Run script from string.
+    pub fn run_script(&mut self, script: &str) -> Result<()> {
+        let stmts = parse_script(script)?;
+        self.run_block(&stmts)?;
+        Ok(())
+    }
+
+    /// Run script from file.
+    pub fn run_script_file(&mut self, path: &Path) -> Result<()> {
+        let script = gut::fs::read_file(path)?;
+        self.run_script(&script)
+    }
+
+    /// Replace variables in `s` with their values.
+    fn substitute(&self, s: &str) -> Result<String> {
+        substitute(s, &self.vars)
+    }
+
+    /// Split `line` into words like shell, and then replace variables in
+    /// each word. Variable values are never split into words.
+    fn substitute_words(&self, line: &str) -> Result<Vec<String>> {
+        let words = shlex::split(line).ok_or_else(|| format_err!("invalid command line: {:?}", line))?;
+        words.iter().map(|w| self.substitute(w)).collect()
+    }
+
+    /// Run statements in block. Return Ok(true) when script should quit.
+    fn run_block(&mut self, stmts: &[Stmt]) -> Result<bool> {
+        for stmt in stmts {
+            match stmt {
+                Stmt::Set { name, value, lineno } => {
+                    let value = self.substitute(value).with_context(|| format!("line {}", lineno))?;
+                    self.vars.insert(name.to_owned(), value);
+                }
+                Stmt::Command { line, lineno, allow_fail } => {
+                    let args = self.substitute_words(line).with_context(|| format!("line {}", lineno))?;
+                    match self.run_command(args) {
+                        Ok(true) => return Ok(true),
+                        Ok(false) => {}
+                        Err(e) if *allow_fail => {
+                            eprintln!("line {}: command failed (ignored): {}\n{:?}", lineno, line, e);
+                        }
+                        Err(e) => return Err(e.context(format!("line {}: command failed: {}", lineno, line))),
+                    }
+                }
+                Stmt::For { var, items, body, lineno } => {
+                    let items = self.substitute_words(items).with_context(|| format!("line {}", lineno))?;
+                    for item in expand_items(&items).with_context(|| format!("line {}", lineno))? {
+                        self.vars.insert(var.to_owned(), item);
+                        if self.run_block(body)? {
+                            return Ok(true);
+                        }
+                    }
+                }
+                Stmt::If {
+                    cond,
+                    then,
+                    otherwise,
+                    lineno,
+                } => {
+                    let tokens = self.substitute_words(cond).with_context(|| format!("line {}", lineno))?;
+                    let ok = self.eval_tokens(&tokens).with_context(|| format!("line {}", lineno))?;
+                    let block = if ok { then } else { otherwise };
+                    if self.run_block(block)? {
+                        return Ok(true);
+                    }
+                }
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Run a single command with arguments `args`. Return Ok(true) for quit
+    /// command.
+    fn run_command(&mut self, args: Vec<String>) -> Result<bool> {
+        let cmd = GoshCmd::try_parse_from(std::iter::once("gosh".to_owned()).chain(args))?;
+        self.run_cmd(cmd)
+    }
+
+    /// Run a parsed command. Return Ok(true) for quit command.
+    pub fn run_cmd(&mut self, cmd: GoshCmd) -> Result<bool> {
+        match cmd {
+            GoshCmd::Quit {} => return Ok(true),
+            GoshCmd::Help {} => {
+                let mut app = GoshCmd::command();
+                app.print_help()?;
+                println!("");
+            }
+            cmd => self.commander.action(&cmd)?,
+        }
+
+        Ok(false)
+    }
+
+    /// Evaluate condition in `tokens` for if statement.
+    ///
+    /// Supported conditions:
+    /// - exists <path>
+    /// - <lhs> <op> <rhs>, where op is one of ==, !=, <, <=, >, >=. The
+    ///   special names `natoms` and `nmols` refer to the number of atoms in
+    ///   the first active molecule and the number of active molecules.
+    /// - not <condition>
+    fn eval_tokens(&self, tokens: &[String]) -> Result<bool> {
+        let tokens = tokens.iter().map(|s| s.as_str()).collect_vec();
+        match tokens.as_slice() {
+            ["not", rest @ ..] | ["!", rest @ ..] => {
+                let rest = rest.iter().map(|s| s.to_string()).collect_vec();
+                Ok(!self.eval_tokens(&rest)?)
+            }
+            ["exists", path] => Ok(Path::new(path).exists()),
+            ["true"] => Ok(true),
+            ["false"] => Ok(false),
+            [lhs, op, rhs] => {
+                let lhs = self.resolve_operand(lhs);
+                let rhs = self.resolve_operand(rhs);
+                match (lhs.parse::<f64>(), rhs.parse::<f64>()) {
+                    (Ok(x), Ok(y)) => match *op {
+                        "==" => Ok(x == y),
+                        "!=" => Ok(x != y),
+                        "<" => Ok(x < y),
+                        "<=" => Ok(x <= y),
+                        ">" => Ok(x > y),
+                        ">=" => Ok(x >= y),
+                        _ => bail!("invalid operator: {:?}", op),
+                    },
+                    _ => match *op {
+                        "==" => Ok(lhs == rhs),
+                        "!=" => Ok(lhs != rhs),
+                        _ => bail!("invalid operator for strings: {:?}", op),
+                    },
+                }
+            }
+            _ => bail!("invalid condition: {:?}", tokens.join(" ")),
+        }
+    }
+
+    fn resolve_operand(&self, s: &str) -> String {
+        match s {
+            "natoms" => self.commander.molecules.first().map_or(0, |m| m.natoms()).to_string(),
+            "nmols" => self.commander.molecules.len().to_string(),
+            _ => s.to_owned(),
+        }
+    }
+}
+
+/// Replace `$name` or `${name}` in `s` with values in `vars`.
+fn substitute(s: &str, vars: &HashMap<String, String>) -> Result<String> {
+    let mut out = String::new();
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '$' {
+            out.push(c);
+            continue;
+        }
+        let mut name = String::new();
+        if chars.peek() == Some(&'{') {
+            chars.next();
+            loop {
+                match chars.next() {
+                    Some('}') => break,
+                    Some(c) => name.push(c),
+                    None => bail!("unterminated variable: ${{{}", name),
+                }
+            }
+            if name.is_empty() {
+                bail!("empty variable name: ${{}}");
+            }
+        } else {
+            while let Some(&c) = chars.peek() {
+                if c.is_alphanumeric() || c == '_' {
+                    name.push(c);
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+        }
+        if name.is_empty() {
+            out.push('$');
+        } else {
+            let value = vars.get(&name).ok_or_else(|| format_err!("undefined variable: {}", name))?;
+            out.push_str(value);
+        }
+    }
+
+    Ok(out)
+}
+
+/// Expand `items` in for loop. Items containing wildcards will be expanded
+/// as glob patterns.
+fn expand_items(items: &[String]) -> Result<Vec<String>> {
+    let mut expanded = vec![];
+    for w in items {
+        if w.contains(|c| c == '*' || c == '?' || c == '[') {
+            let n = expanded.len();
+            for path in glob::glob(w)? {
+                expanded.push(path?.to_string_lossy().into_owned());
+            }
+            if expanded.len() == n {
+                eprintln!("Warning: no files match pattern {:?}", w);
+            }
+        } else {
+            expanded.push(w.to_owned());
+        }
+    }
+
+    Ok(expanded)
+}
+// 3c969a78 ends here
+
+// [[file:../gosh.note::e3a5c7d9][e3a5c7d9]]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_substitute() -> Result<()> {
+        let vars: HashMap<String, String> = [("x", "a b"), ("n", "3")].iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        assert_eq!(substitute("$x", &vars)?, "a b");
+        assert_eq!(substitute("${n}0.xyz", &vars)?, "30.xyz");
+        assert_eq!(substitute("$n.vasp", &vars)?, "3.vasp");
+        assert_eq!(substitute("cost $", &vars)?, "cost $");
+        assert!(substitute("${n", &vars).is_err());
+        assert!(substitute("${}", &vars).is_err());
+        assert!(substitute("$y", &vars).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_script() -> Result<()> {
+        let script = "
+# comment
+set vacuum = 15
+for f in *.cif
+  load $f
+  if natoms > 200
+    try supercell 1 1 1
+  else
+    write ${f}.vasp
+  end
+end
+";
+        let stmts = parse_script(script)?;
+        assert_eq!(stmts.len(), 2);
+        assert!(matches!(&stmts[0], Stmt::Set { name, value, lineno: 3 } if name == "vacuum" && value == "15"));
+        match &stmts[1] {
+            Stmt::For { var, items, body, .. } => {
+                assert_eq!(var, "f");
+                assert_eq!(items, "*.cif");
+                assert_eq!(body.len(), 2);
+                match &body[1] {
+                    Stmt::If { cond, then, otherwise, .. } => {
+                        assert_eq!(cond, "natoms > 200");
+                        assert!(matches!(&then[0], Stmt::Command { allow_fail: true, .. }));
+                        assert!(matches!(&otherwise[0], Stmt::Command { allow_fail: false, lineno: 9, .. }));
+                    }
+                    _ => panic!("expected if statement"),
+                }
+            }
+            _ => panic!("expected for statement"),
+        }
+
+        assert!(parse_script("for i in 1 2\n  load $i\n").is_err());
+        assert!(parse_script("if true\nelse\n").is_err());
+        assert!(parse_script("end").is_err());
+        assert!(parse_script("set x 1").is_err());
+        assert!(parse_script("for i 1 2\nend").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_script() -> Result<()> {
+        let mut commander = Commander::new();
+        let mut runner = ScriptRunner::new(&mut commander);
+        let script = "
+set acc =
+for i in 1 2 3
+  set acc = ${acc}$i
+end
+set f = my file.xyz
+if $f == \"my file.xyz\"
+  set r = yes
+else
+  set r = no
+end
+if not nmols > 0
+  set empty = true
+end
+";
+        runner.run_script(script)?;
+        assert_eq!(runner.vars["acc"], "123");
+        assert_eq!(runner.vars["r"], "yes");
+        assert_eq!(runner.vars["empty"], "true");
+
+        assert!(runner.run_script("set x = $undefined").is_err());
+        assert!(runner.run_script("if 1 ~ 2\nend").is_err());
+        assert!(runner.run_script("no-such-command").is_err());
+        runner.run_script("try no-such-command")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_expand_items() -> Result<()> {
+        let items = ["a.xyz", "b c.xyz"].map(|s| s.to_owned());
+        assert_eq!(expand_items(&items)?, items);
+        let items = ["/nonexistent-gosh-dir/*.cif".to_owned()];
+        assert!(expand_items(&items)?.is_empty());
+        Ok(())
+    }
+}
+// e3a5c7d9 ends here
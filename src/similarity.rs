@@ -0,0 +1,275 @@
+// [[file:../gosh.note::c51f8e27][c51f8e27]]
+use super::*;
+use crate::geometry::mic_vector;
+use crate::info::get_energy;
+
+use gchemol::Molecule;
+use vecfx::nalgebra::Matrix3;
+use vecfx::*;
+// c51f8e27 ends here
+
+// [[file:../gosh.note::e6d09b3a][e6d09b3a]]
+fn centered(positions: &[Vector3f]) -> Vec<Vector3f> {
+    let n = positions.len() as f64;
+    let c = positions.iter().fold(Vector3f::zeros(), |acc, p| acc + p) / n;
+    positions.iter().map(|p| p - c).collect()
+}
+
+/// Return the rotation best aligning centered positions `q` onto `p` using
+/// Kabsch algorithm.
+fn kabsch(p: &[Vector3f], q: &[Vector3f]) -> Matrix3<f64> {
+    let h = q.iter().zip(p).fold(Matrix3::zeros(), |acc, (qi, pi)| acc + qi * pi.transpose());
+    let svd = h.svd(true, true);
+    let (u, v_t) = (svd.u.unwrap(), svd.v_t.unwrap());
+    let d = (v_t.transpose() * u.transpose()).determinant().signum();
+    v_t.transpose() * Matrix3::from_diagonal(&Vector3f::new(1.0, 1.0, d)) * u.transpose()
+}
+
+fn rmsd_of(p: &[Vector3f], q: &[Vector3f]) -> f64 {
+    let sum: f64 = p.iter().zip(q).map(|(a, b)| (a - b).norm_squared()).sum();
+    (sum / p.len() as f64).sqrt()
+}
+
+/// Match atoms of the same element greedily by distance. Return the index
+/// in `q` for each atom in `p`. For periodic `mol`, distances follow the
+/// minimum image convention.
+fn greedy_assignment(mol: &Molecule, symbols: &[&str], p: &[Vector3f], q: &[Vector3f]) -> Vec<usize> {
+    let mut pairs = vec![];
+    for i in 0..p.len() {
+        for j in 0..q.len() {
+            if symbols[i] == symbols[j] {
+                pairs.push((mic_vector(mol, p[i], q[j]).norm(), i, j));
+            }
+        }
+    }
+    pairs.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let mut assigned = vec![None; p.len()];
+    let mut used = vec![false; q.len()];
+    for (_, i, j) in pairs {
+        if assigned[i].is_none() && !used[j] {
+            assigned[i] = Some(j);
+            used[j] = true;
+        }
+    }
+    assigned.into_iter().map(|x| x.unwrap()).collect()
+}
+
+/// Principal axes of centered positions as columns of a proper rotation
+/// matrix.
+fn principal_axes(positions: &[Vector3f]) -> Matrix3<f64> {
+    let mut inertia = Matrix3::zeros();
+    for r in positions {
+        inertia += r.norm_squared() * Matrix3::identity() - r * r.transpose();
+    }
+    let mut axes = inertia.symmetric_eigen().eigenvectors;
+    if axes.determinant() < 0.0 {
+        let c = -axes.column(2);
+        axes.set_column(2, &c);
+    }
+    axes
+}
+
+/// Return the RMSD between `mol1` and `mol2` after optimal alignment. Atoms
+/// are compared in order unless `permute` is true, in which case atoms of
+/// the same element are matched for minimum RMSD. For periodic systems,
+/// displacements follow the minimum image convention without rotation.
+/// Return None if the two molecules have different compositions.
+pub(crate) fn rmsd(mol1: &Molecule, mol2: &Molecule, permute: bool) -> Option<f64> {
+    let symbols1 = mol1.atoms().map(|(_, a)| a.symbol()).collect_vec();
+    let symbols2 = mol2.atoms().map(|(_, a)| a.symbol()).collect_vec();
+    if symbols1.len() != symbols2.len() || symbols1.iter().sorted().ne(symbols2.iter().sorted()) {
+        return None;
+    }
+    if !permute && symbols1 != symbols2 {
+        return None;
+    }
+    let p: Vec<Vector3f> = mol1.positions().map(|x| x.into()).collect();
+    let q: Vec<Vector3f> = mol2.positions().map(|x| x.into()).collect();
+
+    if mol1.lattice.is_some() {
+        // remove overall translation
+        let mut d = p.iter().zip(q.iter()).map(|(a, b)| mic_vector(mol1, *a, *b)).collect_vec();
+        if permute {
+            let order = greedy_assignment(mol1, &symbols1, &p, &q);
+            d = p.iter().zip(order).map(|(a, j)| mic_vector(mol1, *a, q[j])).collect_vec();
+        }
+        let d = centered(&d);
+        let zeros = vec![Vector3f::zeros(); d.len()];
+        return Some(rmsd_of(&zeros, &d));
+    }
+
+    let p = centered(&p);
+    let q = centered(&q);
+    if !permute {
+        let r = kabsch(&p, &q);
+        let q = q.iter().map(|x| r * x).collect_vec();
+        return Some(rmsd_of(&p, &q));
+    }
+
+    // try initial alignments of principal axes, then refine atom matching
+    let ap = principal_axes(&p);
+    let aq = principal_axes(&q);
+    let mut best = f64::MAX;
+    for (s1, s2) in [(1.0, 1.0), (1.0, -1.0), (-1.0, 1.0), (-1.0, -1.0)] {
+        let flip = Matrix3::from_diagonal(&Vector3f::new(s1, s2, s1 * s2));
+        let r0 = ap * flip * aq.transpose();
+        let mut qr = q.iter().map(|x| r0 * x).collect_vec();
+        for _ in 0..5 {
+            let order = greedy_assignment(mol1, &symbols1, &p, &qr);
+            let qo = order.iter().map(|&j| q[j]).collect_vec();
+            let r = kabsch(&p, &qo);
+            qr = q.iter().map(|x| r * x).collect_vec();
+            let qo = order.iter().map(|&j| qr[j]).collect_vec();
+            best = best.min(rmsd_of(&p, &qo));
+        }
+    }
+    Some(best)
+}
+// e6d09b3a ends here
+
+// [[file:../gosh.note::48b2f9d1][48b2f9d1]]
+/// Compute RMSD between all pairs in `mols`.
+pub(crate) fn rmsd_matrix(mols: &[Molecule], permute: bool) -> Vec<Vec<Option<f64>>> {
+    let n = mols.len();
+    let mut matrix = vec![vec![Some(0.0); n]; n];
+    for i in 0..n {
+        for j in 0..i {
+            let x = rmsd(&mols[i], &mols[j], permute);
+            matrix[i][j] = x;
+            matrix[j][i] = x;
+        }
+    }
+    matrix
+}
+
+/// Find duplicates in `mols`. A structure is a duplicate of an earlier one
+/// if their RMSD is below `rmsd_tol` and, when both energies are
+/// available, their energy difference is below `energy_tol`. Return for
+/// each structure the index of the structure it duplicates.
+pub(crate) fn find_duplicates(mols: &[Molecule], rmsd_tol: f64, energy_tol: f64, permute: bool) -> Vec<Option<usize>> {
+    let energies = mols.iter().map(get_energy).collect_vec();
+    let mut kept: Vec<usize> = vec![];
+    let mut duplicates = vec![None; mols.len()];
+    for i in 0..mols.len() {
+        let found = kept.iter().copied().find(|&j| {
+            if let (Some(ei), Some(ej)) = (energies[i], energies[j]) {
+                if (ei - ej).abs() > energy_tol {
+                    return false;
+                }
+            }
+            rmsd(&mols[i], &mols[j], permute).map_or(false, |x| x < rmsd_tol)
+        });
+        match found {
+            Some(j) => duplicates[i] = Some(j),
+            None => kept.push(i),
+        }
+    }
+    duplicates
+}
+
+/// Group `mols` into clusters using single linkage with RMSD threshold
+/// `rmsd_tol`. Return the cluster index (starting from 0) of each
+/// structure, numbered in order of first appearance.
+pub(crate) fn cluster(mols: &[Molecule], rmsd_tol: f64, permute: bool) -> Vec<usize> {
+    let n = mols.len();
+    let mut labels: Vec<Option<usize>> = vec![None; n];
+    let mut ncluster = 0;
+    for i in 0..n {
+        if labels[i].is_some() {
+            continue;
+        }
+        labels[i] = Some(ncluster);
+        let mut stack = vec![i];
+        while let Some(k) = stack.pop() {
+            for j in 0..n {
+                if labels[j].is_none() && rmsd(&mols[k], &mols[j], permute).map_or(false, |x| x < rmsd_tol) {
+                    labels[j] = Some(ncluster);
+                    stack.push(j);
+                }
+            }
+        }
+        ncluster += 1;
+    }
+    labels.into_iter().map(|x| x.unwrap()).collect()
+}
+// 48b2f9d1 ends here
+
+// [[file:../gosh.note::d8a1c6f4][d8a1c6f4]]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gchemol::{Atom, Lattice};
+
+    // a distorted water without any symmetry
+    fn water() -> Molecule {
+        Molecule::from_atoms([
+            Atom::new("O", [0.0, 0.0, 0.1173]),
+            Atom::new("H", [0.0, 0.7572, -0.4692]),
+            Atom::new("H", [0.0, -0.9, -0.3]),
+        ])
+    }
+
+    fn rotation() -> Matrix3<f64> {
+        let (c, s) = (0.6f64.cos(), 0.6f64.sin());
+        let rz = Matrix3::new(c, -s, 0.0, s, c, 0.0, 0.0, 0.0, 1.0);
+        let rx = Matrix3::new(1.0, 0.0, 0.0, 0.0, c, -s, 0.0, s, c);
+        rz * rx
+    }
+
+    #[test]
+    fn test_kabsch() {
+        let p: Vec<Vector3f> = water().positions().map(|x| x.into()).collect();
+        let p = centered(&p);
+        let r0 = rotation();
+        let q = p.iter().map(|x| r0 * x).collect_vec();
+        let r = kabsch(&p, &q);
+        assert!((r * r0 - Matrix3::identity()).norm() < 1e-8);
+        assert_relative_eq!(r.determinant(), 1.0, epsilon = 1e-8);
+        let q = q.iter().map(|x| r * x).collect_vec();
+        assert_relative_eq!(rmsd_of(&p, &q), 0.0, epsilon = 1e-8);
+    }
+
+    #[test]
+    fn test_rmsd() {
+        let mol1 = water();
+        // rotated, translated and with H atoms swapped
+        let r = rotation();
+        let t = Vector3f::new(1.0, 2.0, 3.0);
+        let positions = mol1.positions().map(|x| -> [f64; 3] { (r * Vector3f::from(x) + t).into() }).collect_vec();
+        let mut mol2 = mol1.clone();
+        mol2.set_positions(positions.clone());
+        assert_relative_eq!(rmsd(&mol1, &mol2, false).unwrap(), 0.0, epsilon = 1e-6);
+
+        mol2.set_positions([positions[0], positions[2], positions[1]]);
+        assert!(rmsd(&mol1, &mol2, false).unwrap() > 0.1);
+        assert_relative_eq!(rmsd(&mol1, &mol2, true).unwrap(), 0.0, epsilon = 1e-6);
+
+        let oh = Molecule::from_atoms([Atom::new("O", [0.0; 3]), Atom::new("H", [1.0, 0.0, 0.0])]);
+        assert!(rmsd(&mol1, &oh, true).is_none());
+
+        let matrix = rmsd_matrix(&[mol1.clone(), mol2.clone(), oh], true);
+        assert_relative_eq!(matrix[0][1].unwrap(), 0.0, epsilon = 1e-6);
+        assert!(matrix[0][2].is_none());
+        assert_eq!(cluster(&[mol1.clone(), water(), mol2], 0.1, true), [0, 0, 0]);
+    }
+
+    #[test]
+    fn test_rmsd_periodic() {
+        let lat = Lattice::new([[10.0, 0.0, 0.0], [0.0, 10.0, 0.0], [0.0, 0.0, 10.0]]);
+        let mut mol1 = Molecule::from_atoms([Atom::new("Cu", [0.1, 5.0, 5.0]), Atom::new("Cu", [5.0, 5.0, 5.0])]);
+        mol1.set_lattice(lat);
+        // the first atom moves across the cell boundary
+        let mut mol2 = mol1.clone();
+        mol2.set_positions([[9.9, 5.0, 5.0], [5.0, 5.0, 5.0]]);
+        assert_relative_eq!(rmsd(&mol1, &mol2, false).unwrap(), 0.1, epsilon = 1e-6);
+
+        // the nearest image of atom 1 is atom 2 in mol2
+        let p: Vec<Vector3f> = mol1.positions().map(|x| x.into()).collect();
+        let q = vec![Vector3f::new(5.0, 5.0, 5.0), Vector3f::new(9.9, 5.0, 5.0)];
+        assert_eq!(greedy_assignment(&mol1, &["Cu", "Cu"], &p, &q), [1, 0]);
+        mol2.set_positions([[5.0, 5.0, 5.0], [9.9, 5.0, 5.0]]);
+        assert_relative_eq!(rmsd(&mol1, &mol2, true).unwrap(), 0.1, epsilon = 1e-6);
+    }
+}
+// d8a1c6f4 ends here
@@ -0,0 +1,354 @@
+// [[file:../gosh.note::caf00387][caf00387]]
+use super::*;
+
+use gchemol::{Atom, Lattice, Molecule};
+use vecfx::nalgebra::Matrix3;
+use vecfx::*;
+// caf00387 ends here
+
+// [[file:../gosh.note::7c991bd0][7c991bd0]]
+/// Options for cutting a surface slab from bulk crystal.
+#[derive(Debug, Clone)]
+pub(crate) struct SlabOptions {
+    /// The number of repeated unit layers along surface normal.
+    pub layers: usize,
+    /// The vacuum thickness in Angstrom.
+    pub vacuum: f64,
+    /// The index of atomic layer as the bottom termination.
+    pub termination: usize,
+    /// Search for an orthogonal in-plane supercell.
+    pub orthogonal: bool,
+    /// The number of bottom atomic layers to be frozen.
+    pub freeze: usize,
+}
+
+fn floor_div(a: i64, b: i64) -> i64 {
+    let d = a / b;
+    if a % b != 0 && ((a < 0) != (b < 0)) {
+        d - 1
+    } else {
+        d
+    }
+}
+
+fn gcd(a: i64, b: i64) -> i64 {
+    if b == 0 {
+        a.abs()
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+/// Extended Euclidean algorithm: return (x, y) satisfying a*x + b*y = gcd(a, b)
+fn ext_gcd(a: i64, b: i64) -> (i64, i64) {
+    if b == 0 {
+        (1, 0)
+    } else if a - b * floor_div(a, b) == 0 {
+        (0, 1)
+    } else {
+        let (x, y) = ext_gcd(b, a - b * floor_div(a, b));
+        (y, x - y * floor_div(a, b))
+    }
+}
+
+/// Find an unimodular basis of bulk lattice (given as cell vectors `cell`)
+/// with the first two vectors lying in the (hkl) plane.
+fn surface_basis(cell: &[Vector3f; 3], hkl: [i64; 3]) -> [[i64; 3]; 3] {
+    let [h, k, l] = hkl;
+    match (h == 0, k == 0, l == 0) {
+        (false, true, true) => [[0, 1, 0], [0, 0, 1], [1, 0, 0]],
+        (true, false, true) => [[0, 0, 1], [1, 0, 0], [0, 1, 0]],
+        (true, true, false) => [[1, 0, 0], [0, 1, 0], [0, 0, 1]],
+        _ => {
+            let (mut p, mut q) = ext_gcd(k, l);
+            let [a1, a2, a3] = *cell;
+            // make the first two vectors as orthogonal as possible
+            let v = l as f64 * a2 - k as f64 * a3;
+            let k1 = (p as f64 * (k as f64 * a1 - h as f64 * a2) + q as f64 * (l as f64 * a1 - h as f64 * a3)).dot(&v);
+            let k2 = (l as f64 * (k as f64 * a1 - h as f64 * a2) - k as f64 * (l as f64 * a1 - h as f64 * a3)).dot(&v);
+            if k2.abs() > 1e-10 {
+                let i = -(k1 / k2).round() as i64;
+                p += i * l;
+                q -= i * k;
+            }
+            let (a, b) = ext_gcd(p * k + q * l, h);
+            let g = gcd(l, k);
+            [[p * k + q * l, -p * h, -q * h], [0, l / g, -k / g], [b, a * p, a * q]]
+        }
+    }
+}
+// 7c991bd0 ends here
+
+// [[file:../gosh.note::824f1ef8][824f1ef8]]
+/// Return fractional coordinates of cartesian `positions` in cell formed by
+/// vectors `cell`.
+fn to_frac_in(cell: &[Vector3f; 3], positions: &[Vector3f]) -> Result<Vec<Vector3f>> {
+    let m = Matrix3::from_columns(cell);
+    let inv = m.try_inverse().ok_or_else(|| format_err!("invalid cell: {:?}", cell))?;
+    Ok(positions.iter().map(|p| inv * p).collect())
+}
+
+/// Reduce in-plane cell vectors `a` and `b` using Gauss reduction.
+fn gauss_reduce(mut a: Vector3f, mut b: Vector3f) -> (Vector3f, Vector3f) {
+    loop {
+        if b.norm() < a.norm() {
+            std::mem::swap(&mut a, &mut b);
+        }
+        let m = (a.dot(&b) / a.norm_squared()).round();
+        if m == 0.0 {
+            break;
+        }
+        b -= m * a;
+    }
+    (a, b)
+}
+
+/// Search for in-plane vector `v = i*a + j*b` perpendicular to `a`. Return
+/// `v` and the multiplicity `j` of the new cell.
+fn find_orthogonal(a: Vector3f, b: Vector3f) -> Option<(Vector3f, usize)> {
+    let mut found: Option<(Vector3f, usize)> = None;
+    for j in 1..=4 {
+        for i in -4..=4 {
+            let v = i as f64 * a + j as f64 * b;
+            if v.dot(&a).abs() < 1e-3 * v.norm() * a.norm() {
+                match found {
+                    Some((u, _)) if u.norm() <= v.norm() => {}
+                    _ => found = Some((v, j as usize)),
+                }
+            }
+        }
+    }
+    found
+}
+
+/// Group `values` into layers separated by more than `tol`. Return the layer
+/// index of each value, starting from 0 for the lowest layer.
+pub(crate) fn cluster_layers(values: &[f64], tol: f64) -> Vec<usize> {
+    let order = (0..values.len()).sorted_by(|&i, &j| values[i].total_cmp(&values[j])).collect_vec();
+    let mut layers = vec![0; values.len()];
+    let mut ilayer = 0;
+    for w in 1..order.len() {
+        if values[order[w]] - values[order[w - 1]] > tol {
+            ilayer += 1;
+        }
+        layers[order[w]] = ilayer;
+    }
+    layers
+}
+
+/// Return atoms in the bottom `nlayers` layers of `mol` along z direction.
+/// Atoms with z coordinates differing less than `tol` are in the same layer.
+pub(crate) fn bottom_layer_atoms(mol: &Molecule, nlayers: usize, tol: f64) -> Vec<usize> {
+    let zs = mol.positions().map(|p| p[2]).collect_vec();
+    let layers = cluster_layers(&zs, tol);
+    mol.numbers().zip(layers).filter_map(|(n, l)| if l < nlayers { Some(n) } else { None }).collect()
+}
+// 824f1ef8 ends here
+
+// [[file:../gosh.note::23b3564f][23b3564f]]
+/// Cut a surface slab with Miller indices `hkl` from `bulk` crystal.
+pub(crate) fn build_slab(bulk: &Molecule, hkl: [i64; 3], options: &SlabOptions) -> Result<Molecule> {
+    let lat = bulk.lattice.as_ref().ok_or_else(|| format_err!("not a periodic system!"))?;
+    if hkl == [0, 0, 0] {
+        bail!("invalid Miller indices: {:?}", hkl);
+    }
+    if options.layers == 0 {
+        bail!("the number of layers should be at least 1");
+    }
+
+    let cell: [Vector3f; 3] = [
+        lat.to_cart([1.0, 0.0, 0.0]).into(),
+        lat.to_cart([0.0, 1.0, 0.0]).into(),
+        lat.to_cart([0.0, 0.0, 1.0]).into(),
+    ];
+    let basis = surface_basis(&cell, hkl);
+    let [a1, a2, a3] = basis.map(|c| c[0] as f64 * cell[0] + c[1] as f64 * cell[1] + c[2] as f64 * cell[2]);
+
+    // reduce in-plane vectors, and keep the basis right-handed
+    let (a1, a2) = gauss_reduce(a1, a2);
+    let n = a1.cross(&a2).normalize();
+    let a3 = if a3.dot(&n) < 0.0 { -a3 } else { a3 };
+
+    let mut sources = bulk.numbers().collect_vec();
+    let atoms = bulk.atoms().map(|(_, a)| a.clone()).collect_vec();
+    let mut positions: Vec<Vector3f> = atoms.iter().map(|a| a.position().into()).collect();
+    let mut atoms = atoms;
+    let (a1, a2) = if options.orthogonal {
+        if let Some((v, j)) = find_orthogonal(a1, a2) {
+            // replicate atoms along a2 to fill the larger cell
+            let mut atoms_ = vec![];
+            let mut positions_ = vec![];
+            let mut sources_ = vec![];
+            for i in 0..j {
+                for ((a, p), &n) in atoms.iter().zip(positions.iter()).zip(sources.iter()) {
+                    atoms_.push(a.clone());
+                    positions_.push(p + i as f64 * a2);
+                    sources_.push(n);
+                }
+            }
+            atoms = atoms_;
+            positions = positions_;
+            sources = sources_;
+            (a1, v)
+        } else {
+            eprintln!("No orthogonal in-plane cell found.");
+            (a1, a2)
+        }
+    } else {
+        (a1, a2)
+    };
+
+    // wrap atoms into the oriented unit cell. The third vector is a true
+    // lattice vector, so periodic images are equivalent in bulk.
+    let cell = [a1, a2, a3];
+    let mut fcoords = to_frac_in(&cell, &positions)?;
+    for f in fcoords.iter_mut() {
+        *f = f.map(|x| x - (x + 1e-8).floor());
+    }
+
+    // choose the termination by shifting the given atomic layer to the bottom
+    let d = a3.dot(&n);
+    let fz = fcoords.iter().map(|f| f.z).collect_vec();
+    let layers = cluster_layers(&fz, 0.1 / d);
+    let nlayers = layers.iter().max().map_or(0, |x| x + 1);
+    if options.termination >= nlayers {
+        bail!("termination {} out of range: {} layers found", options.termination, nlayers);
+    }
+    let shift = layers
+        .iter()
+        .zip(fz.iter())
+        .filter_map(|(&l, &z)| if l == options.termination { Some(z) } else { None })
+        .fold(f64::MAX, f64::min);
+    for f in fcoords.iter_mut() {
+        f.z -= shift;
+        f.z -= (f.z + 1e-8).floor();
+    }
+
+    // stack unit layers along a3, then make the third cell vector
+    // perpendicular to the surface. Rotate a1 along x and the surface
+    // normal along z.
+    let e1 = a1.normalize();
+    let e2 = n.cross(&e1);
+    let rotated = |v: Vector3f| Vector3f::new(v.dot(&e1), v.dot(&e2), v.dot(&n));
+    let va = rotated(a1);
+    let vb = rotated(a2);
+    let height = d * options.layers as f64;
+    let vc = Vector3f::new(0.0, 0.0, height + options.vacuum);
+    let inplane = Matrix3::from_columns(&[va, vb, Vector3f::z()]).try_inverse().unwrap();
+
+    let mut slab = Molecule::new(&format!("{} ({}{}{}) slab", bulk.formula(), hkl[0], hkl[1], hkl[2]));
+    let mut serial = 0;
+    for i in 0..options.layers {
+        for (a, f) in atoms.iter().zip(fcoords.iter()) {
+            let p = rotated(f.x * a1 + f.y * a2 + (f.z + i as f64) * a3);
+            // wrap into the in-plane cell
+            let g = inplane * p;
+            let p = (g.x - (g.x + 1e-8).floor()) * va + (g.y - (g.y + 1e-8).floor()) * vb;
+            let p = p + Vector3f::new(0.0, 0.0, g.z + options.vacuum / 2.0);
+            let mut a: Atom = a.clone();
+            a.set_position(p.into());
+            serial += 1;
+            slab.add_atom(serial, a);
+        }
+    }
+    let tvs: [[f64; 3]; 3] = [va.into(), vb.into(), vc.into()];
+    slab.set_lattice(Lattice::new(tvs));
+
+    // freeze bottom layers
+    for n in bottom_layer_atoms(&slab, options.freeze, 0.1) {
+        slab.get_atom_mut(n).unwrap().set_freezing([true; 3]);
+    }
+    let sources = sources.repeat(options.layers);
+    crate::cell::carry_bonds(bulk, &mut slab, &sources, 1e-2);
+
+    Ok(slab)
+}
+// 23b3564f ends here
+
+// [[file:../gosh.note::5e0c7a21][5e0c7a21]]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::mic_vector;
+
+    fn fcc_bulk(a: f64) -> Molecule {
+        let atoms = [[0.0, 0.0, 0.0], [0.0, 0.5, 0.5], [0.5, 0.0, 0.5], [0.5, 0.5, 0.0]].map(|f| Atom::new("Cu", [f[0] * a, f[1] * a, f[2] * a]));
+        let mut mol = Molecule::from_atoms(atoms);
+        mol.set_lattice(Lattice::new([[a, 0.0, 0.0], [0.0, a, 0.0], [0.0, 0.0, a]]));
+        mol
+    }
+
+    fn min_distance(mol: &Molecule) -> f64 {
+        let positions: Vec<Vector3f> = mol.positions().map(|p| p.into()).collect();
+        positions
+            .iter()
+            .tuple_combinations()
+            .map(|(&pi, &pj)| mic_vector(mol, pi, pj).norm())
+            .fold(f64::MAX, f64::min)
+    }
+
+    #[test]
+    fn test_ext_gcd() {
+        for (a, b) in [(3, 5), (12, 18), (-4, 6), (7, -3), (0, 5), (5, 0), (1, 1)] {
+            let (x, y) = ext_gcd(a, b);
+            assert_eq!((a * x + b * y).abs(), gcd(a, b), "{} {}", a, b);
+        }
+    }
+
+    #[test]
+    fn test_surface_basis() {
+        let cell = [Vector3f::x(), Vector3f::y(), Vector3f::z()];
+        for hkl in [[1, 0, 0], [0, 1, 0], [0, 0, 1], [1, 1, 0], [1, 1, 1], [2, 1, 1], [3, 1, 0], [1, -1, 2]] {
+            let basis = surface_basis(&cell, hkl);
+            let m = Matrix3::from_fn(|i, j| basis[i][j] as f64);
+            assert_relative_eq!(m.determinant().abs(), 1.0, epsilon = 1e-8);
+            // the first two vectors lie in the (hkl) plane
+            for c in &basis[..2] {
+                assert_eq!(c[0] * hkl[0] + c[1] * hkl[1] + c[2] * hkl[2], 0, "{:?}", hkl);
+            }
+        }
+    }
+
+    #[test]
+    fn test_cluster_layers() {
+        let layers = cluster_layers(&[0.5, 0.0, 0.05, 1.0, 0.52], 0.1);
+        assert_eq!(layers, vec![1, 0, 0, 2, 1]);
+    }
+
+    #[test]
+    fn test_build_slab() -> Result<()> {
+        let a = 3.61;
+        let bulk = fcc_bulk(a);
+        let options = SlabOptions {
+            layers: 3,
+            vacuum: 10.0,
+            termination: 0,
+            orthogonal: false,
+            freeze: 1,
+        };
+        for hkl in [[1, 0, 0], [1, 1, 0], [1, 1, 1], [2, 1, 1]] {
+            let slab = build_slab(&bulk, hkl, &options)?;
+            assert_eq!(slab.natoms(), 12);
+            // the bulk structure is not distorted
+            assert_relative_eq!(min_distance(&slab), a / 2f64.sqrt(), epsilon = 1e-6);
+
+            let lat = slab.lattice.as_ref().unwrap();
+            let [va, vb, vc]: [Vector3f; 3] = [
+                lat.to_cart([1.0, 0.0, 0.0]).into(),
+                lat.to_cart([0.0, 1.0, 0.0]).into(),
+                lat.to_cart([0.0, 0.0, 1.0]).into(),
+            ];
+            assert_relative_eq!(va.z, 0.0, epsilon = 1e-8);
+            assert_relative_eq!(vb.z, 0.0, epsilon = 1e-8);
+            assert_relative_eq!(vc.x, 0.0, epsilon = 1e-8);
+            assert_relative_eq!(vc.y, 0.0, epsilon = 1e-8);
+            let zs = slab.positions().map(|p| p[2]).collect_vec();
+            let zmin = zs.iter().copied().fold(f64::MAX, f64::min);
+            assert_relative_eq!(zmin, 5.0, epsilon = 1e-6);
+            assert!(zs.iter().all(|&z| z < vc.z - 5.0 + 1e-6));
+            assert!(slab.atoms().any(|(_, a)| a.freezing() == [true; 3]));
+        }
+        Ok(())
+    }
+}
+// 5e0c7a21 ends here
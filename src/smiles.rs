@@ -0,0 +1,443 @@
+// [[file:../gosh.note::9b0d3f6e][9b0d3f6e]]
+use super::*;
+use crate::valence::{bond_key, perceive_bond_orders, BondOrder, BondOrders};
+
+use gchemol::Molecule;
+use std::collections::{HashMap, HashSet};
+// 9b0d3f6e ends here
+
+// [[file:../gosh.note::c2a87e14][c2a87e14]]
+/// The key in molecule properties for SMILES computed by `smiles` command.
+pub(crate) const SMILES_KEY: &str = "smiles";
+
+/// Elements in the organic subset, which can be written without brackets.
+const ORGANIC_SUBSET: &[&str] = &["B", "C", "N", "O", "P", "S", "F", "Cl", "Br", "I"];
+
+/// Elements which can be written as aromatic atoms in lowercase.
+const AROMATIC_ELEMENTS: &[&str] = &["B", "C", "N", "O", "P", "S", "Se", "As"];
+
+/// Normal valences for implicit hydrogens in SMILES.
+fn normal_valences(symbol: &str) -> &'static [usize] {
+    match symbol {
+        "B" => &[3],
+        "C" => &[4],
+        "N" | "P" => &[3, 5],
+        "O" => &[2],
+        "S" => &[2, 4, 6],
+        "F" | "Cl" | "Br" | "I" => &[1],
+        _ => &[],
+    }
+}
+
+/// Heavy atom graph of a molecule with hydrogens folded into their
+/// bonded heavy atoms.
+struct Graph<'a> {
+    mol: &'a Molecule,
+    orders: BondOrders,
+    /// Atoms to be written explicitly
+    atoms: Vec<usize>,
+    /// Number of hydrogens attached to each atom
+    nhydrogens: HashMap<usize, usize>,
+    /// Neighbors of each atom, excluding folded hydrogens
+    neighbors: HashMap<usize, Vec<usize>>,
+    /// Atoms in aromatic rings
+    aromatic: HashSet<usize>,
+}
+
+impl<'a> Graph<'a> {
+    fn new(mol: &'a Molecule) -> Self {
+        let orders = perceive_bond_orders(mol);
+        let symbol = |n: usize| mol.get_atom(n).unwrap().symbol();
+
+        // hydrogens bonded to a heavy atom are written implicitly
+        let mut nhydrogens: HashMap<usize, usize> = mol.numbers().map(|n| (n, 0)).collect();
+        let mut folded = HashSet::new();
+        for n in mol.numbers().filter(|&n| symbol(n) == "H") {
+            if let Some(j) = mol.connected(n).sorted().find(|&j| symbol(j) != "H") {
+                *nhydrogens.get_mut(&j).unwrap() += 1;
+                folded.insert(n);
+            }
+        }
+        let atoms = mol.numbers().filter(|n| !folded.contains(n)).collect_vec();
+        let neighbors = atoms
+            .iter()
+            .map(|&n| (n, mol.connected(n).filter(|j| !folded.contains(j)).sorted().collect_vec()))
+            .collect();
+        let aromatic = orders
+            .iter()
+            .filter(|(_, o)| **o == BondOrder::Aromatic)
+            .flat_map(|(&(i, j), _)| [i, j])
+            .filter(|&n| AROMATIC_ELEMENTS.contains(&symbol(n)))
+            .collect();
+
+        Self {
+            mol,
+            orders,
+            atoms,
+            nhydrogens,
+            neighbors,
+            aromatic,
+        }
+    }
+
+    fn order(&self, i: usize, j: usize) -> BondOrder {
+        self.orders.get(&bond_key(i, j)).copied().unwrap_or(BondOrder::Single)
+    }
+
+    /// Return the bond symbol between atom `i` and `j`.
+    fn bond_symbol(&self, i: usize, j: usize) -> &'static str {
+        match self.order(i, j) {
+            BondOrder::Double => "=",
+            BondOrder::Triple => "#",
+            BondOrder::Aromatic if self.aromatic.contains(&i) && self.aromatic.contains(&j) => "",
+            _ if self.aromatic.contains(&i) && self.aromatic.contains(&j) => "-",
+            _ => "",
+        }
+    }
+
+    /// Return the atom symbol of atom `n` in SMILES, with brackets when
+    /// its hydrogens can not be implied.
+    fn atom_symbol(&self, n: usize) -> String {
+        let symbol = self.mol.get_atom(n).unwrap().symbol();
+        let nh = self.nhydrogens[&n];
+        let aromatic = self.aromatic.contains(&n);
+        let name = if aromatic { symbol.to_lowercase() } else { symbol.to_owned() };
+
+        if ORGANIC_SUBSET.contains(&symbol) {
+            let sum: usize = self.neighbors[&n]
+                .iter()
+                .map(|&j| match self.order(n, j) {
+                    BondOrder::Double => 2,
+                    BondOrder::Triple => 3,
+                    _ => 1,
+                })
+                .sum();
+            let implied = if aromatic {
+                // only aromatic carbon and boron carry implicit hydrogens
+                match symbol {
+                    "C" | "B" => normal_valences(symbol)[0].saturating_sub(sum + 1),
+                    _ => 0,
+                }
+            } else {
+                normal_valences(symbol).iter().find(|&&v| v >= sum).map_or(0, |v| v - sum)
+            };
+            if implied == nh {
+                return name;
+            }
+        }
+        match nh {
+            0 => format!("[{}]", name),
+            1 => format!("[{}H]", name),
+            _ => format!("[{}H{}]", name, nh),
+        }
+    }
+
+    /// Rank atoms canonically by iterative refinement of atom invariants,
+    /// breaking ties among equivalent atoms.
+    ///
+    /// Ties left after refinement are broken at the atom with the lowest
+    /// serial number. This is canonical when tied atoms are symmetry
+    /// equivalent, which covers most molecules, but not for regular graphs
+    /// whose tied atoms are not related by symmetry: different atom orders
+    /// of such a molecule may give different SMILES.
+    fn canonical_ranks(&self) -> HashMap<usize, usize> {
+        let invariant = |n: usize| {
+            let a = self.mol.get_atom(n).unwrap();
+            let orders = self.neighbors[&n].iter().map(|&j| self.order(n, j) as usize).sorted().collect_vec();
+            (a.number(), self.neighbors[&n].len(), self.nhydrogens[&n], orders)
+        };
+        let mut ranks = rank_by(&self.atoms, invariant);
+        loop {
+            // refine by ranks of neighbors until stable
+            loop {
+                let refined = rank_by(&self.atoms, |n| {
+                    let neighbors = self.neighbors[&n]
+                        .iter()
+                        .map(|&j| (ranks[&j], self.order(n, j) as usize))
+                        .sorted()
+                        .collect_vec();
+                    (ranks[&n], neighbors)
+                });
+                let stable = count_ranks(&refined) == count_ranks(&ranks);
+                ranks = refined;
+                if stable {
+                    break;
+                }
+            }
+            if count_ranks(&ranks) == self.atoms.len() {
+                break;
+            }
+            // break the tie of the lowest tied rank
+            let tied = self.atoms.iter().copied().filter(|n| ranks.values().filter(|&&r| r == ranks[n]).count() > 1);
+            let n = tied.min_by_key(|n| (ranks[n], *n)).unwrap();
+            let r = ranks[&n];
+            ranks = self.atoms.iter().map(|&k| (k, 2 * ranks[&k] + usize::from(k != n && ranks[&k] == r))).collect();
+        }
+        ranks
+    }
+
+    /// Visit atoms by depth first search from atom `n`, in order of
+    /// `ranks`. Ring closure bonds are recorded as (opening atom, closing
+    /// atom) in `ring_bonds`.
+    fn visit(
+        &self,
+        n: usize,
+        parent: Option<usize>,
+        ranks: &HashMap<usize, usize>,
+        visited: &mut Vec<usize>,
+        children: &mut HashMap<usize, Vec<usize>>,
+        ring_bonds: &mut Vec<(usize, usize)>,
+    ) {
+        visited.push(n);
+        for &j in self.neighbors[&n].iter().sorted_by_key(|j| ranks[j]) {
+            if Some(j) == parent {
+                continue;
+            }
+            if visited.contains(&j) {
+                // j is an ancestor of n, unless the bond has been recorded from j
+                if !ring_bonds.contains(&(n, j)) {
+                    ring_bonds.push((j, n));
+                }
+            } else {
+                children.entry(n).or_default().push(j);
+                self.visit(j, Some(n), ranks, visited, children, ring_bonds);
+            }
+        }
+    }
+
+    /// Write SMILES for the connected fragment starting from atom `root`.
+    /// Return the SMILES string and the visited atoms.
+    fn write_fragment(&self, root: usize, ranks: &HashMap<usize, usize>) -> (String, Vec<usize>) {
+        let mut visited = vec![];
+        let mut children = HashMap::new();
+        let mut ring_bonds = vec![];
+        self.visit(root, None, ranks, &mut visited, &mut children, &mut ring_bonds);
+
+        let mut digits = HashMap::new();
+        let mut s = String::new();
+        self.write_atom(root, &children, &ring_bonds, &mut digits, &mut s);
+        (s, visited)
+    }
+
+    fn write_atom(
+        &self,
+        n: usize,
+        children: &HashMap<usize, Vec<usize>>,
+        ring_bonds: &[(usize, usize)],
+        digits: &mut HashMap<(usize, usize), usize>,
+        s: &mut String,
+    ) {
+        s.push_str(&self.atom_symbol(n));
+        // close rings opened earlier, then open new rings
+        for &(a, b) in ring_bonds.iter().filter(|(_, b)| *b == n) {
+            let d = digits.remove(&(a, b)).unwrap();
+            s.push_str(&format!("{}{}", self.bond_symbol(a, b), ring_digit(d)));
+        }
+        for &(a, b) in ring_bonds.iter().filter(|(a, _)| *a == n) {
+            let d = (1..).find(|d| !digits.values().any(|x| x == d)).unwrap();
+            digits.insert((a, b), d);
+            s.push_str(&ring_digit(d));
+        }
+
+        let empty = vec![];
+        let kids = children.get(&n).unwrap_or(&empty);
+        for (k, &j) in kids.iter().enumerate() {
+            let branch = k + 1 < kids.len();
+            if branch {
+                s.push('(');
+            }
+            s.push_str(self.bond_symbol(n, j));
+            self.write_atom(j, children, ring_bonds, digits, s);
+            if branch {
+                s.push(')');
+            }
+        }
+    }
+}
+
+fn ring_digit(d: usize) -> String {
+    if d < 10 {
+        d.to_string()
+    } else {
+        format!("%{}", d)
+    }
+}
+
+/// Rank `atoms` by `key`. Atoms with equal keys share the same rank.
+fn rank_by<K: Ord>(atoms: &[usize], key: impl Fn(usize) -> K) -> HashMap<usize, usize> {
+    let keys: HashMap<usize, K> = atoms.iter().map(|&n| (n, key(n))).collect();
+    let sorted = atoms.iter().map(|n| &keys[n]).sorted().dedup().collect_vec();
+    atoms
+        .iter()
+        .map(|n| (*n, sorted.binary_search(&&keys[n]).unwrap()))
+        .collect()
+}
+
+fn count_ranks(ranks: &HashMap<usize, usize>) -> usize {
+    ranks.values().collect::<HashSet<_>>().len()
+}
+
+/// Return SMILES for each connected fragment in `mol`, using
+/// existing connectivity and bond orders perceived from geometry. Return
+/// an error if no bonds are available.
+pub(crate) fn fragment_smiles(mol: &Molecule) -> Result<Vec<String>> {
+    if mol.nbonds() == 0 && mol.natoms() > 1 {
+        bail!("no bonds found. Please run `rebond` first.");
+    }
+
+    let graph = Graph::new(mol);
+    let ranks = graph.canonical_ranks();
+    let mut visited = HashSet::new();
+    let mut fragments = vec![];
+    for &n in graph.atoms.iter().sorted_by_key(|n| ranks[n]) {
+        if visited.contains(&n) {
+            continue;
+        }
+        let (s, atoms) = graph.write_fragment(n, &ranks);
+        visited.extend(atoms);
+        fragments.push(s);
+    }
+    fragments.sort_by(|a, b| b.len().cmp(&a.len()).then(a.cmp(b)));
+
+    Ok(fragments)
+}
+
+/// Return SMILES of `mol`, with fragments separated by ".".
+pub(crate) fn smiles(mol: &Molecule) -> Result<String> {
+    Ok(fragment_smiles(mol)?.join("."))
+}
+// c2a87e14 ends here
+
+// [[file:../gosh.note::4f1a6b2e][4f1a6b2e]]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gchemol::{Atom, Bond};
+    use std::f64::consts::PI;
+    use vecfx::*;
+
+    /// Build a molecule from `atoms` and single `bonds` in serial numbers.
+    fn molecule(atoms: &[(&str, Vector3f)], bonds: &[(usize, usize)]) -> Molecule {
+        let mut mol = Molecule::from_atoms(atoms.iter().map(|(s, p)| Atom::new(*s, [p.x, p.y, p.z])));
+        for &(i, j) in bonds {
+            mol.add_bond(i, j, Bond::single());
+        }
+        mol
+    }
+
+    /// Build a planar regular ring of `symbols` with side 1.40, with
+    /// hydrogens pointing outward from the atoms flagged in `hydrogens`.
+    fn ring(symbols: &[&str], hydrogens: &[bool]) -> Molecule {
+        let n = symbols.len();
+        let r = 1.40 / (2.0 * (PI / n as f64).sin());
+        let mut atoms = vec![];
+        let mut bonds = vec![];
+        for k in 0..n {
+            let t = 2.0 * PI * k as f64 / n as f64;
+            atoms.push((symbols[k], Vector3f::new(r * t.cos(), r * t.sin(), 0.0)));
+            bonds.push((k + 1, (k + 1) % n + 1));
+        }
+        for k in 0..n {
+            if hydrogens[k] {
+                let p = atoms[k].1;
+                atoms.push(("H", p + p.normalize() * 1.08));
+                bonds.push((k + 1, atoms.len()));
+            }
+        }
+        molecule(&atoms, &bonds)
+    }
+
+    #[test]
+    fn test_smiles_aromatic() -> Result<()> {
+        let benzene = ring(&["C"; 6], &[true; 6]);
+        assert_eq!(smiles(&benzene)?, "c1ccccc1");
+
+        let pyridine = ring(&["N", "C", "C", "C", "C", "C"], &[false, true, true, true, true, true]);
+        assert_eq!(smiles(&pyridine)?, "c1ccncc1");
+
+        let pyrrole = ring(&["N", "C", "C", "C", "C"], &[true; 5]);
+        assert_eq!(smiles(&pyrrole)?, "c1cc[nH]c1");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_smiles_naphthalene() -> Result<()> {
+        let (x, y) = (1.40 * 3f64.sqrt() / 2.0, 0.70);
+        let carbons = [
+            Vector3f::new(-x, 2.0 * y, 0.0),
+            Vector3f::new(-2.0 * x, y, 0.0),
+            Vector3f::new(-2.0 * x, -y, 0.0),
+            Vector3f::new(-x, -2.0 * y, 0.0),
+            Vector3f::new(x, -2.0 * y, 0.0),
+            Vector3f::new(2.0 * x, -y, 0.0),
+            Vector3f::new(2.0 * x, y, 0.0),
+            Vector3f::new(x, 2.0 * y, 0.0),
+            Vector3f::new(0.0, -y, 0.0),
+            Vector3f::new(0.0, y, 0.0),
+        ];
+        let mut atoms = carbons.iter().map(|p| ("C", *p)).collect_vec();
+        let mut bonds = vec![(1, 2), (2, 3), (3, 4), (4, 9), (9, 10), (10, 1), (9, 5), (5, 6), (6, 7), (7, 8), (8, 10)];
+        for (k, p) in carbons[..8].iter().enumerate() {
+            // pointing outward from the center of its ring
+            let center = Vector3f::new(x.copysign(p.x), 0.0, 0.0);
+            atoms.push(("H", p + (p - center).normalize() * 1.08));
+            bonds.push((k + 1, atoms.len()));
+        }
+        let naphthalene = molecule(&atoms, &bonds);
+        assert_eq!(smiles(&naphthalene)?, "c1ccc2ccccc2c1");
+
+        // the result does not depend on atom order
+        let order = (1..=naphthalene.natoms()).rev().collect_vec();
+        let reversed = crate::edit::reordered(&naphthalene, &order)?;
+        assert_eq!(smiles(&reversed)?, "c1ccc2ccccc2c1");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_smiles_double_bonds() -> Result<()> {
+        let co2 = molecule(
+            &[
+                ("C", Vector3f::zeros()),
+                ("O", Vector3f::new(0.0, 0.0, 1.16)),
+                ("O", Vector3f::new(0.0, 0.0, -1.16)),
+            ],
+            &[(1, 2), (1, 3)],
+        );
+        assert_eq!(smiles(&co2)?, "C(=O)=O");
+
+        // acetate. Charges are not considered.
+        let (c, s) = (60f64.to_radians().cos(), 60f64.to_radians().sin());
+        let (ch, sh) = (109.47f64.to_radians().cos(), 109.47f64.to_radians().sin());
+        let c1 = Vector3f::new(-1.52, 0.0, 0.0);
+        let mut atoms = vec![
+            ("C", c1),
+            ("C", Vector3f::zeros()),
+            ("O", Vector3f::new(1.26 * c, 1.26 * s, 0.0)),
+            ("O", Vector3f::new(1.26 * c, -1.26 * s, 0.0)),
+        ];
+        for phi in [0.0f64, 120.0, 240.0] {
+            let phi = phi.to_radians();
+            atoms.push(("H", c1 + 1.09 * Vector3f::new(ch, sh * phi.cos(), sh * phi.sin())));
+        }
+        let acetate = molecule(&atoms, &[(1, 2), (2, 3), (2, 4), (1, 5), (1, 6), (1, 7)]);
+        assert_eq!(smiles(&acetate)?, "CC([O])=O");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_smiles_fragments() -> Result<()> {
+        let benzene = ring(&["C"; 6], &[true; 6]);
+        let pyridine = ring(&["N", "C", "C", "C", "C", "C"], &[false, true, true, true, true, true]);
+        let offsets = [Vector3f::new(10.0, 0.0, 0.0)];
+        let mol = crate::edit::merge_molecules(&[benzene, pyridine], &offsets, false)?;
+        assert_eq!(fragment_smiles(&mol)?, ["c1ccccc1", "c1ccncc1"]);
+
+        let mol = Molecule::from_atoms([Atom::new("C", [0.0; 3]), Atom::new("C", [1.5, 0.0, 0.0])]);
+        assert!(smiles(&mol).is_err());
+        Ok(())
+    }
+}
+// 4f1a6b2e ends here
@@ -0,0 +1,650 @@
+// [[file:../gosh.note::4be2c8d1][4be2c8d1]]
+use super::*;
+use crate::cell::cell_vectors;
+use crate::geometry::mic_vector;
+
+use gchemol::Molecule;
+use vecfx::nalgebra::{Matrix3, Rotation3, Unit};
+use vecfx::*;
+
+use std::f64::consts::PI;
+// 4be2c8d1 ends here
+
+// [[file:../gosh.note::9d7a3c62][9d7a3c62]]
+/// A symmetry operation transforming position `p` into `rot * p + trans`.
+#[derive(Debug, Clone)]
+struct SymOp {
+    rot: Matrix3<f64>,
+    trans: Vector3f,
+}
+
+impl SymOp {
+    fn identity() -> Self {
+        Self {
+            rot: Matrix3::identity(),
+            trans: Vector3f::zeros(),
+        }
+    }
+
+    /// Point operation `rot` about `center`.
+    fn about(rot: Matrix3<f64>, center: Vector3f) -> Self {
+        Self {
+            trans: center - rot * center,
+            rot,
+        }
+    }
+
+    /// Return the operation applying `other` first and then `self`.
+    fn compose(&self, other: &SymOp) -> Self {
+        Self {
+            rot: self.rot * other.rot,
+            trans: self.rot * other.trans + self.trans,
+        }
+    }
+
+    fn approx_eq(&self, other: &SymOp) -> bool {
+        (self.rot - other.rot).abs().max() < 1e-3 && (self.trans - other.trans).norm() < 1e-3
+    }
+}
+
+/// Return the atom index (0-based) each atom is mapped to by `op`, or None
+/// if `op` is not a symmetry operation of `mol` within `tol`.
+fn permutation_of(mol: &Molecule, atoms: &[(&str, Vector3f)], op: &SymOp, tol: f64) -> Option<Vec<usize>> {
+    let mut perm = vec![];
+    for (s, p) in atoms.iter() {
+        let q = op.rot * p + op.trans;
+        let j = atoms
+            .iter()
+            .position(|(t, r)| s == t && mic_vector(mol, q, *r).norm() < tol)?;
+        perm.push(j);
+    }
+    Some(perm)
+}
+
+/// Rotation matrix about `axis` by `angle` in radians.
+fn rotation(axis: Vector3f, angle: f64) -> Matrix3<f64> {
+    Rotation3::from_axis_angle(&Unit::new_normalize(axis), angle).into_inner()
+}
+
+/// Reflection matrix through plane with `normal`.
+fn reflection(normal: Vector3f) -> Matrix3<f64> {
+    let n = normal.normalize();
+    Matrix3::identity() - 2.0 * n * n.transpose()
+}
+// 9d7a3c62 ends here
+
+// [[file:../gosh.note::1c68b5fa][1c68b5fa]]
+/// Symmetry elements derived from symmetry operations.
+#[derive(Debug, Default)]
+struct Elements {
+    /// Proper rotation axes and their orders
+    axes: Vec<(Vector3f, usize)>,
+    /// Normals of mirror planes
+    mirrors: Vec<Vector3f>,
+    /// Axes of improper rotations other than mirrors and inversion
+    improper_axes: Vec<Vector3f>,
+    inversion: bool,
+}
+
+fn parallel(u: &Vector3f, v: &Vector3f) -> bool {
+    u.normalize().cross(&v.normalize()).norm() < 1e-2
+}
+
+fn perpendicular(u: &Vector3f, v: &Vector3f) -> bool {
+    u.normalize().dot(&v.normalize()).abs() < 1e-2
+}
+
+/// Return the rotation axis and angle of proper rotation matrix `m`.
+fn axis_angle(m: &Matrix3<f64>) -> (Vector3f, f64) {
+    let theta = ((m.trace() - 1.0) / 2.0).clamp(-1.0, 1.0).acos();
+    let axis = if (theta - PI).abs() < 1e-3 {
+        // for two-fold rotation, use the largest column of m + I
+        let s = m + Matrix3::identity();
+        (0..3).map(|i| s.column(i).into_owned()).max_by(|a, b| a.norm().partial_cmp(&b.norm()).unwrap()).unwrap()
+    } else {
+        Vector3f::new(m[(2, 1)] - m[(1, 2)], m[(0, 2)] - m[(2, 0)], m[(1, 0)] - m[(0, 1)])
+    };
+    (axis.normalize(), theta)
+}
+
+impl Elements {
+    /// Derive symmetry elements from all operations in a group.
+    fn from_ops(ops: &[SymOp]) -> Self {
+        let mut elements = Self::default();
+        for op in ops {
+            let proper = op.rot.determinant() > 0.0;
+            let m = if proper { op.rot } else { -op.rot };
+            let (axis, theta) = axis_angle(&m);
+            if theta < 1e-3 {
+                if !proper {
+                    elements.inversion = true;
+                }
+                continue;
+            }
+            if proper {
+                // the order of axis is the number of rotations about it plus identity
+                match elements.axes.iter_mut().find(|(v, _)| parallel(v, &axis)) {
+                    Some((_, n)) => *n += 1,
+                    None => elements.axes.push((axis, 2)),
+                }
+            } else if (theta - PI).abs() < 1e-3 {
+                if !elements.mirrors.iter().any(|v| parallel(v, &axis)) {
+                    elements.mirrors.push(axis);
+                }
+            } else if !elements.improper_axes.iter().any(|v| parallel(v, &axis)) {
+                elements.improper_axes.push(axis);
+            }
+        }
+        elements
+    }
+
+    /// Return Schoenflies symbol of the point group.
+    fn point_group(&self) -> String {
+        let high = self.axes.iter().filter(|(_, n)| *n >= 3).count();
+        if high >= 2 {
+            let has = |order: usize| self.axes.iter().any(|(_, n)| *n == order);
+            let pg = if has(5) {
+                if self.inversion { "Ih" } else { "I" }
+            } else if has(4) {
+                if self.inversion { "Oh" } else { "O" }
+            } else if self.inversion {
+                "Th"
+            } else if !self.mirrors.is_empty() {
+                "Td"
+            } else {
+                "T"
+            };
+            return pg.into();
+        }
+
+        let n = match self.axes.iter().map(|(_, n)| *n).max() {
+            Some(n) => n,
+            None => {
+                let pg = if !self.mirrors.is_empty() {
+                    "Cs"
+                } else if self.inversion {
+                    "Ci"
+                } else {
+                    "C1"
+                };
+                return pg.into();
+            }
+        };
+
+        // the principal axis. For two-fold axes, prefer the one with S4.
+        let candidates = self.axes.iter().filter(|(_, m)| *m == n).map(|(v, _)| v).collect_vec();
+        let main: &Vector3f = *candidates
+            .iter()
+            .find(|v| self.improper_axes.iter().any(|u| parallel(u, **v)))
+            .unwrap_or(&candidates[0]);
+        let perp_c2 = self.axes.iter().any(|(v, _)| perpendicular(v, main));
+        let sigma_h = self.mirrors.iter().any(|v| parallel(v, main));
+        let sigma_v = self.mirrors.iter().any(|v| perpendicular(v, main));
+        let improper = self.improper_axes.iter().any(|v| parallel(v, main));
+
+        if perp_c2 {
+            if sigma_h {
+                format!("D{}h", n)
+            } else if sigma_v {
+                format!("D{}d", n)
+            } else {
+                format!("D{}", n)
+            }
+        } else if sigma_h {
+            format!("C{}h", n)
+        } else if sigma_v {
+            format!("C{}v", n)
+        } else if improper {
+            format!("S{}", 2 * n)
+        } else {
+            format!("C{}", n)
+        }
+    }
+}
+// 1c68b5fa ends here
+
+// [[file:../gosh.note::7e15d0a9][7e15d0a9]]
+/// Generate the group from `generators` by closure.
+fn closure(generators: &[SymOp]) -> Result<Vec<SymOp>> {
+    let mut group = vec![SymOp::identity()];
+    let mut i = 0;
+    while i < group.len() {
+        for g in generators {
+            let op = group[i].compose(g);
+            if !group.iter().any(|x| x.approx_eq(&op)) {
+                group.push(op);
+            }
+        }
+        if group.len() > 240 {
+            bail!("too many symmetry operations. Please use a tighter tolerance.");
+        }
+        i += 1;
+    }
+    Ok(group)
+}
+
+/// Find symmetry operations of non-periodic `mol` with tolerance `tol` in
+/// Angstrom. Return the operations and whether the molecule is linear.
+fn molecular_ops(mol: &Molecule, atoms: &[(&str, Vector3f)], tol: f64) -> Result<(Vec<SymOp>, bool)> {
+    let c: Vector3f = mol.center_of_mass().into();
+    let rel = atoms.iter().map(|(_, p)| p - c).collect_vec();
+
+    // linear molecule
+    if let Some(v) = rel.iter().find(|v| v.norm() > tol) {
+        if rel.iter().all(|u| u.cross(&v.normalize()).norm() < tol) {
+            let inversion = SymOp::about(-Matrix3::identity(), c);
+            let ops = match permutation_of(mol, atoms, &inversion, tol) {
+                Some(_) => vec![SymOp::identity(), inversion],
+                None => vec![SymOp::identity()],
+            };
+            return Ok((ops, true));
+        }
+    }
+
+    // shells of symmetry equivalent atom candidates: same element and same
+    // distance to center
+    let mut order = (0..atoms.len()).filter(|&i| rel[i].norm() > tol).collect_vec();
+    order.sort_by(|&i, &j| (atoms[i].0, rel[i].norm()).partial_cmp(&(atoms[j].0, rel[j].norm())).unwrap());
+    let mut shells: Vec<Vec<usize>> = vec![];
+    for i in order {
+        match shells.last_mut() {
+            Some(shell) if atoms[shell[0]].0 == atoms[i].0 && (rel[shell[0]].norm() - rel[i].norm()).abs() < tol => {
+                shell.push(i)
+            }
+            _ => shells.push(vec![i]),
+        }
+    }
+    shells.sort_by_key(|s| s.len());
+
+    // candidate directions for rotation axes and mirror plane normals
+    let mut inertia = Matrix3::zeros();
+    for (m, r) in mol.masses().zip(rel.iter()) {
+        inertia += m * (r.norm_squared() * Matrix3::identity() - r * r.transpose());
+    }
+    let eigen = inertia.symmetric_eigen();
+    let mut dirs: Vec<Vector3f> = (0..3).map(|i| eigen.eigenvectors.column(i).into_owned()).collect();
+    for shell in shells.iter().take(3) {
+        for &i in shell {
+            dirs.push(rel[i]);
+        }
+        for (&i, &j) in shell.iter().tuple_combinations() {
+            dirs.push(rel[i] + rel[j]);
+            dirs.push(rel[i] - rel[j]);
+            dirs.push(rel[i].cross(&rel[j]));
+        }
+    }
+    let mut axes: Vec<Vector3f> = vec![];
+    for d in dirs {
+        if d.norm() > 1e-3 && !axes.iter().any(|a| parallel(a, &d)) {
+            axes.push(d.normalize());
+        }
+    }
+
+    let mut generators = vec![];
+    let mut try_op = |rot: Matrix3<f64>| {
+        let op = SymOp::about(rot, c);
+        if permutation_of(mol, atoms, &op, tol).is_some() {
+            generators.push(op);
+        }
+    };
+    try_op(-Matrix3::identity());
+    for axis in axes.iter() {
+        try_op(reflection(*axis));
+        for n in 2..=8 {
+            try_op(rotation(*axis, 2.0 * PI / n as f64));
+        }
+        for n in [4, 6, 8, 10, 12] {
+            try_op(reflection(*axis) * rotation(*axis, 2.0 * PI / n as f64));
+        }
+    }
+
+    Ok((closure(&generators)?, false))
+}
+// 7e15d0a9 ends here
+
+// [[file:../gosh.note::2f86b4e3][2f86b4e3]]
+/// The key in molecule properties for the point group detected by
+/// `symmetry` command.
+pub(crate) const POINT_GROUP_KEY: &str = "point_group";
+
+/// The key in molecule properties for the space group detected by
+/// `symmetry` command.
+pub(crate) const SPACE_GROUP_KEY: &str = "space_group";
+
+/// Symmetry information of a molecule or crystal.
+pub(crate) struct Symmetry {
+    /// Point group in Schoenflies notation. For crystal, this is the
+    /// crystal class.
+    pub point_group: String,
+    /// International space group symbol and number for crystal.
+    pub space_group: Option<String>,
+    /// Crystal system of the space group.
+    pub crystal_system: Option<&'static str>,
+    ops: Vec<SymOp>,
+    /// The index (0-based) of the representative atom for each atom, as
+    /// found by spglib for crystal.
+    equivalent: Option<Vec<usize>>,
+    tol: f64,
+}
+
+/// Detect symmetry of `mol` with tolerance `tol` in Angstrom.
+pub(crate) fn detect_symmetry(mol: &Molecule, tol: f64) -> Result<Symmetry> {
+    let atoms: Vec<(&str, Vector3f)> = mol.atoms().map(|(_, a)| (a.symbol(), a.position().into())).collect();
+    if mol.lattice.is_none() {
+        let (ops, linear) = molecular_ops(mol, &atoms, tol)?;
+        let point_group = match (linear, ops.len()) {
+            (true, 2) => "D*h".to_owned(),
+            (true, _) => "C*v".to_owned(),
+            _ => Elements::from_ops(&ops).point_group(),
+        };
+        return Ok(Symmetry {
+            point_group,
+            space_group: None,
+            crystal_system: None,
+            ops,
+            equivalent: None,
+            tol,
+        });
+    }
+
+    // all symmetry information of crystal is taken from spglib
+    let dataset = spglib_dataset(mol, tol)?;
+    let a = Matrix3::from_columns(&cell_vectors(mol.lattice.as_ref().unwrap()));
+    let a_inv = a.try_inverse().ok_or_else(|| format_err!("invalid cell: {:?}", a))?;
+    // operations in fractional coordinates: x' = R x + t
+    let ops = dataset
+        .rotations
+        .iter()
+        .zip(dataset.translations.iter())
+        .map(|(r, t)| SymOp {
+            rot: a * Matrix3::from_fn(|i, j| r[i][j] as f64) * a_inv,
+            trans: a * Vector3f::new(t[0], t[1], t[2]),
+        })
+        .collect_vec();
+    let number = dataset.spacegroup_number;
+    let symbol = dataset.international_symbol.trim();
+
+    Ok(Symmetry {
+        point_group: schoenflies(dataset.pointgroup_symbol.trim())?.to_owned(),
+        space_group: Some(format!("{} ({})", symbol, number)),
+        crystal_system: Some(crystal_system(number)),
+        ops,
+        equivalent: Some(dataset.equivalent_atoms.iter().map(|&i| i as usize).collect()),
+        tol,
+    })
+}
+
+/// Return the symmetry dataset of crystal `mol` from spglib with tolerance
+/// `tol` in Angstrom.
+pub(crate) fn spglib_dataset(mol: &Molecule, tol: f64) -> Result<spglib::dataset::Dataset> {
+    let lat = mol.lattice.as_ref().ok_or_else(|| format_err!("not a periodic system!"))?;
+    // spglib expects cell vectors in columns
+    let cell = Matrix3::from_columns(&cell_vectors(lat));
+    let lattice: [[f64; 3]; 3] = [0, 1, 2].map(|i| [0, 1, 2].map(|j| cell[(i, j)]));
+    let positions = mol.atoms().map(|(_, a)| lat.to_frac(a.position())).collect_vec();
+    let types = mol.atoms().map(|(_, a)| a.number() as i32).collect_vec();
+
+    let mut cell = spglib::cell::Cell::new(&lattice, &positions, &types);
+    let dataset = spglib::dataset::Dataset::new(&mut cell, tol);
+    if dataset.spacegroup_number <= 0 {
+        bail!("failed to identify space group. Please try another tolerance.");
+    }
+    Ok(dataset)
+}
+
+/// Convert the Hermann-Mauguin symbol of crystal class `hm` into
+/// Schoenflies notation.
+fn schoenflies(hm: &str) -> Result<&'static str> {
+    let pg = match hm {
+        "1" => "C1",
+        "-1" => "Ci",
+        "2" => "C2",
+        "m" => "Cs",
+        "2/m" => "C2h",
+        "222" => "D2",
+        "mm2" => "C2v",
+        "mmm" => "D2h",
+        "4" => "C4",
+        "-4" => "S4",
+        "4/m" => "C4h",
+        "422" => "D4",
+        "4mm" => "C4v",
+        "-42m" | "-4m2" => "D2d",
+        "4/mmm" => "D4h",
+        "3" => "C3",
+        "-3" => "S6",
+        "32" => "D3",
+        "3m" => "C3v",
+        "-3m" => "D3d",
+        "6" => "C6",
+        "-6" => "C3h",
+        "6/m" => "C6h",
+        "622" => "D6",
+        "6mm" => "C6v",
+        "-6m2" | "-62m" => "D3h",
+        "6/mmm" => "D6h",
+        "23" => "T",
+        "m-3" => "Th",
+        "432" => "O",
+        "-43m" => "Td",
+        "m-3m" => "Oh",
+        _ => bail!("unknown crystal class: {:?}", hm),
+    };
+    Ok(pg)
+}
+
+/// Return crystal system of space group `number`.
+pub(crate) fn crystal_system(number: i32) -> &'static str {
+    match number {
+        1..=2 => "triclinic",
+        3..=15 => "monoclinic",
+        16..=74 => "orthorhombic",
+        75..=142 => "tetragonal",
+        143..=167 => "trigonal",
+        168..=194 => "hexagonal",
+        _ => "cubic",
+    }
+}
+
+impl Symmetry {
+    /// The number of symmetry operations.
+    pub fn nops(&self) -> usize {
+        self.ops.len()
+    }
+
+    /// Return sets of symmetry equivalent atoms in serial numbers.
+    pub fn equivalent_atoms(&self, mol: &Molecule) -> Vec<Vec<usize>> {
+        let numbers = mol.numbers().collect_vec();
+        if let Some(equivalent) = &self.equivalent {
+            let mut sets: Vec<Vec<usize>> = vec![];
+            let mut roots: Vec<usize> = vec![];
+            for (i, &r) in equivalent.iter().enumerate() {
+                match roots.iter().position(|&x| x == r) {
+                    Some(k) => sets[k].push(numbers[i]),
+                    None => {
+                        roots.push(r);
+                        sets.push(vec![numbers[i]]);
+                    }
+                }
+            }
+            return sets;
+        }
+        let atoms: Vec<(&str, Vector3f)> = mol.atoms().map(|(_, a)| (a.symbol(), a.position().into())).collect();
+
+        // union atoms connected by symmetry operations
+        let mut parent = (0..atoms.len()).collect_vec();
+        fn find(parent: &mut [usize], i: usize) -> usize {
+            let mut i = i;
+            while parent[i] != i {
+                parent[i] = parent[parent[i]];
+                i = parent[i];
+            }
+            i
+        }
+        for op in self.ops.iter() {
+            if let Some(perm) = permutation_of(mol, &atoms, op, self.tol) {
+                for (i, j) in perm.into_iter().enumerate() {
+                    let (ri, rj) = (find(&mut parent, i), find(&mut parent, j));
+                    parent[ri.max(rj)] = ri.min(rj);
+                }
+            }
+        }
+
+        let mut sets: Vec<Vec<usize>> = vec![];
+        let mut roots: Vec<usize> = vec![];
+        for i in 0..atoms.len() {
+            let r = find(&mut parent, i);
+            match roots.iter().position(|&x| x == r) {
+                Some(k) => sets[k].push(numbers[i]),
+                None => {
+                    roots.push(r);
+                    sets.push(vec![numbers[i]]);
+                }
+            }
+        }
+        sets
+    }
+
+    /// Symmetrize atom positions of `mol` by averaging over all symmetry
+    /// operations.
+    pub fn symmetrize(&self, mol: &mut Molecule) -> Result<()> {
+        let numbers = mol.numbers().collect_vec();
+        let atoms: Vec<(&str, Vector3f)> = mol.atoms().map(|(_, a)| (a.symbol(), a.position().into())).collect();
+        let mut sums = vec![Vector3f::zeros(); atoms.len()];
+        for op in self.ops.iter() {
+            let perm = permutation_of(mol, &atoms, op, self.tol).ok_or_else(|| format_err!("structure changed."))?;
+            let inv = op.rot.try_inverse().ok_or_else(|| format_err!("invalid operation: {:?}", op))?;
+            for (i, j) in perm.into_iter().enumerate() {
+                let pi = atoms[i].1;
+                let q = inv * (atoms[j].1 - op.trans);
+                sums[i] += pi + mic_vector(mol, pi, q);
+            }
+        }
+
+        let n = self.ops.len() as f64;
+        for (k, s) in sums.into_iter().enumerate() {
+            let p: [f64; 3] = (s / n).into();
+            mol.get_atom_mut(numbers[k]).unwrap().set_position(p);
+        }
+
+        Ok(())
+    }
+}
+// 2f86b4e3 ends here
+
+// [[file:../gosh.note::a8d1f5c3][a8d1f5c3]]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gchemol::{Atom, Lattice};
+
+    fn molecule(atoms: &[(&str, [f64; 3])]) -> Molecule {
+        Molecule::from_atoms(atoms.iter().map(|(s, p)| Atom::new(*s, *p)))
+    }
+
+    fn crystal(a: f64, atoms: &[(&str, [f64; 3])]) -> Molecule {
+        let mut mol = Molecule::from_atoms(atoms.iter().map(|(s, f)| Atom::new(*s, [f[0] * a, f[1] * a, f[2] * a])));
+        mol.set_lattice(Lattice::new([[a, 0.0, 0.0], [0.0, a, 0.0], [0.0, 0.0, a]]));
+        mol
+    }
+
+    #[test]
+    fn test_point_group() -> Result<()> {
+        let water = molecule(&[("O", [0.0, 0.0, 0.1173]), ("H", [0.0, 0.7572, -0.4692]), ("H", [0.0, -0.7572, -0.4692])]);
+        assert_eq!(detect_symmetry(&water, 0.1)?.point_group, "C2v");
+
+        let ammonia = molecule(&[
+            ("N", [0.0, 0.0, 0.1162]),
+            ("H", [0.0, 0.9397, -0.2711]),
+            ("H", [0.8138, -0.4699, -0.2711]),
+            ("H", [-0.8138, -0.4699, -0.2711]),
+        ]);
+        assert_eq!(detect_symmetry(&ammonia, 0.1)?.point_group, "C3v");
+
+        let d = 0.629;
+        let methane = molecule(&[
+            ("C", [0.0, 0.0, 0.0]),
+            ("H", [d, d, d]),
+            ("H", [-d, -d, d]),
+            ("H", [-d, d, -d]),
+            ("H", [d, -d, -d]),
+        ]);
+        let sym = detect_symmetry(&methane, 0.1)?;
+        assert_eq!(sym.point_group, "Td");
+        assert_eq!(sym.nops(), 24);
+
+        let benzene = molecule(
+            &(0..12)
+                .map(|i| {
+                    let (s, r) = if i < 6 { ("C", 1.39) } else { ("H", 2.47) };
+                    let t = (i % 6) as f64 * PI / 3.0;
+                    (s, [r * t.cos(), r * t.sin(), 0.0])
+                })
+                .collect_vec(),
+        );
+        let sym = detect_symmetry(&benzene, 0.1)?;
+        assert_eq!(sym.point_group, "D6h");
+        assert_eq!(sym.equivalent_atoms(&benzene).len(), 2);
+
+        let co2 = molecule(&[("C", [0.0, 0.0, 0.0]), ("O", [0.0, 0.0, 1.16]), ("O", [0.0, 0.0, -1.16])]);
+        assert_eq!(detect_symmetry(&co2, 0.1)?.point_group, "D*h");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_space_group() -> Result<()> {
+        let fcc = [[0.0, 0.0, 0.0], [0.0, 0.5, 0.5], [0.5, 0.0, 0.5], [0.5, 0.5, 0.0]];
+        let cu = crystal(3.61, &fcc.map(|f| ("Cu", f)));
+        let sym = detect_symmetry(&cu, 0.01)?;
+        assert_eq!(sym.point_group, "Oh");
+        assert_eq!(sym.space_group.as_deref(), Some("Fm-3m (225)"));
+        assert_eq!(sym.crystal_system, Some("cubic"));
+
+        let fe = crystal(2.87, &[("Fe", [0.0, 0.0, 0.0]), ("Fe", [0.5, 0.5, 0.5])]);
+        assert_eq!(detect_symmetry(&fe, 0.01)?.space_group.as_deref(), Some("Im-3m (229)"));
+
+        // non-symmorphic
+        let mut atoms = fcc.map(|f| ("Si", f)).to_vec();
+        atoms.extend(fcc.map(|f| ("Si", [f[0] + 0.25, f[1] + 0.25, f[2] + 0.25])));
+        let si = crystal(5.43, &atoms);
+        let sym = detect_symmetry(&si, 0.01)?;
+        assert_eq!(sym.point_group, "Oh");
+        assert_eq!(sym.space_group.as_deref(), Some("Fd-3m (227)"));
+
+        let sc = crystal(3.0, &[("Po", [0.0, 0.0, 0.0])]);
+        assert_eq!(detect_symmetry(&sc, 0.01)?.space_group.as_deref(), Some("Pm-3m (221)"));
+
+        // rock salt: operations and equivalent atoms agree with the space group
+        let mut atoms = fcc.map(|f| ("Na", f)).to_vec();
+        atoms.extend(fcc.map(|f| ("Cl", [f[0] + 0.5, f[1], f[2]])));
+        let nacl = crystal(5.64, &atoms);
+        let sym = detect_symmetry(&nacl, 0.01)?;
+        assert_eq!(sym.point_group, "Oh");
+        assert_eq!(sym.space_group.as_deref(), Some("Fm-3m (225)"));
+        // 48 point operations with 4 lattice translations
+        assert_eq!(sym.nops(), 192);
+        assert_eq!(sym.equivalent_atoms(&nacl), [vec![1, 2, 3, 4], vec![5, 6, 7, 8]]);
+
+        // lower symmetry by displacing an atom
+        let mut nacl = nacl;
+        nacl.get_atom_mut(1).unwrap().set_position([0.0, 0.0, 0.2]);
+        let sym = detect_symmetry(&nacl, 0.01)?;
+        assert_eq!(sym.point_group, "C4v");
+        assert_eq!(sym.crystal_system, Some("tetragonal"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_symmetrize() -> Result<()> {
+        let mut water = molecule(&[("O", [0.0, 0.0, 0.1173]), ("H", [0.01, 0.7572, -0.4692]), ("H", [0.0, -0.7572, -0.4692])]);
+        let sym = detect_symmetry(&water, 0.1)?;
+        sym.symmetrize(&mut water)?;
+        let [h1, h2] = [2, 3].map(|n| water.get_atom(n).unwrap().position());
+        assert_relative_eq!(h1[0], h2[0], epsilon = 1e-6);
+        assert_relative_eq!(h1[1], -h2[1], epsilon = 1e-6);
+        Ok(())
+    }
+}
+// a8d1f5c3 ends here
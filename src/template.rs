@@ -0,0 +1,122 @@
+// [[file:../gosh.note::5c2f0e91][5c2f0e91]]
+use super::*;
+
+use gchemol::Molecule;
+use serde_json::{json, Value};
+// 5c2f0e91 ends here
+
+// [[file:../gosh.note::b7e40c13][b7e40c13]]
+/// Build template data for `mol`, following the `Molecule` data model of
+/// gchemol (see docs/technical-guide/si5.json). Atoms are grouped by
+/// element in order of first appearance.
+///
+/// Extra variables provided by gosh:
+/// - molecule.permutation: serial numbers in original order of atoms, as
+///   stored by `sort` command
+/// - atom.original_index: the serial number of atom in original order
+/// - molecule.point_group: point group in Schoenflies notation, as detected
+///   by `symmetry` command, or null
+/// - molecule.space_group: space group detected by `symmetry` command, or
+///   null
+/// - molecule.smiles: SMILES computed by `smiles` command, or
+///   null
+pub(crate) fn template_data(mol: &Molecule) -> Result<Value> {
+    let perm = crate::reorder::get_permutation(mol);
+    let point_group = mol.properties.load::<String>(crate::symmetry::POINT_GROUP_KEY).ok();
+    let space_group = mol.properties.load::<String>(crate::symmetry::SPACE_GROUP_KEY).ok();
+    let smiles = mol.properties.load::<String>(crate::smiles::SMILES_KEY).ok();
+
+    // species in order of first appearance
+    let mut species: Vec<(String, usize, usize)> = vec![];
+    for (_, a) in mol.atoms() {
+        match species.iter_mut().find(|(s, ..)| s == a.symbol()) {
+            Some((_, _, n)) => *n += 1,
+            None => species.push((a.symbol().to_owned(), a.number(), 1)),
+        }
+    }
+
+    let mut atoms = vec![];
+    for (k, (_, a)) in mol.atoms().enumerate() {
+        let element_index = species.iter().position(|(s, ..)| s == a.symbol()).unwrap() + 1;
+        let [x, y, z] = a.position();
+        let [fx, fy, fz] = match &mol.lattice {
+            Some(lat) => lat.to_frac([x, y, z]).into(),
+            None => [0.0; 3],
+        };
+        let [vx, vy, vz] = a.velocity();
+        atoms.push((
+            element_index,
+            json!({
+                "element_index": element_index,
+                "freezing": a.freezing(),
+                "symbol": a.symbol(),
+                "number": a.number(),
+                "x": x, "y": y, "z": z,
+                "fx": fx, "fy": fy, "fz": fz,
+                "vx": vx, "vy": vy, "vz": vz,
+                "original_index": perm[k],
+            }),
+        ));
+    }
+    atoms.sort_by_key(|(i, _)| *i);
+    let atoms: Vec<Value> = atoms
+        .into_iter()
+        .enumerate()
+        .map(|(i, (_, mut a))| {
+            a["index"] = json!(i + 1);
+            a
+        })
+        .collect();
+
+    let bonds: Vec<Value> = mol
+        .bonds()
+        .map(|(i, j, b)| json!({"i": i, "j": j, "order": b.order()}))
+        .collect();
+
+    let unit_cell = mol.lattice.as_ref().map(|lat| {
+        let [a, b, c] = lat.lengths();
+        let [alpha, beta, gamma] = lat.angles();
+        let va: [f64; 3] = lat.to_cart([1.0, 0.0, 0.0]).into();
+        let vb: [f64; 3] = lat.to_cart([0.0, 1.0, 0.0]).into();
+        let vc: [f64; 3] = lat.to_cart([0.0, 0.0, 1.0]).into();
+        json!({
+            "a": a, "b": b, "c": c,
+            "alpha": alpha, "beta": beta, "gamma": gamma,
+            "va": va, "vb": vb, "vc": vc,
+        })
+    });
+
+    let data = json!({
+        "molecule": {
+            "title": mol.title(),
+            "number_of_atoms": mol.natoms(),
+            "number_of_bonds": mol.nbonds(),
+            "number_of_species": species.len(),
+            "element_types": species.iter().map(|(s, _, n)| json!([s, n])).collect_vec(),
+            "species": species.iter().enumerate().map(|(i, (s, z, n))| json!({
+                "index": i + 1,
+                "element_symbol": s,
+                "element_number": z,
+                "number_of_atoms": n,
+            })).collect_vec(),
+            "atoms": atoms,
+            "bonds": bonds,
+            "unit_cell": unit_cell,
+            "permutation": perm,
+            "point_group": point_group,
+            "space_group": space_group,
+            "smiles": smiles,
+        }
+    });
+
+    Ok(data)
+}
+
+/// Render `mol` with template file in `path`.
+pub(crate) fn render_molecule(mol: &Molecule, path: &Path) -> Result<String> {
+    let data = template_data(mol)?;
+    let template = gchemol::io::Template::try_from_path(path)?;
+    let s = template.render_json(&data)?;
+    Ok(s)
+}
+// b7e40c13 ends here
@@ -0,0 +1,144 @@
+// [[file:../gosh.note::77fa142a][77fa142a]]
+use super::*;
+use crate::geometry::atom_position;
+
+use gchemol::Molecule;
+use vecfx::*;
+// 77fa142a ends here
+
+// [[file:../gosh.note::bf86a5d1][bf86a5d1]]
+/// Parse a vector from string, such as "1,0,0" or "1 0 0".
+pub(crate) fn parse_vector(s: &str) -> Result<Vector3f> {
+    let parts: Vec<f64> = s
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|x| !x.is_empty())
+        .map(|x| x.parse().map_err(|_| format_err!("invalid number: {:?}", x)))
+        .collect::<Result<_>>()?;
+    if parts.len() != 3 {
+        bail!("expect a vector with 3 components, but found: {:?}", s);
+    }
+
+    Ok(Vector3f::new(parts[0], parts[1], parts[2]))
+}
+
+/// Parse an axis from string: "x", "y", "z" or a vector such as "1,1,0".
+pub(crate) fn parse_axis(s: &str) -> Result<Vector3f> {
+    let axis = match s.to_lowercase().as_str() {
+        "x" => Vector3f::x(),
+        "y" => Vector3f::y(),
+        "z" => Vector3f::z(),
+        _ => parse_vector(s)?,
+    };
+    if axis.norm() < 1e-6 {
+        bail!("invalid axis: {:?}", s);
+    }
+
+    Ok(axis)
+}
+
+/// Parse the normal vector of a plane from string: "xy", "yz", "xz" or a
+/// normal vector such as "1,1,0".
+pub(crate) fn parse_plane_normal(s: &str) -> Result<Vector3f> {
+    let normal = match s.to_lowercase().as_str() {
+        "xy" | "yx" => Vector3f::z(),
+        "yz" | "zy" => Vector3f::x(),
+        "xz" | "zx" => Vector3f::y(),
+        _ => parse_vector(s)?,
+    };
+    if normal.norm() < 1e-6 {
+        bail!("invalid plane: {:?}", s);
+    }
+
+    Ok(normal)
+}
+
+/// Return the geometric center of `atoms` in `mol`.
+pub(crate) fn center_of(mol: &Molecule, atoms: &[usize]) -> Result<Vector3f> {
+    if atoms.is_empty() {
+        bail!("no atoms for computing center");
+    }
+    let mut c = Vector3f::zeros();
+    for &n in atoms {
+        c += atom_position(mol, n)?;
+    }
+
+    Ok(c / atoms.len() as f64)
+}
+// bf86a5d1 ends here
+
+// [[file:../gosh.note::31e0f1a2][31e0f1a2]]
+/// Reflect `atoms` through the plane with `normal` passing through `origin`.
+pub(crate) fn mirror(mol: &mut Molecule, atoms: &[usize], origin: Vector3f, normal: Vector3f) -> Result<()> {
+    let n = normal.normalize();
+    for &i in atoms {
+        let p = atom_position(mol, i)?;
+        let p = p - 2.0 * (p - origin).dot(&n) * n;
+        mol.get_atom_mut(i).unwrap().set_position(p.into());
+    }
+
+    Ok(())
+}
+
+/// Move the center of `atoms` to the center of the cell for periodic system,
+/// or to the origin otherwise.
+pub(crate) fn center(mol: &mut Molecule, atoms: &[usize], at_origin: bool) -> Result<()> {
+    let target: Vector3f = match &mol.lattice {
+        Some(lat) if !at_origin => lat.to_cart([0.5; 3]).into(),
+        _ => Vector3f::zeros(),
+    };
+    let disp = target - center_of(mol, atoms)?;
+    crate::geometry::translate_atoms(mol, atoms, disp)
+}
+// 31e0f1a2 ends here
+
+// [[file:../gosh.note::8b6d2e04][8b6d2e04]]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gchemol::{Atom, Lattice};
+
+    #[test]
+    fn test_parse_vector() -> Result<()> {
+        assert_eq!(parse_vector("1,0,2")?, Vector3f::new(1.0, 0.0, 2.0));
+        assert_eq!(parse_vector("1 0  2")?, Vector3f::new(1.0, 0.0, 2.0));
+        assert!(parse_vector("1,0").is_err());
+        assert_eq!(parse_axis("Y")?, Vector3f::y());
+        assert!(parse_axis("0,0,0").is_err());
+        assert_eq!(parse_plane_normal("xz")?, Vector3f::y());
+        Ok(())
+    }
+
+    #[test]
+    fn test_mirror() -> Result<()> {
+        let mut mol = Molecule::from_atoms([Atom::new("C", [1.0, 2.0, 3.0]), Atom::new("H", [0.0, 0.0, 1.0])]);
+        mirror(&mut mol, &[1], Vector3f::new(0.0, 0.0, 1.0), Vector3f::z())?;
+        assert_eq!(mol.get_atom(1).unwrap().position(), [1.0, 2.0, -1.0]);
+        // unselected atoms are kept
+        assert_eq!(mol.get_atom(2).unwrap().position(), [0.0, 0.0, 1.0]);
+
+        let mut mol = Molecule::from_atoms([Atom::new("C", [1.0, 2.0, 3.0])]);
+        mirror(&mut mol, &[1], Vector3f::zeros(), Vector3f::new(1.0, 1.0, 0.0))?;
+        let p = mol.get_atom(1).unwrap().position();
+        for (x, y) in p.iter().zip([-2.0, -1.0, 3.0]) {
+            assert_relative_eq!(*x, y, epsilon = 1e-8);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_center() -> Result<()> {
+        let mut mol = Molecule::from_atoms([Atom::new("C", [1.0, 1.0, 1.0]), Atom::new("O", [3.0, 1.0, 1.0])]);
+        center(&mut mol, &[1, 2], false)?;
+        assert_eq!(center_of(&mol, &[1, 2])?, Vector3f::zeros());
+        assert_relative_eq!(crate::geometry::get_distance(&mol, 1, 2)?, 2.0, epsilon = 1e-8);
+
+        mol.set_lattice(Lattice::new([[10.0, 0.0, 0.0], [0.0, 8.0, 0.0], [0.0, 0.0, 6.0]]));
+        center(&mut mol, &[1, 2], false)?;
+        assert_eq!(center_of(&mol, &[1, 2])?, Vector3f::new(5.0, 4.0, 3.0));
+        center(&mut mol, &[1, 2], true)?;
+        assert_eq!(center_of(&mol, &[1, 2])?, Vector3f::zeros());
+        assert!(center(&mut mol, &[], false).is_err());
+        Ok(())
+    }
+}
+// 8b6d2e04 ends here
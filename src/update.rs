@@ -0,0 +1,182 @@
+// [[file:../gosh.note::35bdd830][35bdd830]]
+use super::*;
+use crate::geometry::mic_vector;
+
+use gchemol::Molecule;
+use vecfx::*;
+// 35bdd830 ends here
+
+// [[file:../gosh.note::1b5cea8e][1b5cea8e]]
+/// Parse explicit atom mapping such as "1:5,2:6", which means target atom 1
+/// is mapped to source atom 5, and target atom 2 to source atom 6.
+pub(crate) fn parse_mapping(s: &str) -> Result<Vec<(usize, usize)>> {
+    s.split(',')
+        .filter(|x| !x.trim().is_empty())
+        .map(|x| {
+            let (i, j) = x.split_once(':').ok_or_else(|| format_err!("invalid atom mapping: {:?}", x))?;
+            let i = i.trim().parse().map_err(|_| format_err!("invalid atom mapping: {:?}", x))?;
+            let j = j.trim().parse().map_err(|_| format_err!("invalid atom mapping: {:?}", x))?;
+            Ok((i, j))
+        })
+        .collect()
+}
+
+/// Map atoms in `target` to atoms in `source` in the same order. The two
+/// molecules should have the same number of atoms.
+pub(crate) fn ordered_mapping(target: &Molecule, source: &Molecule) -> Result<Vec<(usize, usize)>> {
+    if target.natoms() != source.natoms() {
+        bail!(
+            "different number of atoms: {} vs {}. Please use explicit or automatic atom mapping.",
+            target.natoms(),
+            source.natoms()
+        );
+    }
+    Ok(target.numbers().zip(source.numbers()).collect())
+}
+
+/// Map atoms in `target` to atoms in `source` automatically by element and
+/// nearest position. Atom pairs are assigned greedily from the shortest
+/// distance.
+pub(crate) fn auto_mapping(target: &Molecule, source: &Molecule) -> Result<Vec<(usize, usize)>> {
+    let mut pairs = vec![];
+    for (i, ai) in target.atoms() {
+        let pi: Vector3f = ai.position().into();
+        for (j, aj) in source.atoms() {
+            if ai.symbol() == aj.symbol() {
+                let d = mic_vector(target, pi, aj.position().into()).norm();
+                pairs.push((d, i, j));
+            }
+        }
+    }
+    pairs.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let mut mapped_t = std::collections::HashSet::new();
+    let mut mapped_s = std::collections::HashSet::new();
+    let mut mapping = vec![];
+    for (_, i, j) in pairs {
+        if !mapped_t.contains(&i) && !mapped_s.contains(&j) {
+            mapped_t.insert(i);
+            mapped_s.insert(j);
+            mapping.push((i, j));
+        }
+    }
+    if mapping.len() < target.natoms() {
+        eprintln!("{} atoms could not be mapped and will be left unchanged.", target.natoms() - mapping.len());
+    }
+    mapping.sort();
+
+    Ok(mapping)
+}
+// 1b5cea8e ends here
+
+// [[file:../gosh.note::b512187a][b512187a]]
+/// The targets available for update.
+pub(crate) const UPDATE_TARGETS: &[&str] = &["coords", "freezing", "symbols", "velocities", "lattice", "title", "all"];
+
+/// Update `targets` of `mol` from `source` using atom `mapping`.
+pub(crate) fn update_from(mol: &mut Molecule, source: &Molecule, targets: &[&str], mapping: &[(usize, usize)]) -> Result<()> {
+    for target in targets {
+        if !UPDATE_TARGETS.contains(target) {
+            bail!("invalid update target: {:?}. Possible values: {}", target, UPDATE_TARGETS.join(", "));
+        }
+    }
+    let has = |t: &str| targets.contains(&t) || targets.contains(&"all");
+    // validate all atom indices before any change
+    for &(i, j) in mapping {
+        if mol.get_atom(i).is_none() {
+            bail!("no such atom: {}", i);
+        }
+        if source.get_atom(j).is_none() {
+            bail!("no such atom in source: {}", j);
+        }
+    }
+
+    if has("lattice") {
+        mol.lattice = source.lattice.clone();
+    }
+    if has("title") {
+        mol.set_title(&source.title());
+    }
+    if targets.contains(&"all") {
+        mol.properties = source.properties.clone();
+    }
+    for &(i, j) in mapping {
+        let aj = source.get_atom(j).unwrap();
+        let ai = mol.get_atom_mut(i).unwrap();
+        if targets.contains(&"all") {
+            *ai = aj.clone();
+            continue;
+        }
+        if has("coords") {
+            ai.set_position(aj.position());
+        }
+        if has("freezing") {
+            ai.set_freezing(aj.freezing());
+        }
+        if has("symbols") {
+            ai.set_symbol(aj.symbol());
+        }
+        if has("velocities") {
+            ai.set_velocity(aj.velocity());
+        }
+    }
+
+    Ok(())
+}
+// b512187a ends here
+
+// [[file:../gosh.note::5e0a7d3c][5e0a7d3c]]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gchemol::Atom;
+
+    #[test]
+    fn test_parse_mapping() -> Result<()> {
+        assert_eq!(parse_mapping("1:5,2:6")?, [(1, 5), (2, 6)]);
+        assert_eq!(parse_mapping(" 1 : 5 , 2:6,")?, [(1, 5), (2, 6)]);
+        assert!(parse_mapping("")?.is_empty());
+        assert!(parse_mapping("1-5").is_err());
+        assert!(parse_mapping("1:a").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_atom_mapping() -> Result<()> {
+        let target = Molecule::from_atoms([Atom::new("O", [0.0; 3]), Atom::new("H", [1.0, 0.0, 0.0]), Atom::new("H", [0.0, 1.0, 0.0])]);
+        // the same water with atoms shuffled and slightly displaced
+        let source = Molecule::from_atoms([Atom::new("H", [0.0, 1.1, 0.0]), Atom::new("O", [0.1, 0.0, 0.0]), Atom::new("H", [1.1, 0.0, 0.0])]);
+
+        assert_eq!(ordered_mapping(&target, &source)?, [(1, 1), (2, 2), (3, 3)]);
+        assert_eq!(auto_mapping(&target, &source)?, [(1, 2), (2, 3), (3, 1)]);
+        let oh = Molecule::from_atoms([Atom::new("O", [0.0; 3]), Atom::new("H", [1.0, 0.0, 0.0])]);
+        assert!(ordered_mapping(&target, &oh).is_err());
+        // the second H is left unmapped
+        assert_eq!(auto_mapping(&target, &oh)?, [(1, 1), (2, 2)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_update_from() -> Result<()> {
+        let mut mol = Molecule::from_atoms([Atom::new("O", [0.0; 3]), Atom::new("H", [1.0, 0.0, 0.0]), Atom::new("H", [0.0, 1.0, 0.0])]);
+        let mut source = mol.clone();
+        source.get_atom_mut(2).unwrap().set_position([2.0, 0.0, 0.0]);
+        source.properties.store("energy", &-1.0)?;
+
+        // invalid indices should leave the molecule untouched
+        assert!(update_from(&mut mol, &source, &["coords"], &[(2, 2), (3, 9)]).is_err());
+        assert_eq!(mol.get_atom(2).unwrap().position(), [1.0, 0.0, 0.0]);
+        assert!(update_from(&mut mol, &source, &["bonds"], &[]).is_err());
+
+        update_from(&mut mol, &source, &["coords"], &[(2, 2)])?;
+        assert_eq!(mol.get_atom(2).unwrap().position(), [2.0, 0.0, 0.0]);
+        assert!(mol.properties.load::<f64>("energy").is_err());
+
+        update_from(&mut mol, &source, &["all"], &[])?;
+        assert_eq!(mol.properties.load::<f64>("energy")?, -1.0);
+
+        Ok(())
+    }
+}
+// 5e0a7d3c ends here
@@ -0,0 +1,538 @@
+// [[file:../gosh.note::2f7c91d4][2f7c91d4]]
+use super::*;
+use crate::geometry::mic_vector;
+
+use gchemol::{Atom, Molecule};
+use std::collections::{HashMap, HashSet, VecDeque};
+use vecfx::*;
+// 2f7c91d4 ends here
+
+// [[file:../gosh.note::a4e6b0c8][a4e6b0c8]]
+/// Typical valences of main group elements (the most common one first),
+/// and the maximum number of bonded neighbors expected. Return None for
+/// elements without simple valence rules, such as transition metals.
+pub(crate) fn valence_rule(symbol: &str) -> Option<(&'static [usize], usize)> {
+    let rule: (&'static [usize], usize) = match symbol {
+        "H" | "F" | "Cl" | "Br" | "I" => (&[1], 1),
+        "Li" | "Na" | "K" => (&[1], 1),
+        "Be" | "Mg" => (&[2], 2),
+        "B" | "Al" => (&[3], 4),
+        "C" | "Si" | "Ge" => (&[4], 4),
+        "N" => (&[3], 4),
+        "P" | "As" => (&[3, 5], 6),
+        "O" => (&[2], 2),
+        "S" | "Se" => (&[2, 4, 6], 6),
+        _ => return None,
+    };
+    Some(rule)
+}
+
+/// Covalent radius in Angstrom of element `symbol`, taken from gchemol
+/// element data.
+fn covalent_radius(symbol: &str) -> Option<f64> {
+    Atom::new(symbol, [0.0; 3]).get_cov_radius()
+}
+
+/// Estimate the number of pi bonds formed by atom `n` from the bond
+/// angles, or from the bond length when it has only one neighbor.
+fn count_pi_bonds(mol: &Molecule, n: usize, neighbors: &[(usize, Vector3f)]) -> usize {
+    let symbol = mol.get_atom(n).unwrap().symbol();
+    match neighbors {
+        [] => 0,
+        [(j, v)] => {
+            let other = mol.get_atom(*j).unwrap().symbol();
+            match (covalent_radius(symbol), covalent_radius(other)) {
+                (Some(ri), Some(rj)) if other != "H" => {
+                    let ratio = v.norm() / (ri + rj);
+                    if ratio < 0.83 {
+                        2
+                    } else if ratio < 0.93 {
+                        1
+                    } else {
+                        0
+                    }
+                }
+                _ => 0,
+            }
+        }
+        [(_, v1), (_, v2)] => {
+            let angle = v1.angle(v2).to_degrees();
+            if angle > 155.0 {
+                2
+            } else if angle > 115.0 {
+                1
+            } else {
+                0
+            }
+        }
+        [(_, v1), (_, v2), (_, v3)] => {
+            let sum = (v1.angle(v2) + v1.angle(v3) + v2.angle(v3)).to_degrees();
+            if sum > 350.0 {
+                1
+            } else {
+                0
+            }
+        }
+        _ => 0,
+    }
+}
+
+/// Return a unit vector perpendicular to `u`.
+fn perpendicular(u: &Vector3f) -> Vector3f {
+    let axis = if u.x.abs() < 0.9 { Vector3f::x() } else { Vector3f::y() };
+    u.cross(&axis).normalize()
+}
+
+/// Return unit vectors for free bonding directions of an atom with
+/// `npi` pi bonds and existing bonds along `bonds`, using ideal sp3, sp2
+/// or sp geometry. `reference` is a direction used for orienting new
+/// bonds when there is only one existing bond.
+fn free_directions(bonds: &[Vector3f], npi: usize, reference: Option<Vector3f>) -> Vec<Vector3f> {
+    let us = bonds.iter().map(|v| v.normalize()).collect_vec();
+    let tetrahedral = 109.47_f64.to_radians();
+    match (npi.min(2), us.as_slice()) {
+        (0, []) => vec![
+            Vector3f::new(1.0, 1.0, 1.0).normalize(),
+            Vector3f::new(-1.0, -1.0, 1.0).normalize(),
+            Vector3f::new(-1.0, 1.0, -1.0).normalize(),
+            Vector3f::new(1.0, -1.0, -1.0).normalize(),
+        ],
+        (1, []) => {
+            let s = 3f64.sqrt() / 2.0;
+            vec![Vector3f::x(), Vector3f::new(-0.5, s, 0.0), Vector3f::new(-0.5, -s, 0.0)]
+        }
+        (_, []) => vec![Vector3f::x(), -Vector3f::x()],
+        (npi, [u]) => {
+            // orient new bonds with respect to the substituents of the neighbor
+            let p = reference
+                .map(|r| r - u * u.dot(&r))
+                .filter(|p| p.norm() > 1e-3)
+                .map(|p| p.normalize())
+                .unwrap_or_else(|| perpendicular(u));
+            let q = u.cross(&p);
+            match npi {
+                // staggered conformation
+                0 => [60.0_f64, 180.0, 300.0]
+                    .iter()
+                    .map(|phi| {
+                        let phi = phi.to_radians();
+                        u * tetrahedral.cos() + (p * phi.cos() + q * phi.sin()) * tetrahedral.sin()
+                    })
+                    .collect(),
+                // in the same plane as the substituents of the neighbor
+                1 => {
+                    let a = 120_f64.to_radians();
+                    vec![u * a.cos() + p * a.sin(), u * a.cos() - p * a.sin()]
+                }
+                _ => vec![-u],
+            }
+        }
+        (npi, [u1, u2]) => {
+            let b = -(u1 + u2);
+            let normal = u1.cross(u2);
+            let b = if b.norm() > 1e-3 { b.normalize() } else { perpendicular(u1) };
+            let normal = if normal.norm() > 1e-3 { normal.normalize() } else { b.cross(u1).normalize() };
+            match npi {
+                0 => {
+                    let a = tetrahedral / 2.0;
+                    vec![b * a.cos() + normal * a.sin(), b * a.cos() - normal * a.sin()]
+                }
+                1 => vec![b],
+                _ => vec![],
+            }
+        }
+        (0, [u1, u2, u3]) => {
+            let b = -(u1 + u2 + u3);
+            if b.norm() > 1e-3 {
+                vec![b.normalize()]
+            } else {
+                vec![(u2 - u1).cross(&(u3 - u1)).normalize()]
+            }
+        }
+        _ => vec![],
+    }
+}
+
+/// Return bonded neighbors of atom `n` with bond vectors, following the
+/// minimum image convention for periodic systems.
+fn bonded_neighbors(mol: &Molecule, n: usize) -> Vec<(usize, Vector3f)> {
+    let pn: Vector3f = mol.get_atom(n).unwrap().position().into();
+    mol.connected(n)
+        .sorted()
+        .map(|j| {
+            let pj: Vector3f = mol.get_atom(j).unwrap().position().into();
+            (j, mic_vector(mol, pn, pj))
+        })
+        .collect()
+}
+
+/// Add hydrogens to `atoms` in `mol` to saturate their valences. Bonds
+/// are taken from existing connectivity, and the hybridization (sp3, sp2
+/// or sp) is estimated from bond angles and lengths. New hydrogens are
+/// appended after existing atoms, bonded to the atoms they saturate.
+/// Return the number of hydrogens added.
+pub(crate) fn add_hydrogens(mol: &mut Molecule, atoms: &[usize]) -> Result<usize> {
+    if mol.nbonds() == 0 && mol.natoms() > 1 {
+        bail!("no bonds found. Please run `rebond` first.");
+    }
+
+    let mut hydrogens = vec![];
+    for &n in atoms {
+        let a = mol.get_atom(n).ok_or_else(|| format_err!("no such atom: {}", n))?;
+        let symbol = a.symbol();
+        if symbol == "H" {
+            continue;
+        }
+        let valence = match valence_rule(symbol) {
+            Some((valences, _)) => valences[0],
+            None => continue,
+        };
+        let neighbors = bonded_neighbors(mol, n);
+        let npi = count_pi_bonds(mol, n, &neighbors).min(valence - 1);
+        let nh = (valence - npi).saturating_sub(neighbors.len());
+        if nh == 0 {
+            continue;
+        }
+
+        let reference = match neighbors.as_slice() {
+            [(j, _)] => bonded_neighbors(mol, *j).into_iter().find(|(k, _)| *k != n).map(|(_, w)| w),
+            _ => None,
+        };
+        let bonds = neighbors.iter().map(|(_, v)| *v).collect_vec();
+        let pn: Vector3f = a.position().into();
+        let d = covalent_radius(symbol).unwrap() + covalent_radius("H").unwrap();
+        for u in free_directions(&bonds, npi, reference).into_iter().take(nh) {
+            let p: [f64; 3] = (pn + u * d).into();
+            hydrogens.push((n, p));
+        }
+    }
+
+    let nadded = hydrogens.len();
+    let mut serial = mol.numbers().max().unwrap_or(0);
+    for (n, p) in hydrogens {
+        serial += 1;
+        mol.add_atom(serial, Atom::new("H", p));
+        mol.add_bond(n, serial, gchemol::Bond::single());
+    }
+
+    Ok(nadded)
+}
+
+/// Return atoms with unusual number of bonds in `mol`, as tuples of atom
+/// serial number, symbol, number of bonds and a description of the
+/// problem. An atom is under-coordinated if its bonds and the pi bonds
+/// estimated from geometry can not reach its lowest valence. Elements
+/// without valence rules are ignored.
+pub(crate) fn check_valence(mol: &Molecule) -> Vec<(usize, String, usize, &'static str)> {
+    let mut problems = vec![];
+    for (n, a) in mol.atoms() {
+        let (valences, max_bonds) = match valence_rule(a.symbol()) {
+            Some(rule) => rule,
+            None => continue,
+        };
+        let neighbors = bonded_neighbors(mol, n);
+        let nbonds = neighbors.len();
+        let problem = if nbonds > max_bonds {
+            "over-coordinated"
+        } else if nbonds == 0 && mol.natoms() > 1 {
+            "isolated"
+        } else if nbonds > 0 && nbonds + count_pi_bonds(mol, n, &neighbors) < valences[0] {
+            "under-coordinated"
+        } else {
+            continue;
+        };
+        problems.push((n, a.symbol().to_owned(), nbonds, problem));
+    }
+    problems
+}
+// a4e6b0c8 ends here
+
+// [[file:../gosh.note::e81d5a27][e81d5a27]]
+/// Bond order perceived from geometry and valence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum BondOrder {
+    Single,
+    Double,
+    Triple,
+    Aromatic,
+}
+
+/// Perceived bond orders keyed by atom pairs (i, j) with i < j.
+pub(crate) type BondOrders = HashMap<(usize, usize), BondOrder>;
+
+pub(crate) fn bond_key(i: usize, j: usize) -> (usize, usize) {
+    if i < j {
+        (i, j)
+    } else {
+        (j, i)
+    }
+}
+
+/// Return the ratio of bond length between atom `i` and `j` to the sum of
+/// their covalent radii.
+fn bond_length_ratio(mol: &Molecule, i: usize, j: usize) -> f64 {
+    let ri = covalent_radius(mol.get_atom(i).unwrap().symbol());
+    let rj = covalent_radius(mol.get_atom(j).unwrap().symbol());
+    match (ri, rj, crate::geometry::get_distance(mol, i, j)) {
+        (Some(ri), Some(rj), Ok(d)) => d / (ri + rj),
+        _ => 1.0,
+    }
+}
+
+/// Return the number of extra bonds atom `n` can form beyond single bonds.
+/// Hypervalent atoms such as S in SO2 take a larger valence when bonded to
+/// terminal chalcogens; otherwise the number is limited by its geometry.
+fn free_valence(mol: &Molecule, n: usize) -> usize {
+    let symbol = mol.get_atom(n).unwrap().symbol();
+    let valences = match valence_rule(symbol) {
+        Some((valences, _)) => valences,
+        None => return 0,
+    };
+    let neighbors = bonded_neighbors(mol, n);
+    let degree = neighbors.len();
+    let terminal = neighbors
+        .iter()
+        .filter(|(j, _)| matches!(mol.get_atom(*j).unwrap().symbol(), "O" | "S") && mol.connected(*j).count() == 1)
+        .count();
+    let valence = valences
+        .iter()
+        .copied()
+        .find(|&v| v >= degree + terminal)
+        .or_else(|| valences.iter().copied().filter(|&v| v >= degree).max());
+    match valence {
+        Some(v) if v == valences[0] => (v - degree).min(count_pi_bonds(mol, n, &neighbors)),
+        Some(v) => v - degree,
+        None => 0,
+    }
+}
+
+/// Choose double bonds from `edges[k..]` within `free` valences, keeping
+/// the largest choice found in `best`. The search stops when `budget` is
+/// used up.
+fn search_double_bonds(
+    edges: &[(usize, usize)],
+    k: usize,
+    free: &mut HashMap<usize, usize>,
+    chosen: &mut Vec<usize>,
+    best: &mut Vec<usize>,
+    budget: &mut usize,
+) {
+    if chosen.len() > best.len() {
+        *best = chosen.clone();
+    }
+    if k == edges.len() || *budget == 0 {
+        return;
+    }
+    *budget -= 1;
+    let bound = free.values().sum::<usize>() / 2;
+    if chosen.len() + bound.min(edges.len() - k) <= best.len() {
+        return;
+    }
+
+    let (i, j) = edges[k];
+    if free[&i] > 0 && free[&j] > 0 {
+        *free.get_mut(&i).unwrap() -= 1;
+        *free.get_mut(&j).unwrap() -= 1;
+        chosen.push(k);
+        search_double_bonds(edges, k + 1, free, chosen, best, budget);
+        chosen.pop();
+        *free.get_mut(&i).unwrap() += 1;
+        *free.get_mut(&j).unwrap() += 1;
+    }
+    search_double_bonds(edges, k + 1, free, chosen, best, budget);
+}
+
+/// Find the smallest ring (up to 8 atoms) through each bond in `mol`.
+/// Return atoms of each ring in ring order.
+pub(crate) fn find_rings(mol: &Molecule) -> Vec<Vec<usize>> {
+    let mut rings = vec![];
+    let mut seen = HashSet::new();
+    for (i, j, _) in mol.bonds() {
+        // shortest path from j to i without using bond i-j
+        let mut prev: HashMap<usize, (usize, usize)> = HashMap::new();
+        prev.insert(j, (j, 1));
+        let mut queue = VecDeque::from([j]);
+        while let Some(k) = queue.pop_front() {
+            let depth = prev[&k].1;
+            if k == i || depth >= 8 {
+                continue;
+            }
+            for l in mol.connected(k) {
+                if (k == j && l == i) || prev.contains_key(&l) {
+                    continue;
+                }
+                prev.insert(l, (k, depth + 1));
+                queue.push_back(l);
+            }
+        }
+        if !prev.contains_key(&i) {
+            continue;
+        }
+        let mut ring = vec![i];
+        let mut k = i;
+        while k != j {
+            k = prev[&k].0;
+            ring.push(k);
+        }
+        if seen.insert(ring.iter().copied().sorted().collect_vec()) {
+            rings.push(ring);
+        }
+    }
+    rings
+}
+
+/// Test if `ring` is aromatic by Hückel's 4n+2 rule, counting pi
+/// electrons from the double bonds in `orders`.
+fn is_aromatic(mol: &Molecule, ring: &[usize], ring_atoms: &HashSet<usize>, orders: &BondOrders) -> bool {
+    let n = ring.len();
+    if !(5..=7).contains(&n) {
+        return false;
+    }
+    let mut npi = 0;
+    for (k, &i) in ring.iter().enumerate() {
+        let (prev, next) = (ring[(k + n - 1) % n], ring[(k + 1) % n]);
+        let doubles = mol
+            .connected(i)
+            .filter(|&j| orders.get(&bond_key(i, j)) == Some(&BondOrder::Double))
+            .collect_vec();
+        let symbol = mol.get_atom(i).unwrap().symbol();
+        if doubles.iter().any(|&j| j == prev || j == next || ring_atoms.contains(&j)) {
+            npi += 1;
+        } else if !doubles.is_empty() {
+            // exocyclic double bond, such as C=O
+            continue;
+        } else if matches!(symbol, "N" | "O" | "S" | "Se" | "P") && mol.connected(i).count() <= 3 {
+            // lone pair
+            npi += 2;
+        } else {
+            return false;
+        }
+    }
+    npi >= 2 && (npi - 2) % 4 == 0
+}
+
+/// Perceive bond orders in `mol` from existing connectivity, bond lengths
+/// and valences. Triple bonds are assigned first, then double bonds are
+/// searched for saturating as many valences as possible, preferring short
+/// bonds. Rings satisfying Hückel's rule are marked as aromatic.
+pub(crate) fn perceive_bond_orders(mol: &Molecule) -> BondOrders {
+    let mut orders: BondOrders = mol.bonds().map(|(i, j, _)| (bond_key(i, j), BondOrder::Single)).collect();
+    let mut free: HashMap<usize, usize> = mol.numbers().map(|n| (n, free_valence(mol, n))).collect();
+
+    let mut candidates = orders
+        .keys()
+        .copied()
+        .filter(|(i, j)| free[i] > 0 && free[j] > 0)
+        .map(|(i, j)| (bond_length_ratio(mol, i, j), i, j))
+        .collect_vec();
+    candidates.sort_by(|a, b| a.0.total_cmp(&b.0).then((a.1, a.2).cmp(&(b.1, b.2))));
+
+    for &(ratio, i, j) in candidates.iter() {
+        if ratio < 0.83 && free[&i] >= 2 && free[&j] >= 2 {
+            orders.insert((i, j), BondOrder::Triple);
+            *free.get_mut(&i).unwrap() -= 2;
+            *free.get_mut(&j).unwrap() -= 2;
+        }
+    }
+
+    let edges = candidates
+        .iter()
+        .filter(|(_, i, j)| free[i] > 0 && free[j] > 0 && orders[&(*i, *j)] == BondOrder::Single)
+        .map(|(_, i, j)| (*i, *j))
+        .collect_vec();
+    let mut best = vec![];
+    let mut budget = 100_000;
+    search_double_bonds(&edges, 0, &mut free, &mut vec![], &mut best, &mut budget);
+    if budget == 0 {
+        eprintln!("Bond order search stopped early. Some double bonds may be missing.");
+    }
+    for k in best {
+        orders.insert(edges[k], BondOrder::Double);
+    }
+
+    let rings = find_rings(mol);
+    let ring_atoms: HashSet<usize> = rings.iter().flatten().copied().collect();
+    let kekule = orders.clone();
+    for ring in rings.iter().filter(|ring| is_aromatic(mol, ring, &ring_atoms, &kekule)) {
+        let n = ring.len();
+        for k in 0..n {
+            orders.insert(bond_key(ring[k], ring[(k + 1) % n]), BondOrder::Aromatic);
+        }
+    }
+
+    orders
+}
+
+/// Update bonds in `mol` with perceived bond orders. Return the number of
+/// multiple and aromatic bonds.
+pub(crate) fn set_bond_orders(mol: &mut Molecule) -> usize {
+    let orders = perceive_bond_orders(mol);
+    let mut n = 0;
+    for (&(i, j), order) in orders.iter() {
+        let bond = match order {
+            BondOrder::Single => gchemol::Bond::single(),
+            BondOrder::Double => gchemol::Bond::double(),
+            BondOrder::Triple => gchemol::Bond::triple(),
+            BondOrder::Aromatic => gchemol::Bond::aromatic(),
+        };
+        if *order != BondOrder::Single {
+            n += 1;
+        }
+        mol.add_bond(i, j, bond);
+    }
+    n
+}
+// e81d5a27 ends here
+
+// [[file:../gosh.note::c0e2d9a1][c0e2d9a1]]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_hydrogens() -> Result<()> {
+        let mut mol = Molecule::from_atoms([Atom::new("C", [0.0; 3])]);
+        assert_eq!(add_hydrogens(&mut mol, &[1])?, 4);
+        assert_eq!(mol.natoms(), 5);
+        assert_eq!(mol.nbonds(), 4);
+        let d = covalent_radius("C").unwrap() + covalent_radius("H").unwrap();
+        for n in 2..=5 {
+            assert!(mol.get_bond(1, n).is_some());
+            assert_relative_eq!(crate::geometry::get_distance(&mol, 1, n)?, d, epsilon = 1e-6);
+        }
+        assert!(check_valence(&mol).is_empty());
+        // saturated atoms are left untouched
+        assert_eq!(add_hydrogens(&mut mol, &[1, 2])?, 0);
+
+        let mut water = Molecule::from_atoms([Atom::new("O", [0.0; 3])]);
+        assert_eq!(add_hydrogens(&mut water, &[1])?, 2);
+        assert_eq!(water.natoms(), 3);
+        assert_eq!(water.nbonds(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_valence() -> Result<()> {
+        let mut mol = Molecule::from_atoms([Atom::new("C", [0.0; 3])]);
+        add_hydrogens(&mut mol, &[1])?;
+
+        // methyl radical
+        let mut methyl = mol.clone();
+        crate::edit::delete_atoms(&mut methyl, &[5])?;
+        assert_eq!(check_valence(&methyl), [(1, "C".to_owned(), 3, "under-coordinated")]);
+
+        let mut over = mol.clone();
+        over.add_atom(6, Atom::new("H", [0.0, 0.0, 1.1]));
+        over.add_bond(1, 6, gchemol::Bond::single());
+        assert_eq!(check_valence(&over), [(1, "C".to_owned(), 5, "over-coordinated")]);
+
+        let mut isolated = mol.clone();
+        isolated.add_atom(6, Atom::new("Ar", [5.0, 0.0, 0.0]));
+        isolated.add_atom(7, Atom::new("O", [0.0, 5.0, 0.0]));
+        assert_eq!(check_valence(&isolated), [(7, "O".to_owned(), 0, "isolated")]);
+
+        Ok(())
+    }
+}
+// c0e2d9a1 ends here